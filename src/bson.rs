@@ -0,0 +1,307 @@
+//! Decoding BSON, as written by `mongodump`: a series of documents, one after another.
+//!
+//! Arrays become lists, and the types JSON doesn't have are written the way the MongoDB shell
+//! writes them, like `ObjectId("5f0c...")`, `ISODate("2013-03-21T20:04:00Z")`,
+//! `NumberLong(5)`, `NumberDecimal("1.5")`, `BinData(0, "AQI=")` and `/^a/i`.
+
+use std::io::BufRead;
+
+use crate::decode::{encode_bytes, float, timestamp, Builder, Bytes};
+use crate::{Error, JsonError, Sink, Terminal};
+
+/// A document or array which is being decoded.
+struct Frame {
+    end: u64, // the offset just past it
+    list: bool,
+}
+
+struct Decoder<R, S> {
+    input: Bytes<R>,
+    builder: Builder<S>,
+    frames: Vec<Frame>,
+    start: u64, // the offset of the element being decoded
+    hex: bool,
+}
+
+/// Decode a series of BSON documents and give them to the sink, as numbered records. Binary
+/// values are written in hex if `hex` is set, or base64 otherwise. If `repair` is set, the
+/// documents and arrays which are open when the input is cut off are closed.
+pub(crate) fn decode<S: Sink>(input: impl BufRead, sink: S, hex: bool, repair: bool)
+    -> Result<(), Error>
+{
+    let mut decoder = Decoder {
+        input: Bytes::new(input),
+        builder: Builder::new(sink),
+        frames: vec![],
+        start: 0,
+        hex,
+    };
+    let result = decoder.run();
+    decoder.builder.finish(result, decoder.start, repair)
+}
+
+fn invalid(msg: &str) -> JsonError {
+    JsonError::Syntax(msg.to_owned())
+}
+
+impl<R: BufRead, S: Sink> Decoder<R, S> {
+    fn run(&mut self) -> Result<(), JsonError> {
+        let mut record = 0;
+        loop {
+            if self.builder.done() {
+                return Ok(());
+            }
+            self.start = self.input.offset;
+            let Some(frame) = self.frames.last() else {
+                if self.input.at_end()? {
+                    return Ok(());
+                }
+                record += 1;
+                self.builder.sink.record(record);
+                self.open(false)?;
+                continue;
+            };
+            let list = frame.list;
+
+            let kind = self.input.u8()?;
+            if kind == 0 {
+                let frame = self.frames.pop().unwrap();
+                if self.input.offset != frame.end {
+                    return Err(invalid("BSON document length doesn't match its contents"));
+                }
+                self.builder.end()?;
+                continue;
+            }
+            let name = self.cstring()?;
+            if !list {
+                self.builder.key(name)?;
+            }
+            match kind {
+                0x03 => self.open(false)?,
+                0x04 => self.open(true)?,
+                _ => {
+                    let value = self.value(kind)?;
+                    self.builder.scalar(value)?;
+                }
+            }
+        }
+    }
+
+    /// Start a document, or an array, which is a document with keys "0", "1" and so on.
+    fn open(&mut self, list: bool) -> Result<(), JsonError> {
+        let start = self.input.offset;
+        let len = self.int32()?;
+        if len < 5 {
+            return Err(invalid("invalid BSON document length"));
+        }
+        let end = start + len as u64;
+        if self.frames.last().is_some_and(|parent| end > parent.end) {
+            return Err(invalid("BSON document is longer than the one it's in"));
+        }
+        if list {
+            self.builder.start_list()?;
+        } else {
+            self.builder.start_object()?;
+        }
+        self.frames.push(Frame { end, list });
+        Ok(())
+    }
+
+    fn int32(&mut self) -> Result<i32, JsonError> {
+        Ok(i32::from_le_bytes(self.input.array()?))
+    }
+
+    fn cstring(&mut self) -> Result<String, JsonError> {
+        String::from_utf8(self.input.cstring()?).map_err(|e| JsonError::Unicode(e.utf8_error()))
+    }
+
+    /// A string, which is written with its length and a NUL at the end.
+    fn string(&mut self) -> Result<String, JsonError> {
+        let len = self.int32()?;
+        if len < 1 {
+            return Err(invalid("invalid BSON string length"));
+        }
+        let mut bytes = self.input.vec(len as u64)?;
+        if bytes.pop() != Some(0) {
+            return Err(invalid("BSON string doesn't end with NUL"));
+        }
+        String::from_utf8(bytes).map_err(|e| JsonError::Unicode(e.utf8_error()))
+    }
+
+    fn object_id(&mut self) -> Result<String, JsonError> {
+        let id: [u8; 12] = self.input.array()?;
+        Ok(format!("ObjectId(\"{}\")", encode_bytes(&id, true)))
+    }
+
+    /// Decode an element which isn't a document or array.
+    fn value(&mut self, kind: u8) -> Result<Terminal, JsonError> {
+        let quoted = |s: String| Terminal::String(s).to_string();
+        Ok(match kind {
+            0x01 => float(f64::from_le_bytes(self.input.array()?)),
+            0x02 | 0x0E => Terminal::String(self.string()?), // string, symbol
+            0x05 => {
+                let len = self.int32()?;
+                if len < 0 {
+                    return Err(invalid("invalid BSON binary length"));
+                }
+                let subtype = self.input.u8()?;
+                let data = self.input.vec(len as u64)?;
+                if self.hex {
+                    Terminal::Typed(format!("HexData({subtype}, \"{}\")", encode_bytes(&data, true)))
+                } else {
+                    Terminal::Typed(format!("BinData({subtype}, \"{}\")", encode_bytes(&data, false)))
+                }
+            }
+            0x06 => Terminal::Typed("undefined".to_owned()),
+            0x07 => Terminal::Typed(self.object_id()?),
+            0x08 => match self.input.u8()? {
+                0 => Terminal::Bool(false),
+                1 => Terminal::Bool(true),
+                _ => return Err(invalid("invalid BSON boolean")),
+            },
+            0x09 => {
+                let ms = i64::from_le_bytes(self.input.array()?);
+                let nanos = ms.rem_euclid(1000) as u32 * 1_000_000;
+                Terminal::Typed(format!("ISODate(\"{}\")", timestamp(ms.div_euclid(1000), nanos)))
+            }
+            0x0A => Terminal::Null,
+            0x0B => {
+                let pattern = self.cstring()?;
+                let flags = self.cstring()?;
+                Terminal::Typed(format!("/{pattern}/{flags}"))
+            }
+            0x0C => {
+                let namespace = self.string()?;
+                let id = self.object_id()?;
+                Terminal::Typed(format!("DBPointer({}, {id})", quoted(namespace)))
+            }
+            0x0D => Terminal::Typed(format!("Code({})", quoted(self.string()?))),
+            0x0F => {
+                // Code with a scope, which is deprecated. The scope is left out.
+                let len = self.int32()?;
+                let start = self.input.offset;
+                let code = self.string()?;
+                let rest = (len as u64).checked_sub(4 + self.input.offset - start)
+                    .ok_or_else(|| invalid("invalid BSON code with scope length"))?;
+                self.input.vec(rest)?;
+                Terminal::Typed(format!("Code({})", quoted(code)))
+            }
+            0x10 => Terminal::Number(self.int32()?.to_string()),
+            0x11 => {
+                let n = u64::from_le_bytes(self.input.array()?);
+                Terminal::Typed(format!("Timestamp({}, {})", n >> 32, n & 0xFFFF_FFFF))
+            }
+            0x12 => {
+                let n = i64::from_le_bytes(self.input.array()?);
+                Terminal::Typed(format!("NumberLong({n})"))
+            }
+            0x13 => {
+                let bits = u128::from_le_bytes(self.input.array()?);
+                Terminal::Typed(format!("NumberDecimal(\"{}\")", decimal128(bits)))
+            }
+            0xFF => Terminal::Typed("MinKey".to_owned()),
+            0x7F => Terminal::Typed("MaxKey".to_owned()),
+            _ => return Err(JsonError::Syntax(format!("unknown BSON type 0x{kind:02x}"))),
+        })
+    }
+}
+
+/// Write an IEEE 754 decimal128 number, in its binary integer encoding, the way the General
+/// Decimal Arithmetic specification's to-scientific-string does.
+fn decimal128(bits: u128) -> String {
+    let sign = if bits >> 127 != 0 { "-" } else { "" };
+    let (exponent, coefficient) = match bits >> 122 & 0x1F {
+        0x1F => return "NaN".to_owned(),
+        0x1E => return format!("{sign}Infinity"),
+        c if c >> 3 == 0b11 => {
+            // The coefficient would be bigger than 34 digits, so it counts as zero.
+            ((bits >> 111 & 0x3FFF) as i64, 0)
+        }
+        _ => ((bits >> 113 & 0x3FFF) as i64, bits & ((1 << 113) - 1)),
+    };
+    let exponent = exponent - 6176;
+    let digits = if coefficient < 10u128.pow(34) { coefficient.to_string() } else { "0".to_owned() };
+    let adjusted = exponent + digits.len() as i64 - 1;
+    if exponent <= 0 && adjusted >= -6 {
+        let point = digits.len() as i64 + exponent;
+        if exponent == 0 {
+            format!("{sign}{digits}")
+        } else if point > 0 {
+            let (int, fraction) = digits.split_at(point as usize);
+            format!("{sign}{int}.{fraction}")
+        } else {
+            format!("{sign}0.{}{digits}", "0".repeat(-point as usize))
+        }
+    } else {
+        let (first, rest) = digits.split_at(1);
+        let point = if rest.is_empty() { "" } else { "." };
+        format!("{sign}{first}{point}{rest}E{}{adjusted}", if adjusted >= 0 { "+" } else { "" })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_with, Format, Options};
+
+    #[test]
+    fn test_bson() {
+        // {"_id": ObjectId, "n": [1, 2^40], "d": {"t": 2013-03-21T20:04:00.5Z, "x": 1.5, "b": bin}},
+        // {"r": /^a/i, "s": "é"}
+        let input = b"\x6b\x00\x00\x00\
+            \x07_id\x00\x5f\x0c\x1a\x2b\x3c\x4d\x5e\x6f\x70\x81\x92\xa3\
+            \x04n\x00\x17\x00\x00\x00\
+                \x100\x00\x01\x00\x00\x00\
+                \x121\x00\x00\x00\x00\x00\x00\x01\x00\x00\
+            \x00\
+            \x03d\x00\x38\x00\x00\x00\
+                \x09t\x00\x74\x09\x8d\x8e\x3d\x01\x00\x00\
+                \x01x\x00\x00\x00\x00\x00\x00\x00\xf8\x3f\
+                \x05b\x00\x02\x00\x00\x00\x00\x01\x02\
+                \x13z\x00\x0f\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x3e\x30\
+            \x00\
+            \x00\
+            \x17\x00\x00\x00\x0br\x00^a\x00i\x00\x02s\x00\x03\x00\x00\x00\xc3\xa9\x00\x00";
+        let mut out = vec![];
+        let options = Options { format: Format::Bson, ..Options::default() };
+        parse_with(&input[..], &mut out, options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\
+            [#1]._id = ObjectId(\"5f0c1a2b3c4d5e6f708192a3\")\n\
+            [#1].n[0] = 1\n\
+            [#1].n[1] = NumberLong(1099511627776)\n\
+            [#1].d.t = ISODate(\"2013-03-21T20:04:00.5Z\")\n\
+            [#1].d.x = 1.5\n\
+            [#1].d.b = BinData(0, \"AQI=\")\n\
+            [#1].d.z = NumberDecimal(\"1.5\")\n\
+            [#2].r = /^a/i\n\
+            [#2].s = \"é\"\n");
+
+        let mut input = input.to_vec();
+        input[0] = 0x6c;
+        let options = Options { format: Format::Bson, ..Options::default() };
+        let e = parse_with(&input[..], std::io::sink(), options).unwrap_err();
+        assert_eq!((e.offset, e.path.to_string(), e.error.to_string()),
+                   (106, "".to_owned(), "BSON document length doesn't match its contents".to_owned()));
+
+        let options = Options { format: Format::Bson, ..Options::default() };
+        let e = parse_with(&input[.. 40], std::io::sink(), options).unwrap_err();
+        assert_eq!((e.offset, e.path.to_string(), e.error.to_string()),
+                   (35, ".n[1]".to_owned(), "input truncated".to_owned()));
+    }
+
+    #[test]
+    fn test_decimal128() {
+        let decimal = |exponent: i64, coefficient: u128| {
+            decimal128(((exponent + 6176) as u128) << 113 | coefficient)
+        };
+        assert_eq!("0", decimal(0, 0));
+        assert_eq!("123", decimal(0, 123));
+        assert_eq!("1.23", decimal(-2, 123));
+        assert_eq!("0.00123", decimal(-5, 123));
+        assert_eq!("1.23E-8", decimal(-10, 123));
+        assert_eq!("1.23E+5", decimal(3, 123));
+        assert_eq!("1E+3", decimal(3, 1));
+        assert_eq!("-Infinity", decimal128(0xF8 << 120));
+        assert_eq!("NaN", decimal128(0x7C << 120));
+    }
+}
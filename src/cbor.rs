@@ -0,0 +1,417 @@
+//! Decoding CBOR (RFC 8949) into the same values JSON has.
+//!
+//! Integers and floats become numbers, byte strings become base64 (or hex) strings, and `undefined`
+//! becomes `null`. Map keys which aren't text strings are written as JSON, like `.1` or
+//! `."[1,2]"`. Tags are shown as an object with one key, like `.when."tag(1)" = 1363896240`,
+//! except for the self-describe tag, which is left out, and bignums, which are written as numbers.
+//! A series of items (RFC 8742) is read like a series of top-level JSON values.
+
+use std::io::BufRead;
+
+use crate::decode::{encode_bytes, float, Builder, Bytes};
+use crate::{decimal, Error, JsonError, Sink, Terminal};
+
+/// CBOR data starting with the self-describe tag, 55799, which can be detected.
+pub(crate) const MAGIC: &[u8] = b"\xd9\xd9\xf7";
+
+/// How deeply lists and maps can be nested within a map key.
+const MAX_KEY_DEPTH: usize = 64;
+
+const BREAK: u8 = 0xFF;
+
+/// A list or map which is being decoded.
+enum Frame {
+    /// A list, with how many more items it has, if it has a definite length.
+    List(Option<u64>),
+    /// A map, with how many more keys it has, if it has a definite length, and whether a key is
+    /// next rather than a value. Tags are also one of these, with their key already given.
+    Map { left: Option<u64>, key: bool },
+}
+
+struct Decoder<R, S> {
+    input: Bytes<R>,
+    builder: Builder<S>,
+    frames: Vec<Frame>,
+    start: u64, // the offset of the item being decoded
+    hex: bool,
+}
+
+/// Decode a series of CBOR items and give them to the sink. Byte strings are written in hex if
+/// `hex` is set, or base64 otherwise. If `repair` is set, the lists and maps which are open when
+/// the input is cut off are closed.
+pub(crate) fn decode<S: Sink>(input: impl BufRead, sink: S, hex: bool, repair: bool)
+    -> Result<(), Error>
+{
+    let mut decoder = Decoder {
+        input: Bytes::new(input),
+        builder: Builder::new(sink),
+        frames: vec![],
+        start: 0,
+        hex,
+    };
+    let result = decoder.run();
+    decoder.builder.finish(result, decoder.start, repair)
+}
+
+impl<R: BufRead, S: Sink> Decoder<R, S> {
+    fn run(&mut self) -> Result<(), JsonError> {
+        loop {
+            while let Some(Frame::List(Some(0)) | Frame::Map { left: Some(0), key: true })
+                = self.frames.last()
+            {
+                self.end()?;
+            }
+            if self.builder.done() || (self.frames.is_empty() && self.input.at_end()?) {
+                return Ok(());
+            }
+
+            self.start = self.input.offset;
+            let initial = self.input.u8()?;
+            match self.frames.last_mut() {
+                Some(Frame::List(None) | Frame::Map { left: None, key: true })
+                    if initial == BREAK =>
+                {
+                    self.end()?;
+                    continue;
+                }
+                Some(Frame::List(Some(left))) => *left -= 1,
+                Some(Frame::Map { left, key: key @ true }) => {
+                    if let Some(left) = left {
+                        *left -= 1;
+                    }
+                    *key = false;
+                    let key = self.key(initial)?;
+                    self.builder.key(key)?;
+                    continue;
+                }
+                _ => (),
+            }
+            self.item(initial)?;
+        }
+    }
+
+    /// Close the list or map on top of the stack.
+    fn end(&mut self) -> Result<(), JsonError> {
+        self.frames.pop();
+        self.builder.end()?;
+        self.value_done();
+        Ok(())
+    }
+
+    /// A value is finished, so if it was in a map, a key is next.
+    fn value_done(&mut self) {
+        if let Some(Frame::Map { key, .. }) = self.frames.last_mut() {
+            *key = true;
+        }
+    }
+
+    fn item(&mut self, initial: u8) -> Result<(), JsonError> {
+        match initial >> 5 {
+            4 => {
+                let len = self.length(initial)?;
+                self.builder.start_list()?;
+                self.frames.push(Frame::List(len));
+            }
+            5 => {
+                let len = self.length(initial)?;
+                self.builder.start_object()?;
+                self.frames.push(Frame::Map { left: len, key: true });
+            }
+            6 => {
+                let tag = self.argument(initial)?;
+                if tag == 55799 {
+                    // Self-describe: it only says that this is CBOR. The item it's on is still to
+                    // come, so it mustn't count as one of a list's items as well.
+                    if let Some(Frame::List(Some(left))) = self.frames.last_mut() {
+                        *left += 1;
+                    }
+                    return Ok(());
+                }
+                if let Some(number) = self.bignum(tag)? {
+                    self.builder.scalar(number)?;
+                    self.value_done();
+                    return Ok(());
+                }
+                // The tagged item is read by the main loop rather than here, so that nesting tags
+                // deeply can't overflow the stack.
+                self.builder.start_object()?;
+                self.builder.key(format!("tag({tag})"))?;
+                self.frames.push(Frame::Map { left: Some(0), key: false });
+            }
+            _ => {
+                let value = self.scalar(initial)?;
+                self.builder.scalar(value)?;
+                self.value_done();
+            }
+        }
+        Ok(())
+    }
+
+    /// The argument of an item: its value, length or tag number.
+    fn argument(&mut self, initial: u8) -> Result<u64, JsonError> {
+        Ok(match initial & 0x1F {
+            n @ 0 ..= 23 => u64::from(n),
+            24 => u64::from(self.input.u8()?),
+            25 => u64::from(u16::from_be_bytes(self.input.array()?)),
+            26 => u64::from(u32::from_be_bytes(self.input.array()?)),
+            27 => u64::from_be_bytes(self.input.array()?),
+            31 => return Err(JsonError::Syntax(format!("unexpected indefinite length in CBOR \
+                                                         item 0x{initial:02x}"))),
+            _ => return Err(JsonError::Syntax(format!("invalid CBOR item 0x{initial:02x}"))),
+        })
+    }
+
+    /// The length of a list or map, or `None` if it's indefinite.
+    fn length(&mut self, initial: u8) -> Result<Option<u64>, JsonError> {
+        if initial & 0x1F == 31 {
+            Ok(None)
+        } else {
+            self.argument(initial).map(Some)
+        }
+    }
+
+    /// The contents of a byte or text string, joining the chunks of an indefinite-length one.
+    fn string(&mut self, initial: u8) -> Result<Vec<u8>, JsonError> {
+        if initial & 0x1F != 31 {
+            let len = self.argument(initial)?;
+            return self.input.vec(len);
+        }
+        let mut data = vec![];
+        loop {
+            let chunk = self.input.u8()?;
+            if chunk == BREAK {
+                return Ok(data);
+            }
+            if chunk >> 5 != initial >> 5 || chunk & 0x1F == 31 {
+                return Err(JsonError::Syntax(format!(
+                    "invalid chunk 0x{chunk:02x} in indefinite-length CBOR string")));
+            }
+            let len = self.argument(chunk)?;
+            data.extend(self.input.vec(len)?);
+        }
+    }
+
+    fn text(&mut self, initial: u8) -> Result<String, JsonError> {
+        String::from_utf8(self.string(initial)?).map_err(|e| JsonError::Unicode(e.utf8_error()))
+    }
+
+    /// Decode an item which isn't a list, map or tag.
+    fn scalar(&mut self, initial: u8) -> Result<Terminal, JsonError> {
+        Ok(match (initial >> 5, initial & 0x1F) {
+            (0, _) => Terminal::Number(self.argument(initial)?.to_string()),
+            (1, _) => Terminal::Number(format!("-{}", u128::from(self.argument(initial)?) + 1)),
+            (2, _) => Terminal::String(encode_bytes(&self.string(initial)?, self.hex)),
+            (3, _) => Terminal::String(self.text(initial)?),
+            (7, 20) => Terminal::Bool(false),
+            (7, 21) => Terminal::Bool(true),
+            (7, 22 | 23) => Terminal::Null,
+            (7, n @ 0 ..= 19) => Terminal::String(format!("simple({n})")),
+            (7, 24) => Terminal::String(format!("simple({})", self.input.u8()?)),
+            (7, 25) => float(half(u16::from_be_bytes(self.input.array()?))),
+            (7, 26) => float(f32::from_be_bytes(self.input.array()?)),
+            (7, 27) => float(f64::from_be_bytes(self.input.array()?)),
+            (7, 31) => return Err(JsonError::Syntax("unexpected CBOR break".to_owned())),
+            _ => return Err(JsonError::Syntax(format!("invalid CBOR item 0x{initial:02x}"))),
+        })
+    }
+
+    /// Tags 2 and 3 on a byte string are a big positive or negative integer. The byte string is
+    /// only read if it's there.
+    fn bignum(&mut self, tag: u64) -> Result<Option<Terminal>, JsonError> {
+        if !matches!(tag, 2 | 3) || self.input.peek()?.is_none_or(|initial| initial >> 5 != 2) {
+            return Ok(None);
+        }
+        let initial = self.input.u8()?;
+        let mut bytes = self.string(initial)?;
+        if tag == 2 {
+            return Ok(Some(Terminal::Number(decimal(bytes.iter().map(|&b| u32::from(b)), 256))));
+        }
+        // It's -1 - n, so add one to n.
+        let mut carry = true;
+        for b in bytes.iter_mut().rev() {
+            (*b, carry) = b.overflowing_add(u8::from(carry));
+        }
+        if carry {
+            bytes.insert(0, 1);
+        }
+        Ok(Some(Terminal::Number(format!("-{}", decimal(bytes.iter().map(|&b| u32::from(b)), 256)))))
+    }
+
+    /// The text of a map key: a text or byte string as it would be written as a value, without
+    /// the quotes, and anything else as JSON.
+    fn key(&mut self, mut initial: u8) -> Result<String, JsonError> {
+        let mut s = String::new();
+        loop {
+            match initial >> 5 {
+                2 | 3 => match self.scalar(initial)? {
+                    Terminal::String(s) => return Ok(s),
+                    _ => unreachable!(),
+                },
+                6 => match self.argument(initial)? {
+                    // Self-describe, which doesn't change the key.
+                    55799 => initial = self.input.u8()?,
+                    tag => {
+                        self.tag_json(tag, &mut s, 0)?;
+                        return Ok(s);
+                    }
+                },
+                _ => {
+                    self.key_json(initial, &mut s, 0)?;
+                    return Ok(s);
+                }
+            }
+        }
+    }
+
+    /// Write an item in a map key as JSON, with tags written the way CBOR's diagnostic notation
+    /// does, like `1(0)`.
+    fn key_json(&mut self, initial: u8, out: &mut String, depth: usize) -> Result<(), JsonError> {
+        if depth > MAX_KEY_DEPTH {
+            return Err(JsonError::Syntax("CBOR map key nested too deeply".to_owned()));
+        }
+        match initial >> 5 {
+            4 | 5 => {
+                let map = initial >> 5 == 5;
+                let mut left = self.length(initial)?;
+                out.push(if map { '{' } else { '[' });
+                let mut first = true;
+                while left != Some(0) {
+                    let item = self.input.u8()?;
+                    if left.is_none() && item == BREAK {
+                        break;
+                    }
+                    if !first {
+                        out.push(',');
+                    }
+                    first = false;
+                    if map {
+                        out.push_str(&Terminal::String(self.key(item)?).to_string());
+                        out.push(':');
+                        let item = self.input.u8()?;
+                        self.key_json(item, out, depth + 1)?;
+                    } else {
+                        self.key_json(item, out, depth + 1)?;
+                    }
+                    left = left.map(|n| n - 1);
+                }
+                out.push(if map { '}' } else { ']' });
+            }
+            6 => {
+                let tag = self.argument(initial)?;
+                self.tag_json(tag, out, depth)?;
+            }
+            _ => out.push_str(&self.scalar(initial)?.to_string()),
+        }
+        Ok(())
+    }
+
+    /// Write a tagged item in a map key, after its tag number, for [`Decoder::key_json`].
+    fn tag_json(&mut self, tag: u64, out: &mut String, depth: usize) -> Result<(), JsonError> {
+        if let Some(number) = self.bignum(tag)? {
+            out.push_str(&number.to_string());
+        } else if tag == 55799 {
+            let item = self.input.u8()?;
+            self.key_json(item, out, depth + 1)?;
+        } else {
+            out.push_str(&format!("{tag}("));
+            let item = self.input.u8()?;
+            self.key_json(item, out, depth + 1)?;
+            out.push(')');
+        }
+        Ok(())
+    }
+}
+
+/// Convert a half-precision float to a single-precision one, which it fits in exactly.
+fn half(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = i32::from(bits >> 10 & 0x1F);
+    let mantissa = f32::from(bits & 0x3FF);
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        31 if mantissa == 0. => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1024. + mantissa) * 2f32.powi(exponent - 25),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{parse_with, Format, Options};
+
+    fn run(input: &[u8], options: Options) -> String {
+        let mut out = vec![];
+        let options = Options { format: Format::Cbor, ..options };
+        parse_with(input, &mut out, options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_cbor() {
+        // {"a": [1, -500, 1.5, h'0102', "é"], 1: {}, "t": 1(1363896240), "b": 2(h'010000000000000000'),
+        //  [1, "x"]: [_ null, true, 1.0e+300], "h": -0.0 (half)}
+        let input = b"\xa6\x61\x61\x85\x01\x39\x01\xf3\xf9\x3e\x00\x42\x01\x02\x62\xc3\xa9\
+            \x01\xa0\x61\x74\xc1\x1a\x51\x4b\x67\xb0\x61\x62\xc2\x49\x01\x00\x00\x00\x00\x00\x00\
+            \x00\x00\x82\x01\x61\x78\x9f\xf6\xf5\xfb\x7e\x37\xe4\x3c\x88\x00\x75\x9c\xff\x61\x68\
+            \xf9\x80\x00";
+        assert_eq!(run(input, Options::default()), "\
+            .a[0] = 1\n\
+            .a[1] = -500\n\
+            .a[2] = 1.5\n\
+            .a[3] = \"AQI=\"\n\
+            .a[4] = \"é\"\n\
+            .1 = {}\n\
+            .t.\"tag(1)\" = 1363896240\n\
+            .b = 18446744073709551616\n\
+            .\"[1,\\\"x\\\"]\"[0] = null\n\
+            .\"[1,\\\"x\\\"]\"[1] = true\n\
+            .\"[1,\\\"x\\\"]\"[2] = 1e300\n\
+            .h = -0.0\n\
+            \n");
+
+        // a sequence of items, with the self-describe tag, an indefinite-length string, and
+        // byte strings in hex
+        let input = b"\xd9\xd9\xf7\x82\x7f\x61\x61\x62\x62\x63\xff\x43\x01\xab\xff\xc3\x41\xff";
+        let options = Options { hex: true, ..Options::default() };
+        assert_eq!(run(input, options), "[0] = \"abc\"\n[1] = \"01abff\"\n\n\n");
+
+        // the self-describe tag on items in a list, and on a key and a value in a map
+        let input = b"\x82\xd9\xd9\xf7\x01\x02\xa1\xd9\xd9\xf7\x61\x6b\xd9\xd9\xf7\x03";
+        assert_eq!(run(input, Options::default()), "[0] = 1\n[1] = 2\n\n.k = 3\n\n");
+
+        // the self-describe tag is recognised without being asked for CBOR
+        let mut out = vec![];
+        parse_with(&b"\xd9\xd9\xf7\xa1\x61\x6b\x80"[..], &mut out, Options::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), ".k = []\n\n");
+    }
+
+    #[test]
+    fn test_cbor_errors() {
+        let error = |input: &[u8]| {
+            let options = Options { format: Format::Cbor, ..Options::default() };
+            let e = parse_with(input, std::io::sink(), options).unwrap_err();
+            (e.offset, e.path.to_string(), e.error.to_string())
+        };
+        assert_eq!(error(b"\xa1\x61\x61\x82\x01"),
+                   (5, ".a[1]".to_owned(), "input truncated".to_owned()));
+        assert_eq!(error(b"\x82\x01\x1c"),
+                   (2, "[1]".to_owned(), "invalid CBOR item 0x1c".to_owned()));
+        assert_eq!(error(b"\x81\xff"),
+                   (1, "[0]".to_owned(), "unexpected CBOR break".to_owned()));
+
+        // tags nested too deeply to decode by recursion
+        let mut input = vec![0xC1; 100_000];
+        input.push(0x00);
+        let options = Options { format: Format::Cbor, ..Options::default() };
+        assert!(parse_with(&input[..], std::io::sink(), options).is_ok());
+        assert_eq!(error(&input[..input.len() - 1]).2, "input truncated");
+
+        // with --repair, the open lists and maps are closed, leaving out the unfinished key
+        let mut out = vec![];
+        let options = Options { format: Format::Cbor, repair: true, ..Options::default() };
+        let input = b"\xa2\x61\x61\x82\x01\x02\x62\x62";
+        let e = parse_with(&input[..], &mut out, options).unwrap_err();
+        assert!(matches!(e.error, crate::JsonError::Truncated));
+        assert_eq!(String::from_utf8(out).unwrap(), ".a[0] = 1\n.a[1] = 2\n\n");
+    }
+}
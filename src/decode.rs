@@ -0,0 +1,378 @@
+//! Support for reading formats other than JSON. Their decoders drive a [`Builder`], which keeps a
+//! data stack just like the JSON parser's and makes the same calls to a [`Sink`], so the output is
+//! written the same way whatever the input was.
+
+use std::io::{self, BufRead, Read};
+
+use crate::{pop_append, pop_setitem, Error, Event, JsonError, Path, Segment, Sink, Terminal, Value};
+
+/// Builds up the data stack from a decoder's values, telling the sink as it goes.
+pub(crate) struct Builder<S> {
+    pub ds: Vec<Value>,
+    pub sink: S,
+}
+
+impl<S: Sink> Builder<S> {
+    pub fn new(sink: S) -> Self {
+        Builder { ds: vec![], sink }
+    }
+
+    pub fn start_list(&mut self) -> io::Result<()> {
+        self.ds.push(Value::List { index: 0 });
+        self.sink.pushed(&self.ds);
+        self.sink.start_container(&self.ds)?;
+        if S::EVENTS {
+            self.sink.event(Event::StartArray)?;
+        }
+        Ok(())
+    }
+
+    pub fn start_object(&mut self) -> io::Result<()> {
+        self.ds.push(Value::Object { empty: true });
+        self.sink.pushed(&self.ds);
+        self.sink.start_container(&self.ds)?;
+        if S::EVENTS {
+            self.sink.event(Event::StartObject)?;
+        }
+        Ok(())
+    }
+
+    /// The key of the next value in the object on top of the data stack.
+    pub fn key(&mut self, key: String) -> io::Result<()> {
+        if S::EVENTS {
+            self.sink.event(Event::Key(key.clone()))?;
+        }
+        self.ds.push(Terminal::String(key).into());
+        self.sink.pushed(&self.ds);
+        Ok(())
+    }
+
+    pub fn scalar(&mut self, value: Terminal) -> io::Result<()> {
+        if S::EVENTS {
+            self.sink.event(Event::Scalar(value.clone()))?;
+        }
+        self.ds.push(value.into());
+        self.sink.pushed(&self.ds);
+        self.finish_value()
+    }
+
+    /// The list or object on top of the data stack is finished.
+    pub fn end(&mut self) -> io::Result<()> {
+        if S::EVENTS {
+            match self.ds.last() {
+                Some(Value::List { .. }) => self.sink.event(Event::EndArray)?,
+                _ => self.sink.event(Event::EndObject)?,
+            }
+        }
+        self.finish_value()
+    }
+
+    /// Put the value on top of the data stack into whatever it's in, or if it's a top-level
+    /// value, get ready for the next one.
+    fn finish_value(&mut self) -> io::Result<()> {
+        let n = self.ds.len();
+        if n == 1 {
+            if let Some(Value::Terminal(t)) = self.ds.pop() {
+                self.sink.top_level(t)?;
+            }
+            self.ds.clear();
+            self.sink.popped(0);
+            self.sink.next_document()
+        } else if matches!(self.ds[n - 2], Value::List { .. }) {
+            pop_append(&mut self.ds, &mut self.sink)
+        } else {
+            pop_setitem(&mut self.ds, &mut self.sink)
+        }
+    }
+
+    /// Whether the sink doesn't want any more values.
+    pub fn done(&self) -> bool {
+        self.sink.done()
+    }
+
+    /// End the sink's output if decoding went well, or give the error, `offset` bytes into the
+    /// input. If the input was cut off and `repair` is set, what there was of the last value is
+    /// finished off first, the way the JSON parser does it.
+    pub fn finish(&mut self, result: Result<(), JsonError>, offset: u64, repair: bool)
+        -> Result<(), Error>
+    {
+        let error = match result.and_then(|()| Ok(self.sink.end()?)) {
+            Ok(()) => return Ok(()),
+            Err(e) => self.error(offset, e),
+        };
+        if repair && matches!(error.error, JsonError::Truncated) {
+            self.repair()
+                .and_then(|()| self.sink.end())
+                .map_err(|e| self.error(offset, e.into()))?;
+        }
+        Err(error)
+    }
+
+    /// Close the open lists and objects, dropping a key with no value.
+    fn repair(&mut self) -> io::Result<()> {
+        while let Some(top) = self.ds.last() {
+            if let Value::Terminal(_) = top {
+                // Scalars are taken off as soon as they're added, so this is a key.
+                self.ds.pop();
+                self.sink.popped(self.ds.len());
+            } else {
+                self.end()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// An error `offset` bytes into binary input, which has no lines or columns.
+    pub fn error(&self, offset: u64, error: JsonError) -> Error {
+        Error {
+            line: 0,
+            col: 0,
+            offset,
+            path: Path::partial(&self.ds),
+            snippet: None,
+            error,
+        }
+    }
+}
+
+/// Reads binary input, keeping count of how far into it we are.
+pub(crate) struct Bytes<R> {
+    input: R,
+    pub offset: u64,
+}
+
+impl<R: BufRead> Bytes<R> {
+    pub fn new(input: R) -> Self {
+        Bytes { input, offset: 0 }
+    }
+
+    /// Whether the input has ended.
+    pub fn at_end(&mut self) -> io::Result<bool> {
+        loop {
+            match self.input.fill_buf() {
+                Ok(buf) => return Ok(buf.is_empty()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// The next byte, without reading it, or `None` at the end of the input.
+    pub fn peek(&mut self) -> Result<Option<u8>, JsonError> {
+        self.at_end()?;
+        Ok(self.input.fill_buf()?.first().copied())
+    }
+
+    pub fn u8(&mut self) -> Result<u8, JsonError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], JsonError> {
+        let mut buf = [0; N];
+        match self.input.read_exact(&mut buf) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(JsonError::Truncated),
+            Err(e) => return Err(e.into()),
+        }
+        self.offset += N as u64;
+        Ok(buf)
+    }
+
+    /// Read a string ending with a NUL byte, without the NUL.
+    pub fn cstring(&mut self) -> Result<Vec<u8>, JsonError> {
+        let mut buf = vec![];
+        let read = self.input.read_until(0, &mut buf)?;
+        self.offset += read as u64;
+        if buf.pop() != Some(0) {
+            return Err(JsonError::Truncated);
+        }
+        Ok(buf)
+    }
+
+    /// Read `len` bytes. The buffer grows as they're read, rather than trusting the length up
+    /// front, in case the input is cut off.
+    pub fn vec(&mut self, len: u64) -> Result<Vec<u8>, JsonError> {
+        let mut buf = vec![];
+        let read = (&mut self.input).take(len).read_to_end(&mut buf)?;
+        self.offset += read as u64;
+        if (read as u64) < len {
+            return Err(JsonError::Truncated);
+        }
+        Ok(buf)
+    }
+}
+
+/// A position in text input.
+#[derive(Clone, Copy)]
+pub(crate) struct Mark {
+    pub pos: usize, // in chars
+    pub line: u64,
+    pub col: usize,
+    pub offset: u64, // in bytes
+}
+
+/// Text input which is all in memory, for formats which need to look ahead, keeping count of the
+/// line and column.
+pub(crate) struct Text {
+    pub chars: Vec<char>,
+    pub at: Mark,
+}
+
+impl Text {
+    pub fn new(text: &str) -> Self {
+        Text {
+            chars: text.chars().collect(),
+            at: Mark { pos: 0, line: 1, col: 0, offset: 0 },
+        }
+    }
+
+    pub fn peek(&self) -> Option<char> {
+        self.peek_at(0)
+    }
+
+    pub fn peek_at(&self, n: usize) -> Option<char> {
+        self.chars.get(self.at.pos + n).copied()
+    }
+
+    pub fn advance(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.at.pos += 1;
+        self.at.offset += c.len_utf8() as u64;
+        if c == '\n' {
+            self.at.line += 1;
+            self.at.col = 0;
+        } else {
+            self.at.col += 1;
+        }
+        Some(c)
+    }
+
+    pub fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.advance();
+        }
+    }
+
+    /// Skip to the end of the line, leaving the position at the line break.
+    pub fn skip_line(&mut self) {
+        while !matches!(self.peek(), None | Some('\n')) {
+            self.advance();
+        }
+    }
+
+    /// An error at the current position, in the value at `path`.
+    pub fn error(&self, path: &[Segment], error: JsonError) -> Error {
+        Error {
+            line: self.at.line,
+            col: self.at.col as u64 + 1,
+            offset: self.at.offset,
+            path: Path(path.to_vec()),
+            snippet: None,
+            error,
+        }
+    }
+}
+
+/// Read all of a text input, which has to be UTF-8. A byte order mark is left out, and CRLF line
+/// endings are read as LF.
+pub(crate) fn read_text(mut input: impl BufRead) -> Result<String, Error> {
+    let mut data = vec![];
+    if let Err(e) = input.read_to_end(&mut data) {
+        return Err(Text::new("").error(&[], e.into()));
+    }
+    let text = match String::from_utf8(data) {
+        Ok(text) => text,
+        Err(e) => {
+            let valid = e.utf8_error().valid_up_to();
+            let mut text = Text::new(&String::from_utf8_lossy(&e.as_bytes()[.. valid]));
+            while text.advance().is_some() {}
+            return Err(text.error(&[], JsonError::Unicode(e.utf8_error()))
+                .with_snippet(e.as_bytes(), 0));
+        }
+    };
+    Ok(text.strip_prefix('\u{FEFF}').unwrap_or(&text).replace("\r\n", "\n"))
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Binary data as a string: base64, with padding, or hex if `hex` is set.
+pub(crate) fn encode_bytes(bytes: &[u8], hex: bool) -> String {
+    if hex {
+        return bytes.iter().map(|b| format!("{b:02x}")).collect();
+    }
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | u32::from(b) << (16 - 8 * i));
+        for i in 0 .. 4 {
+            if i <= chunk.len() {
+                s.push(char::from(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize]));
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+/// Write a floating point number the way JSON would, apart from `Infinity` and `NaN`, which JSON
+/// has no way to write.
+pub(crate) fn float<F: Into<f64> + Copy + std::fmt::Debug>(value: F) -> Terminal {
+    let f: f64 = value.into();
+    Terminal::Number(if f.is_nan() {
+        "NaN".to_owned()
+    } else if f.is_infinite() {
+        if f > 0. { "Infinity" } else { "-Infinity" }.to_owned()
+    } else {
+        // Debug formatting gives the shortest digits which round-trip, with an exponent for
+        // very large and small numbers.
+        format!("{value:?}")
+    })
+}
+
+/// A time as seconds and nanoseconds since the Unix epoch, written in RFC 3339 format in UTC, like
+/// `2023-06-01T12:30:00.5Z`.
+pub(crate) fn timestamp(secs: i64, nanos: u32) -> String {
+    let days = secs.div_euclid(86400);
+    let time = secs.rem_euclid(86400);
+    // Howard Hinnant's days_from_civil, backwards.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let mut s = format!("{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}",
+                        time / 3600, time / 60 % 60, time % 60);
+    if nanos != 0 {
+        let fraction = format!("{nanos:09}");
+        s.push('.');
+        s.push_str(fraction.trim_end_matches('0'));
+    }
+    s.push('Z');
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_bytes() {
+        assert_eq!("", encode_bytes(b"", false));
+        assert_eq!("Zg==", encode_bytes(b"f", false));
+        assert_eq!("Zm8=", encode_bytes(b"fo", false));
+        assert_eq!("Zm9v+/8=", encode_bytes(b"foo\xfb\xff", false));
+        assert_eq!("00ff10", encode_bytes(b"\x00\xff\x10", true));
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!("1970-01-01T00:00:00Z", timestamp(0, 0));
+        assert_eq!("2013-03-21T20:04:00Z", timestamp(1363896240, 0));
+        assert_eq!("2000-02-29T23:59:59.5Z", timestamp(951868799, 500_000_000));
+        assert_eq!("1969-12-31T23:59:59.000000001Z", timestamp(-1, 1));
+    }
+}
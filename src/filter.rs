@@ -0,0 +1,380 @@
+//! Include/exclude filters on paths, using glob-style patterns.
+//!
+//! Patterns are written like catj's paths, with some wildcards:
+//!
+//! * `.*` matches any key, and `*` can also be used as part of a bare key, like `.user_*`.
+//! * `[*]` matches any list index.
+//! * `.**` matches any number of levels (including zero), and `..key` is short for `.**.key`.
+//!
+//! A pattern has to match the whole path of a value, so to match everything under some path, end
+//! the pattern with `.**`.
+//!
+//! Patterns can also be written as JSON Pointers, like `/items/*/metadata/**`, where `*` matches any
+//! one key or index.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::{Terminal, Value};
+use crate::ungron::{unescape_pointer_token, Line, UngronError};
+
+#[derive(Debug)]
+pub struct PatternError {
+    pub pattern: String,
+    pub message: String,
+}
+
+impl Display for PatternError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid pattern {:?}: {}", self.pattern, self.message)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Key(String),
+    KeyGlob(String),
+    Index(u64),
+    AnyIndex,
+    AnyDepth,
+    /// From a JSON Pointer, which doesn't say whether a number is a key or an index.
+    KeyOrIndex(String, u64),
+    Any,
+}
+
+/// A path step taken from the parser's data stack.
+#[derive(Debug, Clone, Copy)]
+enum Step<'a> {
+    Key(&'a str),
+    Index(u64),
+}
+
+/// Get the next key or index from the data stack, and the rest of the stack after it.
+fn next_step(ds: &[Value]) -> Option<(Step<'_>, &[Value])> {
+    let mut ds = ds;
+    loop {
+        let (first, rest) = ds.split_first()?;
+        match first {
+            Value::Object { .. } => ds = rest,
+            Value::List { index } => return Some((Step::Index(*index), rest)),
+            Value::Terminal(Terminal::String(key)) => return Some((Step::Key(key), rest)),
+            Value::Terminal(other) => panic!("invalid item in a path: {:?}", other),
+        }
+    }
+}
+
+/// Whether `s` matches `pattern`, where `*` matches any run of bytes. When a match fails, only the
+/// last `*` is tried with a longer run, which is enough and takes at most `pattern.len() * s.len()`
+/// steps.
+fn glob(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    let mut star = None; // where the last `*` was, and where the run it matches ends
+    while i < s.len() {
+        if pattern.get(p) == Some(&b'*') {
+            star = Some((p, i));
+            p += 1;
+        } else if pattern.get(p) == Some(&s[i]) {
+            p += 1;
+            i += 1;
+        } else if let Some((star_p, star_i)) = star {
+            star = Some((star_p, star_i + 1));
+            p = star_p + 1;
+            i = star_i + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p ..].iter().all(|&c| c == b'*')
+}
+
+impl Part {
+    fn matches(&self, step: Step<'_>) -> bool {
+        match (self, step) {
+            (Part::Key(k), Step::Key(s)) => k == s,
+            (Part::KeyGlob(g), Step::Key(s)) => glob(g.as_bytes(), s.as_bytes()),
+            (Part::Index(i), Step::Index(j)) => *i == j,
+            (Part::AnyIndex, Step::Index(_)) => true,
+            (Part::KeyOrIndex(k, _), Step::Key(s)) => k == s,
+            (Part::KeyOrIndex(_, i), Step::Index(j)) => *i == j,
+            (Part::Any, _) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    parts: Vec<Part>,
+}
+
+impl Pattern {
+    fn parse(s: &str) -> Result<Self, PatternError> {
+        let error = |message: String| PatternError { pattern: s.to_owned(), message };
+        let syntax = |e| match e {
+            UngronError::Syntax(msg) => error(msg),
+            other => error(format!("{other:?}")),
+        };
+        if s.starts_with('/') {
+            return Self::parse_pointer(s).map_err(error);
+        }
+        let mut line = Line::new(s);
+        let mut parts = vec![];
+        while let Some(c) = line.peek() {
+            match c {
+                b'.' => {
+                    line.eat(".");
+                    if line.eat(".") {
+                        parts.push(Part::AnyDepth);
+                        if line.peek() == Some(b'[') {
+                            continue;
+                        }
+                    }
+                    if line.peek() == Some(b'"') {
+                        parts.push(Part::Key(line.string().map_err(syntax)?));
+                    } else if line.eat("**") {
+                        parts.push(Part::AnyDepth);
+                    } else {
+                        let key = line.take_while(
+                            |c| c.is_ascii_alphanumeric() || c == b'_' || c == b'*');
+                        if key.is_empty() {
+                            return Err(syntax(line.error("expected a key")));
+                        } else if key.contains('*') {
+                            parts.push(Part::KeyGlob(key.to_owned()));
+                        } else {
+                            parts.push(Part::Key(key.to_owned()));
+                        }
+                    }
+                }
+                b'[' => {
+                    line.eat("[");
+                    if line.eat("*") {
+                        parts.push(Part::AnyIndex);
+                    } else {
+                        let index = line.take_while(|c| c.is_ascii_digit())
+                            .parse()
+                            .map_err(|_| syntax(line.error("expected a list index or '*'")))?;
+                        parts.push(Part::Index(index));
+                    }
+                    line.expect("]").map_err(syntax)?;
+                }
+                _ => return Err(syntax(line.error("expected '.' or '['"))),
+            }
+        }
+        if parts.is_empty() {
+            return Err(error("empty pattern".to_owned()));
+        }
+        Ok(Pattern { parts })
+    }
+
+    fn parse_pointer(s: &str) -> Result<Self, String> {
+        let parts = s.split('/')
+            .skip(1)
+            .map(|token| {
+                Ok(match token {
+                    "**" => Part::AnyDepth,
+                    "*" => Part::Any,
+                    _ if token.contains('*') => Part::KeyGlob(unescape_pointer_token(token)?),
+                    _ => match token.parse() {
+                        Ok(i) if token == "0" || !token.starts_with(['0', '+']) => {
+                            Part::KeyOrIndex(token.to_owned(), i)
+                        }
+                        _ => Part::Key(unescape_pointer_token(token)?),
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Pattern { parts })
+    }
+
+    fn is_literal(&self) -> bool {
+        self.parts.iter().all(|p| matches!(p, Part::Key(_) | Part::Index(_) | Part::KeyOrIndex(..)))
+    }
+
+    fn matches(&self, ds: &[Value]) -> bool {
+        fn match_from(parts: &[Part], ds: &[Value]) -> bool {
+            match parts.split_first() {
+                None => next_step(ds).is_none(),
+                Some((Part::AnyDepth, rest)) => {
+                    let mut ds = ds;
+                    loop {
+                        if match_from(rest, ds) {
+                            return true;
+                        }
+                        match next_step(ds) {
+                            Some((_, next)) => ds = next,
+                            None => return false,
+                        }
+                    }
+                }
+                Some((part, rest)) => match next_step(ds) {
+                    Some((step, next)) => part.matches(step) && match_from(rest, next),
+                    None => false,
+                },
+            }
+        }
+        match_from(&self.parts, ds)
+    }
+
+    /// For a literal pattern: `Some(true)` if the given path is the pattern or is under it,
+    /// `Some(false)` if it comes after the pattern in some list, and `None` otherwise.
+    fn position(&self, ds: &[Value]) -> Option<bool> {
+        let mut ds = ds;
+        for part in &self.parts {
+            let (step, next) = next_step(ds)?;
+            match (part, step) {
+                (Part::Key(k) | Part::KeyOrIndex(k, _), Step::Key(s)) if k == s => (),
+                (Part::Index(i) | Part::KeyOrIndex(_, i), Step::Index(j)) if *i == j => (),
+                (Part::Index(i) | Part::KeyOrIndex(_, i), Step::Index(j)) => {
+                    return if j > *i { Some(false) } else { None };
+                }
+                _ => return None,
+            }
+            ds = next;
+        }
+        Some(true)
+    }
+}
+
+/// A set of include and exclude patterns. A path passes if it matches any of the include patterns
+/// (or there aren't any), and none of the exclude patterns.
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    stop_early: bool,
+    entered: Vec<bool>, // for each include pattern, whether a path under it has been seen
+    passed: Vec<bool>,  // for each include pattern, whether the input has moved past it
+}
+
+impl Filter {
+    pub fn include(&mut self, pattern: &str) -> Result<(), PatternError> {
+        self.include.push(Pattern::parse(pattern)?);
+        self.entered.push(false);
+        self.passed.push(false);
+        Ok(())
+    }
+
+    pub fn exclude(&mut self, pattern: &str) -> Result<(), PatternError> {
+        self.exclude.push(Pattern::parse(pattern)?);
+        Ok(())
+    }
+
+    /// If every include pattern is free of wildcards, stop reading the input as soon as the
+    /// parser has moved past all of them. This assumes that objects don't have duplicate keys,
+    /// and means any further top-level values are ignored.
+    pub fn stop_early(&mut self, stop: bool) {
+        self.stop_early = stop;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Check the path on the given data stack.
+    pub fn matches(&self, ds: &[Value]) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(ds)))
+            && !self.exclude.iter().any(|p| p.matches(ds))
+    }
+
+    /// Check the path on the given data stack, also keeping track of which patterns have been
+    /// passed, for [`Filter::done`].
+    pub(crate) fn check(&mut self, ds: &[Value]) -> bool {
+        if self.stop_early {
+            for (i, pattern) in self.include.iter().enumerate() {
+                match pattern.position(ds) {
+                    Some(true) => self.entered[i] = true,
+                    Some(false) => self.passed[i] = true,
+                    None if self.entered[i] => self.passed[i] = true,
+                    None => (),
+                }
+            }
+        }
+        self.matches(ds)
+    }
+
+    /// Whether no more paths can pass the filter, and the rest of the input can be skipped.
+    pub fn done(&self) -> bool {
+        self.stop_early
+            && !self.include.is_empty()
+            && self.include.iter().all(Pattern::is_literal)
+            && self.passed.iter().all(|&p| p)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn key(s: &str) -> Value {
+        Terminal::String(s.to_owned()).into()
+    }
+
+    fn obj() -> Value {
+        Value::Object { empty: false }
+    }
+
+    fn list(index: u64) -> Value {
+        Value::List { index }
+    }
+
+    fn matches(pattern: &str, ds: &[Value]) -> bool {
+        Pattern::parse(pattern).unwrap().matches(ds)
+    }
+
+    #[test]
+    fn test_patterns() {
+        let ds = [obj(), key("items"), list(3), obj(), key("metadata"), obj(), key("quoted key")];
+        assert!(matches(r#".items[3].metadata."quoted key""#, &ds));
+        assert!(matches(".items[*].metadata.**", &ds));
+        assert!(matches(r#".."quoted key""#, &ds));
+        assert!(matches(".**", &ds));
+        assert!(matches(".it*s[*].*.*", &ds));
+        assert!(!matches(".items[*].metadata", &ds));
+        assert!(!matches(".items[2].**", &ds));
+        assert!(matches(".items.**", &ds));
+        assert!(!matches(".items.*.**", &ds));
+        assert!(matches("..[3].**", &ds));
+
+        assert!(matches("/items/3/metadata/quoted key", &ds));
+        assert!(matches("/items/*/meta*/**", &ds));
+        assert!(matches("/**/quoted key", &ds));
+        assert!(!matches("/items/03/**", &ds));
+        assert!(matches("/3", &[obj(), key("3")]));
+
+        assert!(Pattern::parse("items").is_err());
+        assert!(Pattern::parse("/a~").is_err());
+        assert!(Pattern::parse(".a[x]").is_err());
+        assert!(Pattern::parse(".").is_err());
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob(b"", b""));
+        assert!(glob(b"*", b""));
+        assert!(glob(b"a*c", b"abbc"));
+        assert!(glob(b"a*b*c", b"abcbc"));
+        assert!(glob(b"*ab", b"aab"));
+        assert!(!glob(b"a*c", b"abcb"));
+        assert!(!glob(b"a*", b"ba"));
+        // Recursing over every split would take years for this.
+        let key = "a".repeat(100);
+        assert!(!glob(format!("{}b", "*a".repeat(30)).as_bytes(), key.as_bytes()));
+    }
+
+    #[test]
+    fn test_stop_early() {
+        let mut filter = Filter::default();
+        filter.include(".a[1].b").unwrap();
+        filter.stop_early(true);
+        assert!(!filter.check(&[obj(), key("a"), list(0), obj(), key("b")]));
+        assert!(!filter.done());
+        assert!(filter.check(&[obj(), key("a"), list(1), obj(), key("b")]));
+        assert!(!filter.done());
+        assert!(!filter.check(&[obj(), key("a"), list(1), obj(), key("c")]));
+        assert!(filter.done());
+
+        filter.include(".x.**").unwrap();
+        assert!(!filter.done());
+    }
+}
@@ -0,0 +1,203 @@
+//! Output style which matches gron's, byte for byte.
+
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+
+use crate::unicode::{is_id_continue, is_id_start};
+use crate::{Terminal, Value};
+
+/// The words gron quotes even though they'd be identifiers otherwise.
+const RESERVED: &[&str] = &[
+    "break", "case", "catch", "class", "const", "continue", "debugger", "default", "delete", "do",
+    "else", "export", "extends", "false", "finally", "for", "function", "if", "import", "in",
+    "instanceof", "new", "null", "return", "super", "switch", "this", "throw", "true", "try",
+    "typeof", "var", "void", "while", "with", "yield",
+];
+
+/// gron goes by general categories (letters and `Nl` first, then also `Mn`, `Mc`, `Nd` and `Pc`),
+/// which is ID_Start and ID_Continue without titlecase letters and the "Other_ID" characters, and
+/// with the one letter that's also Pattern_Syntax.
+fn is_titlecase(c: char) -> bool {
+    matches!(c, '\u{1C5}' | '\u{1C8}' | '\u{1CB}' | '\u{1F2}' | '\u{1F88}' ..= '\u{1F8F}'
+        | '\u{1F98}' ..= '\u{1F9F}' | '\u{1FA8}' ..= '\u{1FAF}' | '\u{1FBC}' | '\u{1FCC}'
+        | '\u{1FFC}')
+}
+
+fn is_other_id(c: char) -> bool {
+    matches!(c, '\u{B7}' | '\u{387}' | '\u{1369}' ..= '\u{1371}' | '\u{19DA}' | '\u{2118}'
+        | '\u{212E}' | '\u{309B}' | '\u{309C}')
+}
+
+fn is_first(c: char) -> bool {
+    c == '$' || c == '_' || c == '\u{2E2F}'
+        || (is_id_start(c) && !is_titlecase(c) && !is_other_id(c)
+            && c != '\u{1885}' && c != '\u{1886}')
+}
+
+fn is_rest(c: char) -> bool {
+    is_first(c) || (is_id_continue(c) && !is_titlecase(c) && !is_other_id(c))
+}
+
+/// Whether gron would write the key bare, after a `.`.
+pub fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    is_first(first) && chars.all(is_rest) && !RESERVED.contains(&s)
+}
+
+/// Quote a string the way gron does: like JSON, but with the line and paragraph separators and
+/// all control characters (including DEL and the C1 range) escaped, in upper case hex.
+fn write_quoted(s: &str, f: &mut Formatter<'_>) -> fmt::Result {
+    f.write_str("\"")?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escape = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\x08' => "\\b",
+            '\x0C' => "\\f",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            '\u{2028}' | '\u{2029}' => "",
+            c if c.is_control() => "",
+            _ => continue,
+        };
+        f.write_str(&s[start .. i])?;
+        if escape.is_empty() {
+            write!(f, "\\u{:04X}", c as u32)?;
+        } else {
+            f.write_str(escape)?;
+        }
+        start = i + c.len_utf8();
+    }
+    f.write_str(&s[start ..])?;
+    f.write_str("\"")
+}
+
+struct Quoted<'a>(&'a str);
+
+impl Display for Quoted<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_quoted(self.0, f)
+    }
+}
+
+/// Displays an object key as gron does: `.foo` if it's an identifier, otherwise `["quoted"]`.
+pub struct Key<'a>(pub &'a str);
+
+impl Display for Key<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if is_identifier(self.0) {
+            write!(f, ".{}", self.0)
+        } else {
+            write!(f, "[{}]", Quoted(self.0))
+        }
+    }
+}
+
+/// Displays a value as gron does.
+pub struct Literal<'a>(pub &'a Terminal);
+
+impl Display for Literal<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            // gron's output has to be JSON, which these aren't.
+            Terminal::String(s) | Terminal::Typed(s) => write_quoted(s, f),
+            other => write!(f, "{other}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Other,
+    Index,
+    Equals,
+    Number,
+}
+
+/// A line of output, kept along with the tokens gron would sort it by.
+pub struct Statement {
+    tokens: Vec<(Kind, String)>,
+    pub line: Vec<u8>,
+}
+
+impl Statement {
+    pub fn new(root: &str, ds: &[Value], value: &Terminal, line: Vec<u8>) -> Self {
+        let other = |s: &str| (Kind::Other, s.to_owned());
+        let mut tokens = vec![other(root)];
+        for item in ds {
+            match item {
+                Value::Object { .. } => (),
+                Value::List { index } => {
+                    tokens.extend([other("["), (Kind::Index, index.to_string()), other("]")]);
+                }
+                Value::Terminal(Terminal::String(s)) if is_identifier(s) => {
+                    tokens.extend([other("."), other(s)]);
+                }
+                Value::Terminal(Terminal::String(s)) => {
+                    tokens.extend([other("["), (Kind::Other, Quoted(s).to_string()), other("]")]);
+                }
+                Value::Terminal(other) => panic!("invalid item in a path: {:?}", other),
+            }
+        }
+        tokens.push((Kind::Equals, "=".to_owned()));
+        let kind = match value {
+            Terminal::Number(_) => Kind::Number,
+            _ => Kind::Other,
+        };
+        tokens.push((kind, Literal(value).to_string()));
+        Statement { tokens, line }
+    }
+}
+
+/// Sort lines the way gron does: by the first token which differs, with a statement's `=` before
+/// anything under its path, list indices in numeric order, and other tokens by their text.
+pub fn sort(statements: &mut [Statement]) {
+    statements.sort_by(|a, b| {
+        let Some((ta, tb)) = a.tokens.iter().zip(&b.tokens).find(|(ta, tb)| ta != tb) else {
+            return a.tokens.len().cmp(&b.tokens.len());
+        };
+        match (ta, tb) {
+            ((Kind::Equals, _), _) => Ordering::Less,
+            (_, (Kind::Equals, _)) => Ordering::Greater,
+            ((Kind::Index, x), (Kind::Index, y)) => {
+                x.parse::<u64>().unwrap().cmp(&y.parse().unwrap())
+            }
+            ((Kind::Number, x), (Kind::Number, y)) => {
+                let x: f64 = x.parse().unwrap_or(0.);
+                let y: f64 = y.parse().unwrap_or(0.);
+                x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+            }
+            ((_, x), (_, y)) => x.cmp(y),
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_identifiers() {
+        assert!(is_identifier("foo"));
+        assert!(is_identifier("$_1"));
+        assert!(is_identifier("名前"));
+        assert!(is_identifier("let"));
+        assert!(!is_identifier("class"));
+        assert!(!is_identifier("1a"));
+        assert!(!is_identifier("a-b"));
+        assert!(!is_identifier("\u{1C5}"));
+        assert!(!is_identifier(""));
+    }
+
+    #[test]
+    fn test_quoting() {
+        assert_eq!(Literal(&Terminal::String("a\"\x01\x7F\u{85}\u{2028}é\t".to_owned()))
+            .to_string(), r#""a\"\u0001\u007F\u0085\u2028é\t""#);
+        assert_eq!(Key("a b").to_string(), r#"["a b"]"#);
+    }
+}
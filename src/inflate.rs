@@ -0,0 +1,786 @@
+//! Decompression of gzip (RFC 1952) and zlib (RFC 1950) data, and the DEFLATE (RFC 1951)
+//! format inside them, so that compressed JSON can be read without any dependencies.
+//!
+//! Input is decompressed as it's read, keeping only the last 32 KiB of output around for the
+//! compressed data to refer back to.
+
+use std::cmp::min;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, Read};
+
+/// Something wrong with compressed input. These are returned inside [`io::Error`]s, and become the
+/// matching [`JsonError`](crate::JsonError) variants when parsing.
+#[derive(Debug)]
+pub enum InflateError {
+    /// The input ended in the middle of the compressed data.
+    Truncated,
+    /// The checksum stored with the data doesn't match what it decompressed to.
+    Checksum,
+    /// The input isn't valid compressed data.
+    Invalid(&'static str),
+}
+
+impl Display for InflateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            InflateError::Truncated => f.write_str("compressed data truncated"),
+            InflateError::Checksum => f.write_str("compressed data checksum mismatch"),
+            InflateError::Invalid(e) => f.write_str(e),
+        }
+    }
+}
+
+impl std::error::Error for InflateError {}
+
+impl From<InflateError> for io::Error {
+    fn from(e: InflateError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// How much output to keep for back references.
+const WINDOW_SIZE: usize = 32 * 1024;
+
+/// The base lengths and number of extra bits for length codes 257 to 285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// The base distances and number of extra bits for distance codes 0 to 29.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The order the code length code lengths come in, in a dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Reads the input a bit at a time, least significant bit first.
+struct Bits<R> {
+    input: R,
+    buf: u64,
+    count: u32,
+}
+
+impl<R: BufRead> Bits<R> {
+    /// Fill up the bit buffer with as many whole bytes as will fit, or as are left.
+    fn refill(&mut self) -> io::Result<()> {
+        while self.count <= 56 {
+            let available = match self.input.fill_buf() {
+                Ok(available) => available,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if available.is_empty() {
+                break;
+            }
+            let n = min(available.len(), (64 - self.count) as usize / 8);
+            for &byte in &available[.. n] {
+                self.buf |= u64::from(byte) << self.count;
+                self.count += 8;
+            }
+            self.input.consume(n);
+        }
+        Ok(())
+    }
+
+    fn need(&mut self, n: u32) -> io::Result<()> {
+        if self.count < n {
+            self.refill()?;
+            if self.count < n {
+                return Err(InflateError::Truncated.into());
+            }
+        }
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        self.need(n)?;
+        let value = (self.buf & ((1 << n) - 1)) as u32;
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        self.bits(8).map(|b| b as u8)
+    }
+
+    /// Skip to the next byte boundary.
+    fn align(&mut self) {
+        let skip = self.count % 8;
+        self.buf >>= skip;
+        self.count -= skip;
+    }
+
+    /// Whether all the input has been used up. Only meaningful on a byte boundary.
+    fn at_end(&mut self) -> io::Result<bool> {
+        self.refill()?;
+        Ok(self.count == 0)
+    }
+
+    /// Copy up to `n` bytes straight to `out`, on a byte boundary. Returns how many were copied.
+    fn copy(&mut self, n: usize, out: &mut Vec<u8>) -> io::Result<usize> {
+        let mut copied = 0;
+        while copied < n && self.count >= 8 {
+            out.push(self.byte()?);
+            copied += 1;
+        }
+        if copied < n {
+            let available = self.input.fill_buf()?;
+            let len = min(n - copied, available.len());
+            out.extend_from_slice(&available[.. len]);
+            self.input.consume(len);
+            copied += len;
+        }
+        if copied == 0 && n != 0 {
+            return Err(InflateError::Truncated.into());
+        }
+        Ok(copied)
+    }
+}
+
+/// How many bits of code are looked up at once; longer codes are decoded a bit at a time.
+const FAST_BITS: u32 = 10;
+
+/// A canonical Huffman code.
+struct Huffman {
+    /// Indexed by the next `FAST_BITS` bits of input: the symbol shifted left by 4, plus the
+    /// length of its code, or 0 if the code is longer than that.
+    fast: Vec<u16>,
+    /// The number of codes of each length.
+    counts: [u16; 16],
+    /// The symbols, in order of their codes.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Make the code from the length of each symbol's code, where 0 means it's not used.
+    fn new(lengths: &[u8]) -> Result<Self, InflateError> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[usize::from(len)] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1i32;
+        for &count in &counts[1 ..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(InflateError::Invalid("invalid Huffman code lengths"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        let mut next_code = [0u32; 16];
+        let mut code = 0;
+        for len in 1 .. 16 {
+            offsets[len] = offsets[len - 1] + counts[len - 1];
+            code = (code + u32::from(counts[len - 1])) << 1;
+            next_code[len] = code;
+        }
+
+        let mut symbols = vec![0; usize::from(offsets[15] + counts[15])];
+        let mut fast = vec![0; 1 << FAST_BITS];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len == 0 {
+                continue;
+            }
+            let len = usize::from(len);
+            symbols[usize::from(offsets[len])] = symbol as u16;
+            offsets[len] += 1;
+
+            let code = next_code[len];
+            next_code[len] += 1;
+            if len as u32 <= FAST_BITS {
+                // The input comes least significant bit first, so look codes up backwards.
+                let reversed = code.reverse_bits() >> (32 - len);
+                let entry = (symbol as u16) << 4 | len as u16;
+                for index in (reversed as usize .. fast.len()).step_by(1 << len) {
+                    fast[index] = entry;
+                }
+            }
+        }
+        Ok(Huffman { fast, counts, symbols })
+    }
+
+    fn decode<R: BufRead>(&self, bits: &mut Bits<R>) -> io::Result<u16> {
+        if bits.count < FAST_BITS {
+            bits.refill()?;
+        }
+        let entry = self.fast[(bits.buf & ((1 << FAST_BITS) - 1)) as usize];
+        if entry != 0 {
+            let len = u32::from(entry & 15);
+            bits.need(len)?;
+            bits.buf >>= len;
+            bits.count -= len;
+            return Ok(entry >> 4);
+        }
+
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+        for &count in &self.counts[1 ..] {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(InflateError::Invalid("invalid Huffman code").into())
+    }
+}
+
+enum Block {
+    /// Between blocks.
+    Header,
+    /// In an uncompressed block, with this many bytes left.
+    Stored(usize),
+    /// In a compressed block, with its literal/length and distance codes.
+    Huffman(Box<(Huffman, Huffman)>),
+    /// After the last block.
+    Done,
+}
+
+/// Decompresses raw DEFLATE data.
+pub(crate) struct Inflater<R> {
+    bits: Bits<R>,
+    block: Block,
+    last: bool,
+    /// Output, including up to `WINDOW_SIZE` bytes which have been read already.
+    out: Vec<u8>,
+    /// Where the output that hasn't been read yet starts.
+    pos: usize,
+}
+
+impl<R: BufRead> Inflater<R> {
+    pub(crate) fn new(input: R) -> Self {
+        Inflater {
+            bits: Bits { input, buf: 0, count: 0 },
+            block: Block::Header,
+            last: false,
+            out: vec![],
+            pos: 0,
+        }
+    }
+
+    /// Get ready for another DEFLATE stream, straight after this one in the input.
+    fn restart(&mut self) {
+        self.block = Block::Header;
+        self.last = false;
+        self.out.clear();
+        self.pos = 0;
+    }
+
+    /// Decompress some more output, up to the end of the current block.
+    fn step(&mut self) -> io::Result<()> {
+        if self.pos > 2 * WINDOW_SIZE {
+            self.out.drain(.. self.pos - WINDOW_SIZE);
+            self.pos = WINDOW_SIZE;
+        }
+        match &mut self.block {
+            Block::Header if self.last => self.block = Block::Done,
+            Block::Header => {
+                self.last = self.bits.bits(1)? == 1;
+                self.block = match self.bits.bits(2)? {
+                    0 => {
+                        self.bits.align();
+                        let len = self.bits.bits(16)?;
+                        let nlen = self.bits.bits(16)?;
+                        if len != !nlen & 0xFFFF {
+                            return Err(InflateError::Invalid("invalid stored block length").into());
+                        }
+                        Block::Stored(len as usize)
+                    }
+                    1 => Block::Huffman(Box::new(fixed_codes())),
+                    2 => Block::Huffman(Box::new(self.dynamic_codes()?)),
+                    _ => return Err(InflateError::Invalid("invalid block type").into()),
+                };
+            }
+            Block::Stored(left) => {
+                *left -= self.bits.copy(*left, &mut self.out)?;
+                if *left == 0 {
+                    self.block = Block::Header;
+                }
+            }
+            Block::Huffman(codes) => {
+                let (lengths, distances) = &**codes;
+                let mut end = false;
+                while !end && self.out.len() - self.pos < WINDOW_SIZE {
+                    let symbol = lengths.decode(&mut self.bits)?;
+                    if symbol < 256 {
+                        self.out.push(symbol as u8);
+                        continue;
+                    }
+                    if symbol == 256 {
+                        end = true;
+                        continue;
+                    }
+                    let i = usize::from(symbol - 257);
+                    if i >= LENGTH_BASE.len() {
+                        return Err(InflateError::Invalid("invalid length code").into());
+                    }
+                    let len = usize::from(LENGTH_BASE[i])
+                        + self.bits.bits(u32::from(LENGTH_EXTRA[i]))? as usize;
+                    let i = usize::from(distances.decode(&mut self.bits)?);
+                    if i >= DISTANCE_BASE.len() {
+                        return Err(InflateError::Invalid("invalid distance code").into());
+                    }
+                    let distance = usize::from(DISTANCE_BASE[i])
+                        + self.bits.bits(u32::from(DISTANCE_EXTRA[i]))? as usize;
+                    if distance > self.out.len() {
+                        return Err(InflateError::Invalid("distance too far back").into());
+                    }
+                    // The copy can overlap the bytes it's writing, so go one at a time.
+                    let from = self.out.len() - distance;
+                    for i in from .. from + len {
+                        self.out.push(self.out[i]);
+                    }
+                }
+                if end {
+                    self.block = Block::Header;
+                }
+            }
+            Block::Done => (),
+        }
+        Ok(())
+    }
+
+    fn dynamic_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let nlen = self.bits.bits(5)? as usize + 257;
+        let ndist = self.bits.bits(5)? as usize + 1;
+        let ncode = self.bits.bits(4)? as usize + 4;
+        if nlen > 286 || ndist > 30 {
+            return Err(InflateError::Invalid("too many length or distance codes").into());
+        }
+
+        let mut lengths = [0u8; 19];
+        for &i in &CODE_LENGTH_ORDER[.. ncode] {
+            lengths[i] = self.bits.bits(3)? as u8;
+        }
+        let code = Huffman::new(&lengths)?;
+
+        let mut lengths = vec![];
+        while lengths.len() < nlen + ndist {
+            let symbol = code.decode(&mut self.bits)?;
+            let (len, repeat) = match symbol {
+                0 ..= 15 => (symbol as u8, 1),
+                16 => match lengths.last() {
+                    Some(&len) => (len, 3 + self.bits.bits(2)?),
+                    None => return Err(InflateError::Invalid("repeated length with no first length").into()),
+                },
+                17 => (0, 3 + self.bits.bits(3)?),
+                _ => (0, 11 + self.bits.bits(7)?),
+            };
+            if lengths.len() + repeat as usize > nlen + ndist {
+                return Err(InflateError::Invalid("too many code lengths").into());
+            }
+            lengths.extend((0 .. repeat).map(|_| len));
+        }
+        if lengths[256] == 0 {
+            return Err(InflateError::Invalid("no end of block code").into());
+        }
+        Ok((Huffman::new(&lengths[.. nlen])?, Huffman::new(&lengths[nlen ..])?))
+    }
+}
+
+/// The codes used by blocks with fixed Huffman codes.
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[.. 144].fill(8);
+    lengths[144 .. 256].fill(9);
+    lengths[256 .. 280].fill(7);
+    lengths[280 ..].fill(8);
+    let lengths = Huffman::new(&lengths).expect("fixed codes are valid");
+    let distances = Huffman::new(&[5; 30]).expect("fixed codes are valid");
+    (lengths, distances)
+}
+
+impl<R: BufRead> Read for Inflater<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_buffered(self, buf)
+    }
+}
+
+impl<R: BufRead> BufRead for Inflater<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.out.len() && !matches!(self.block, Block::Done) {
+            self.step()?;
+        }
+        Ok(&self.out[self.pos ..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+/// Read from something which has its own buffer, without copying into another one first.
+fn read_buffered(input: &mut impl BufRead, buf: &mut [u8]) -> io::Result<usize> {
+    let available = input.fill_buf()?;
+    let n = min(buf.len(), available.len());
+    buf[.. n].copy_from_slice(&available[.. n]);
+    input.consume(n);
+    Ok(n)
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { 0xEDB8_8320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// Continue the CRC-32 of some data, as used by gzip and zip, with some more of it.
+pub(crate) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = CRC_TABLE[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Continue the Adler-32 checksum of some data, as used by zlib, with some more of it.
+fn adler32(adler: u32, data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let mut a = adler & 0xFFFF;
+    let mut b = adler >> 16;
+    // The most bytes that can be added up before the sums could overflow.
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    b << 16 | a
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Gzip,
+    Zlib,
+}
+
+impl Format {
+    fn detect(head: &[u8]) -> Option<Format> {
+        match head {
+            [0x1F, 0x8B, ..] => Some(Format::Gzip),
+            // The compression method is DEFLATE, and the header checksum is right. Text like
+            // `x = 1` passes that too, but only with the preset dictionary flag set, so then the
+            // dictionary's checksum has to follow, which text is very unlikely to look like. (It's
+            // still recognised, so that it's reported as unsupported.)
+            [cmf, flg, rest @ ..] if cmf & 0x0F == 8 && cmf >> 4 <= 7
+                && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0
+                && (flg & 0x20 == 0 || rest.len() >= 4 && !rest[.. 4].iter().all(|&c| text(c))) => {
+                Some(Format::Zlib)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Whether a byte could be part of a text file.
+fn text(c: u8) -> bool {
+    c.is_ascii_graphic() || c.is_ascii_whitespace() || c >= 0x80
+}
+
+/// Whether some data looks like the start of a gzip or zlib stream. Neither can be mistaken for
+/// the start of a JSON document.
+pub(crate) fn is_compressed(head: &[u8]) -> bool {
+    Format::detect(head).is_some()
+}
+
+/// Decompresses gzip or zlib data, checking its checksum at the end.
+struct Decoder<R> {
+    inflater: Inflater<R>,
+    format: Format,
+    crc: u32,
+    size: u32,
+    done: bool,
+}
+
+impl<R: BufRead> Decoder<R> {
+    fn new(input: R, format: Format) -> io::Result<Self> {
+        let mut decoder = Decoder {
+            inflater: Inflater::new(input),
+            format,
+            crc: 0,
+            size: 0,
+            done: false,
+        };
+        decoder.header()?;
+        Ok(decoder)
+    }
+
+    fn header(&mut self) -> io::Result<()> {
+        let bits = &mut self.inflater.bits;
+        match self.format {
+            Format::Gzip => {
+                if bits.byte()? != 0x1F || bits.byte()? != 0x8B {
+                    return Err(InflateError::Invalid("trailing garbage after gzip data").into());
+                }
+                if bits.byte()? != 8 {
+                    return Err(InflateError::Invalid("unknown gzip compression method").into());
+                }
+                let flags = bits.byte()?;
+                if flags & 0xE0 != 0 {
+                    return Err(InflateError::Invalid("reserved gzip flags set").into());
+                }
+                // modification time, extra flags, and operating system
+                for _ in 0 .. 6 {
+                    bits.byte()?;
+                }
+                if flags & 4 != 0 {
+                    let len = bits.bits(16)?;
+                    for _ in 0 .. len {
+                        bits.byte()?;
+                    }
+                }
+                // file name, then comment
+                for flag in [8, 16] {
+                    if flags & flag != 0 {
+                        while bits.byte()? != 0 {}
+                    }
+                }
+                if flags & 2 != 0 {
+                    bits.bits(16)?; // header CRC
+                }
+                self.crc = 0;
+                self.size = 0;
+            }
+            Format::Zlib => {
+                bits.byte()?;
+                if bits.byte()? & 0x20 != 0 {
+                    return Err(InflateError::Invalid("zlib preset dictionaries aren't supported")
+                        .into());
+                }
+                self.crc = 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the trailer after the compressed data. Returns whether there's another gzip member
+    /// after it.
+    fn trailer(&mut self) -> io::Result<bool> {
+        let bits = &mut self.inflater.bits;
+        bits.align();
+        match self.format {
+            Format::Gzip => {
+                let crc = bits.bits(32)?;
+                let size = bits.bits(32)?;
+                if crc != self.crc || size != self.size {
+                    return Err(InflateError::Checksum.into());
+                }
+                Ok(!bits.at_end()?)
+            }
+            Format::Zlib => {
+                let mut adler = 0;
+                for _ in 0 .. 4 {
+                    adler = adler << 8 | u32::from(bits.byte()?);
+                }
+                if adler != self.crc {
+                    return Err(InflateError::Checksum.into());
+                }
+                Ok(false)
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_buffered(self, buf)
+    }
+}
+
+impl<R: BufRead> BufRead for Decoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while !self.done && self.inflater.fill_buf()?.is_empty() {
+            if self.trailer()? {
+                // Concatenated gzip files decompress to the concatenation of their contents.
+                self.inflater.restart();
+                self.header()?;
+            } else {
+                self.done = true;
+            }
+        }
+        self.inflater.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let data = &self.inflater.out[self.inflater.pos ..][.. amt];
+        match self.format {
+            Format::Gzip => {
+                self.crc = crc32(self.crc, data);
+                self.size = self.size.wrapping_add(amt as u32);
+            }
+            Format::Zlib => self.crc = adler32(self.crc, data),
+        }
+        self.inflater.consume(amt);
+    }
+}
+
+enum Inner<R> {
+    Plain(R),
+    Compressed(Box<Decoder<R>>),
+}
+
+/// Input which is decompressed as it's read, if it turned out to be compressed.
+pub struct Decompress<R> {
+    inner: Inner<R>,
+}
+
+impl<R> Decompress<R> {
+    /// Whether the input is being decompressed.
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.inner, Inner::Compressed(_))
+    }
+}
+
+impl<R: BufRead> Read for Decompress<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Inner::Plain(input) => input.read(buf),
+            Inner::Compressed(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+impl<R: BufRead> BufRead for Decompress<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match &mut self.inner {
+            Inner::Plain(input) => input.fill_buf(),
+            Inner::Compressed(decoder) => decoder.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match &mut self.inner {
+            Inner::Plain(input) => input.consume(amt),
+            Inner::Compressed(decoder) => decoder.consume(amt),
+        }
+    }
+}
+
+/// Read from `input`, decompressing it first if it starts like gzip or zlib data, which is
+/// detected by peeking at its first bytes.
+pub fn decompress<R: BufRead>(mut input: R) -> io::Result<Decompress<R>> {
+    let format = loop {
+        match input.fill_buf() {
+            Ok(head) => break Format::detect(head),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    let inner = match format {
+        Some(format) => Inner::Compressed(Box::new(Decoder::new(input, format)?)),
+        None => Inner::Plain(input),
+    };
+    Ok(Decompress { inner })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEXT: &[u8] = br#"{"a": [1, 2], "b": "hello hello hello"}"#;
+
+    // gzip.compress(TEXT, mtime=0), using fixed codes
+    const GZIP: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xab\x56\x4a\x54\xb2\x52\x88\x36\
+        \xd4\x51\x30\x8a\xd5\x51\x50\x4a\x02\x72\x94\x32\x52\x73\x72\xf2\x15\x90\x48\xa5\x5a\x00\
+        \x12\x8b\x7d\x86\x27\x00\x00\x00";
+
+    // zlib.compress(TEXT, 0), with a stored block
+    const STORED: &[u8] = b"\x78\x01\x01\x27\x00\xd8\xff\x7b\x22\x61\x22\x3a\x20\x5b\x31\x2c\x20\
+        \x32\x5d\x2c\x20\x22\x62\x22\x3a\x20\x22\x68\x65\x6c\x6c\x6f\x20\x68\x65\x6c\x6c\x6f\x20\
+        \x68\x65\x6c\x6c\x6f\x22\x7d\xcb\x3e\x0b\x6b";
+
+    // zlib.compress(b'[{"id": 0}, {"id": 37}, ...]', 9), using dynamic codes
+    const DYNAMIC: &[u8] = b"\x78\xda\x4d\x8e\x31\x0e\x80\x30\x0c\xc4\xbe\x52\x75\x66\x68\x92\x3b\
+        \x9a\xf2\x15\xc4\xc6\xc2\x1b\x50\xff\xce\x82\xd4\xdb\xec\xc9\x3e\xdf\xfa\xdc\xf5\x28\x6d\
+        \x6e\xe5\xc7\xe8\x8b\x3b\x16\x9b\x99\x08\x52\x24\xb9\xc4\xdd\x45\x38\x44\xc6\x2e\x95\x08\
+        \x4d\x4a\x1f\x4d\x06\x00\x39\x40\xca\x01\x4d\x0e\x48\xce\xeb\x03\x09\xc7\x34\x4b";
+
+    fn read_all(input: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = vec![];
+        decompress(input)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn error(input: &[u8]) -> InflateError {
+        let e = read_all(input).unwrap_err();
+        e.downcast().unwrap()
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(read_all(TEXT).unwrap(), TEXT);
+        assert_eq!(read_all(GZIP).unwrap(), TEXT);
+        assert_eq!(read_all(STORED).unwrap(), TEXT);
+        assert_eq!(read_all(&[GZIP, GZIP].concat()).unwrap(), [TEXT, TEXT].concat());
+
+        let expected = (0 .. 16).map(|i| format!("{{\"id\": {}}}", i * 37))
+            .collect::<Vec<_>>()
+            .join(", ");
+        assert_eq!(read_all(DYNAMIC).unwrap(), format!("[{expected}]").as_bytes());
+        assert!(!is_compressed(b"x = 1\n"));
+        assert!(!is_compressed(b"x "));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(error(&GZIP[.. 30]), InflateError::Truncated));
+        assert!(matches!(error(&GZIP[.. GZIP.len() - 1]), InflateError::Truncated));
+        assert!(matches!(error(&DYNAMIC[.. 40]), InflateError::Truncated));
+
+        let mut bad = GZIP.to_vec();
+        bad[GZIP.len() - 8] ^= 1;
+        assert!(matches!(error(&bad), InflateError::Checksum));
+        let mut bad = STORED.to_vec();
+        bad[10] = b'x';
+        assert!(matches!(error(&bad), InflateError::Checksum));
+        assert!(matches!(error(&[GZIP, b"junk"].concat()), InflateError::Invalid(_)));
+        // A preset dictionary, with its checksum.
+        assert!(matches!(error(b"\x78\xbb\x01\x02\x03\x04\x03\x00"),
+                         InflateError::Invalid("zlib preset dictionaries aren't supported")));
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut out = vec![];
+        crate::parse_compressed(GZIP, &mut out, crate::Options::default()).unwrap();
+        assert_eq!(out, b".a[0] = 1\n.a[1] = 2\n.b = \"hello hello hello\"\n\n");
+
+        let e = crate::parse_compressed(&GZIP[.. 40], vec![], crate::Options::default())
+            .unwrap_err();
+        assert!(matches!(e.error, crate::JsonError::CompressedTruncated));
+        let e = crate::parse_compressed(&GZIP[.. 20], vec![], crate::Options::default())
+            .unwrap_err();
+        assert!(matches!(e.error, crate::JsonError::CompressedTruncated));
+    }
+}
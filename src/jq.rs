@@ -0,0 +1,162 @@
+//! Output styles compatible with jq.
+
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+
+use crate::{write_jq_quoted, Event, Sink, Terminal, Value};
+
+/// Words which can't be used bare after a `.` in older versions of jq.
+const KEYWORDS: &[&str] = &[
+    "__loc__", "and", "as", "catch", "def", "elif", "else", "end", "foreach", "if", "import",
+    "include", "label", "or", "reduce", "then", "try",
+];
+
+/// Displays an object key as a step in a jq path: `.foo` if it's an identifier, otherwise
+/// `["quoted"]`, with a leading `.` if it's the first step in the path.
+pub struct Key<'a> {
+    pub key: &'a str,
+    pub first: bool,
+}
+
+impl Display for Key<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = self.key;
+        let ident = s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !KEYWORDS.contains(&s);
+        if ident {
+            write!(f, ".{s}")
+        } else {
+            if self.first {
+                f.write_str(".")?;
+            }
+            f.write_str("[")?;
+            write_jq_quoted(s, f)?;
+            f.write_str("]")
+        }
+    }
+}
+
+struct JqString<'a>(&'a str);
+
+impl Display for JqString<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write_jq_quoted(self.0, f)
+    }
+}
+
+enum Frame {
+    List { len: u64 },
+    Object { key: Option<String> }, // the most recent key
+}
+
+/// Writes the same output as `jq -c --stream`. This works from the parser's events rather than
+/// its leaf values, because jq also reports top-level scalars, empty lists within lists, and the
+/// closing of every non-empty list and object.
+pub struct StreamWriter<W> {
+    output: W,
+    prefix: String, // written at the start of every line
+    frames: Vec<Frame>,
+}
+
+impl<W: Write> StreamWriter<W> {
+    pub fn new(output: W, prefix: String) -> Self {
+        StreamWriter { output, prefix, frames: vec![] }
+    }
+
+    /// Write the path to the current position, with the innermost list or object's position
+    /// replaced by the last child's, if `last` is set.
+    fn write_path(&mut self, last: bool) -> io::Result<()> {
+        self.output.write_all(b"[")?;
+        let n = self.frames.len();
+        for (i, frame) in self.frames.iter().enumerate() {
+            if i != 0 {
+                self.output.write_all(b",")?;
+            }
+            match frame {
+                Frame::List { len } if last && i == n - 1 => write!(self.output, "{}", len - 1)?,
+                Frame::List { len } => write!(self.output, "{len}")?,
+                Frame::Object { key } => {
+                    let key = key.as_deref().expect("no key for object value");
+                    write!(self.output, "{}", JqString(key))?;
+                }
+            }
+        }
+        self.output.write_all(b"]")
+    }
+
+    fn write_leaf(&mut self, value: &Terminal) -> io::Result<()> {
+        self.output.write_all(self.prefix.as_bytes())?;
+        self.output.write_all(b"[")?;
+        self.write_path(false)?;
+        match value {
+            Terminal::String(s) => write!(self.output, ",{}]", JqString(s))?,
+            other => write!(self.output, ",{other}]")?,
+        }
+        self.output.write_all(b"\n")
+    }
+
+    /// A value in the current list or object is finished.
+    fn advance(&mut self) {
+        if let Some(Frame::List { len }) = self.frames.last_mut() {
+            *len += 1;
+        }
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        let empty = match self.frames.last() {
+            Some(Frame::List { len }) => *len == 0,
+            Some(Frame::Object { key }) => key.is_none(),
+            None => panic!("end of container with nothing open"),
+        };
+        if empty {
+            let frame = self.frames.pop().unwrap();
+            let value = match frame {
+                Frame::List { .. } => Terminal::EmptyList,
+                Frame::Object { .. } => Terminal::EmptyObject,
+            };
+            self.write_leaf(&value)?;
+        } else {
+            self.output.write_all(self.prefix.as_bytes())?;
+            self.output.write_all(b"[")?;
+            self.write_path(true)?;
+            self.output.write_all(b"]\n")?;
+            self.frames.pop();
+        }
+        self.advance();
+        Ok(())
+    }
+}
+
+impl<W: Write> Sink for StreamWriter<W> {
+    fn leaf(&mut self, _ds: &[Value], _value: Terminal) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn next_document(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    const EVENTS: bool = true;
+
+    fn event(&mut self, event: Event) -> io::Result<()> {
+        match event {
+            Event::StartObject => self.frames.push(Frame::Object { key: None }),
+            Event::StartArray => self.frames.push(Frame::List { len: 0 }),
+            Event::Key(k) => match self.frames.last_mut() {
+                Some(Frame::Object { key }) => *key = Some(k),
+                _ => panic!("key outside of an object"),
+            },
+            Event::Scalar(value) => {
+                self.write_leaf(&value)?;
+                self.advance();
+            }
+            Event::EndObject | Event::EndArray => self.close()?,
+        }
+        Ok(())
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
@@ -0,0 +1,77 @@
+//! Output style which is valid JavaScript.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::unicode::{is_id_continue, is_id_start};
+use crate::{write_js_quoted, Terminal};
+
+/// Reserved words, including future and strict-mode ones. ES5 and later allow these after a `.`,
+/// but older engines don't, so they get quoted anyway.
+const RESERVED: &[&str] = &[
+    "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default",
+    "delete", "do", "else", "enum", "export", "extends", "false", "finally", "for", "function",
+    "if", "implements", "import", "in", "instanceof", "interface", "let", "new", "null", "package",
+    "private", "protected", "public", "return", "static", "super", "switch", "this", "throw",
+    "true", "try", "typeof", "var", "void", "while", "with", "yield",
+];
+
+/// Whether the string is a JavaScript IdentifierName which isn't a reserved word.
+pub fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    let Some(first) = chars.next() else {
+        return false;
+    };
+    (first == '$' || first == '_' || is_id_start(first))
+        && chars.all(|c| c == '$' || c == '\u{200C}' || c == '\u{200D}' || is_id_continue(c))
+        && !RESERVED.contains(&s)
+}
+
+/// Displays an object key as a JavaScript property access: `.foo` if it's an identifier,
+/// otherwise `["quoted"]`.
+pub struct Key<'a>(pub &'a str);
+
+impl Display for Key<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if is_identifier(self.0) {
+            write!(f, ".{}", self.0)
+        } else {
+            f.write_str("[")?;
+            write_js_quoted(self.0, f)?;
+            f.write_str("]")
+        }
+    }
+}
+
+/// Displays a value as a JavaScript literal.
+pub struct Literal<'a>(pub &'a Terminal);
+
+impl Display for Literal<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            // There's no way to write these as they are in JavaScript.
+            Terminal::String(s) | Terminal::Typed(s) => write_js_quoted(s, f),
+            other => write!(f, "{other}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_identifiers() {
+        assert!(is_identifier("foo"));
+        assert!(is_identifier("$_1"));
+        assert!(is_identifier("ünïcödé"));
+        assert!(is_identifier("名前"));
+        assert!(is_identifier("a\u{0301}")); // combining mark
+        assert!(!is_identifier("123abc"));
+        assert!(!is_identifier("\u{0301}a"));
+        assert!(!is_identifier("x y"));
+        assert!(!is_identifier(""));
+        assert!(!is_identifier("class"));
+        assert!(!is_identifier("a-b"));
+        assert!(!is_identifier("🦀"));
+    }
+}
@@ -0,0 +1,2255 @@
+#![deny(rust_2018_idioms)]
+
+//! catj, rust streaming parser edition
+//!
+//! Parses JSON in a streaming fashion, and displays it in a flat format.
+//!
+//! https://github.com/wfraser/catj-rss
+//!
+//! Copyright 2019-2023 William R. Fraser
+
+use std::char;
+use std::cmp::min;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, Read, Seek, Write};
+use std::str::{self, Utf8Error};
+
+mod tables;
+use tables::{Grammar, STRICT, LENIENT, LIST_END, OBJECT_END, STRING, INTEGER, FRACTION, EXPONENT};
+use tables::{CATEGORY_NAMES, STATE_CONTEXTS, COMMENT_START, PUSH_STATE};
+use tables::{NULL, FALSE_END, LIST_START, LIST_ELEMENT, LIST_NEXT, OBJECT_START, OBJECT_KEY,
+             OBJECT_COLON, OBJECT_VALUE, OBJECT_NEXT, OBJECT_NEXT_KEY, OBJECT_NEXT_COLON, ESCAPE,
+             UNICODE_ESCAPE, UNICODE_ESCAPE_END, FRACTION_START, EXPONENT_START, EXPONENT_SIGN,
+             MINUS};
+use tables::{SQ_STRING, SQ_ESCAPE, DQ_CONTINUATION, SQ_CONTINUATION, HEX_ESCAPE, HEX_ESCAPE_END,
+             COMMENT_END, HEX_START, INFINITY, INFINITY_END, NAN_END, PLUS};
+
+mod bson;
+mod cbor;
+mod decode;
+pub mod filter;
+mod gron;
+pub mod inflate;
+mod jq;
+mod js;
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod mmap;
+mod msgpack;
+mod parallel;
+mod toml;
+pub mod ungron;
+mod unicode;
+mod yaml;
+pub mod zip;
+
+pub use filter::Filter;
+use inflate::InflateError;
+
+#[derive(Debug)]
+pub enum JsonError {
+    Truncated,
+    Syntax(String),
+    InvalidEscape(String),
+    Unicode(Utf8Error),
+    CompressedTruncated,
+    ChecksumMismatch,
+    InvalidCompression(String),
+    IO(io::Error),
+}
+
+impl From<io::Error> for JsonError {
+    fn from(e: io::Error) -> Self {
+        match e.downcast::<InflateError>() {
+            Ok(InflateError::Truncated) => JsonError::CompressedTruncated,
+            Ok(InflateError::Checksum) => JsonError::ChecksumMismatch,
+            Ok(InflateError::Invalid(e)) => JsonError::InvalidCompression(e.to_owned()),
+            Err(e) => JsonError::IO(e),
+        }
+    }
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::Truncated => f.write_str("input truncated"),
+            JsonError::Syntax(e) => f.write_str(e),
+            JsonError::InvalidEscape(e) => write!(f, "invalid string escape sequence: {e}"),
+            JsonError::Unicode(e) => write!(f, "invalid UTF-8: {e}"),
+            JsonError::CompressedTruncated => f.write_str("compressed data truncated"),
+            JsonError::ChecksumMismatch => f.write_str("compressed data checksum mismatch"),
+            JsonError::InvalidCompression(e) => write!(f, "invalid compressed data: {e}"),
+            JsonError::IO(e) => write!(f, "I/O error: {e}"),
+        }
+    }
+}
+
+/// A [`JsonError`] along with where in the input it happened.
+#[derive(Debug)]
+pub struct Error {
+    /// The line, starting from 1, or 0 for binary formats, which don't have lines.
+    pub line: u64,
+    /// The column, counted in characters rather than bytes, starting from 1.
+    pub col: u64,
+    /// How far into the input the error is, in bytes, starting from 0.
+    pub offset: u64,
+    /// The path of the value that was being parsed.
+    pub path: Path,
+    /// The input around the error, if it was still available.
+    pub snippet: Option<Snippet>,
+    pub error: JsonError,
+}
+
+impl Error {
+    /// Add a snippet of the input around the error, from `data`, which is the part of the input
+    /// starting `start` bytes in. Errors from reading the input don't get one.
+    fn with_snippet(mut self, data: &[u8], start: u64) -> Self {
+        let pos = self.offset.checked_sub(start)
+            .and_then(|pos| usize::try_from(pos).ok())
+            .filter(|&pos| pos <= data.len());
+        let syntax = matches!(self.error, JsonError::Truncated | JsonError::Syntax(_)
+            | JsonError::InvalidEscape(_) | JsonError::Unicode(_));
+        if let Some(pos) = pos.filter(|_| syntax) {
+            self.snippet = Some(Snippet::new(data, pos));
+        }
+        self
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "byte {}", self.offset)?;
+        } else {
+            write!(f, "line {} column {} (byte {})", self.line, self.col, self.offset)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, " in {}", self.path)?;
+        }
+        write!(f, ": {}", self.error)?;
+        if let Some(snippet) = &self.snippet {
+            write!(f, "\n    {}\n    {:>width$}", snippet.text, "^", width = snippet.caret + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// How many characters of input to show on either side of an error.
+const SNIPPET_CONTEXT: usize = 40;
+
+/// Part of the line of input where an error is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    /// The input, with control characters replaced by spaces.
+    pub text: String,
+    /// Where the error is in `text`, in characters.
+    pub caret: usize,
+}
+
+impl Snippet {
+    /// The snippet around the byte at `pos` in `data`, which may be just past the end of it.
+    fn new(data: &[u8], pos: usize) -> Self {
+        // Only look a limited distance either way, since lines can be gigabytes long. Each
+        // character is at most 4 bytes.
+        let mut start = pos.saturating_sub(SNIPPET_CONTEXT * 4);
+        let mut end = min(data.len(), pos + SNIPPET_CONTEXT * 4);
+        while start < pos && data[start] & 0xC0 == 0x80 {
+            start += 1;
+        }
+        while end > pos && end < data.len() && data[end] & 0xC0 == 0x80 {
+            end -= 1;
+        }
+        if let Some(i) = data[start .. pos].iter().rposition(|&c| c == b'\n') {
+            start += i + 1;
+        }
+        if let Some(i) = data[pos .. end].iter().position(|&c| c == b'\n' || c == b'\r') {
+            end = pos + i;
+        }
+
+        let printable = |c: char| if c.is_control() { ' ' } else { c };
+        let before = String::from_utf8_lossy(&data[start .. pos]);
+        let skip = before.chars().count().saturating_sub(SNIPPET_CONTEXT);
+        let mut text: String = before.chars().skip(skip).map(printable).collect();
+        let caret = text.chars().count();
+        text.extend(String::from_utf8_lossy(&data[pos .. end]).chars()
+            .take(SNIPPET_CONTEXT)
+            .map(printable));
+        Snippet { text, caret }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.error {
+            JsonError::Unicode(e) => Some(e),
+            JsonError::IO(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// An entry on the parser's data stack.
+#[derive(Debug)]
+pub enum Value {
+    Object { empty: bool }, // empty: whether we've seen any fields yet while parsing
+    List { index: u64 }, // index: the current size of the list while parsing
+    Terminal(Terminal),
+}
+
+/// Things we print a line for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Terminal {
+    Null,
+    Bool(bool),
+    Number(String),
+    String(String),
+    EmptyList,
+    EmptyObject,
+    /// A value of a type JSON doesn't have, written along with its type the way the MongoDB shell
+    /// would, like `ObjectId("5f0c...")`. It's written as it is, so it isn't valid JSON, except by
+    /// [`Style::Js`] and [`Style::Gron`], which write it as a string.
+    Typed(String),
+}
+
+impl Display for Terminal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminal::Null => f.write_str("null"),
+            Terminal::Bool(v) => write!(f, "{v:?}"),
+            Terminal::Number(s) => f.write_str(s),
+            Terminal::String(s) => write_quoted(s, f),
+            Terminal::EmptyList => f.write_str("[]"),
+            Terminal::EmptyObject => f.write_str("{}"),
+            Terminal::Typed(s) => f.write_str(s),
+        }
+    }
+}
+
+fn write_quoted(s: &str, f: &mut Formatter<'_>) -> fmt::Result {
+    write_quoted_impl(s, |_| false, f)
+}
+
+/// Quote a string exactly the way jq does, which is like JSON but also escapes DEL.
+fn write_jq_quoted(s: &str, f: &mut Formatter<'_>) -> fmt::Result {
+    write_quoted_impl(s, |c| c == '\x7F', f)
+}
+
+/// Quote a string for JavaScript, which is like JSON but also needs the line and paragraph
+/// separators escaped, for engines older than ES2019.
+fn write_js_quoted(s: &str, f: &mut Formatter<'_>) -> fmt::Result {
+    write_quoted_impl(s, |c| c == '\u{2028}' || c == '\u{2029}', f)
+}
+
+/// Write a string as a JSON string literal. Characters which `escape` returns true for are written
+/// as `\u` escapes in addition to the ones JSON requires.
+fn write_quoted_impl(s: &str, escape: fn(char) -> bool, f: &mut Formatter<'_>) -> fmt::Result {
+    f.write_str("\"")?;
+    let mut tmp = [0u8; 4];
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\x08' => f.write_str("\\b")?,
+            '\t' => f.write_str("\\t")?,
+            '\x0C' => f.write_str("\\f")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            c if (c as u32) < 0x20 || escape(c) => {
+                write!(f, "\\u{:04x}", c as u32)?;
+            }
+            // to emit astral plane characters as escaped surrogate pairs:
+            /*c if (c as u32) > 0xFFFF => {
+                let mut pair = [0u16; 2];
+                c.encode_utf16(&mut pair);
+                write!(f, "\\u{:04x}\\u{:04x}", pair[0], pair[1])?;
+            }*/
+            c => f.write_str(c.encode_utf8(&mut tmp))?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl From<Terminal> for Value {
+    fn from(t: Terminal) -> Self {
+        Value::Terminal(t)
+    }
+}
+
+/// One step of a path: `.key` or `[index]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Segment {
+    Key(String),
+    Index(u64),
+}
+
+/// Displays an object key the way catj prints it: bare if it's a simple identifier, otherwise
+/// quoted and escaped like a string value.
+struct Key<'a>(&'a str);
+
+impl Display for Key<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let s = self.0;
+        if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            // Write it as a bare string
+            f.write_str(s)
+        } else {
+            // Write it with quotes, escapes, etc. as if it were a value
+            write_quoted(s, f)
+        }
+    }
+}
+
+/// Displays an object key as a JSON Pointer (RFC 6901) reference token, escaping `~` as `~0` and
+/// `/` as `~1`.
+struct PointerToken<'a>(&'a str);
+
+impl Display for PointerToken<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut rest = self.0;
+        while let Some(i) = rest.find(['~', '/']) {
+            f.write_str(&rest[.. i])?;
+            f.write_str(if rest.as_bytes()[i] == b'~' { "~0" } else { "~1" })?;
+            rest = &rest[i + 1 ..];
+        }
+        f.write_str(rest)
+    }
+}
+
+/// The location of a value within a document.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Path(pub Vec<Segment>);
+
+impl Path {
+    fn from_stack(ds: &[Value]) -> Self {
+        Path(ds.iter()
+            .filter_map(|item| match item {
+                Value::Object { .. } => None,
+                Value::List { index } => Some(Segment::Index(*index)),
+                Value::Terminal(Terminal::String(s)) => Some(Segment::Key(s.clone())),
+                Value::Terminal(other) => panic!("invalid item in a path: {:?}", other),
+            })
+            .collect())
+    }
+
+    /// The path of whatever the parser is in the middle of, from a data stack which might also
+    /// have a finished value on top.
+    fn partial(ds: &[Value]) -> Self {
+        Path(ds.iter()
+            .enumerate()
+            .filter_map(|(i, item)| match item {
+                Value::List { index } => Some(Segment::Index(*index)),
+                Value::Terminal(Terminal::String(s))
+                    if i > 0 && matches!(ds[i - 1], Value::Object { .. }) =>
+                {
+                    Some(Segment::Key(s.clone()))
+                }
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+impl Path {
+    /// Render the path as a JSON Pointer (RFC 6901), like `/a/b c/0`.
+    pub fn pointer(&self) -> String {
+        let mut s = String::new();
+        for seg in &self.0 {
+            match seg {
+                Segment::Key(k) => s += &format!("/{}", PointerToken(k)),
+                Segment::Index(i) => s += &format!("/{i}"),
+            }
+        }
+        s
+    }
+}
+
+impl std::ops::Deref for Path {
+    type Target = [Segment];
+    fn deref(&self) -> &[Segment] {
+        &self.0
+    }
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for seg in &self.0 {
+            match seg {
+                Segment::Key(k) => write!(f, ".{}", Key(k))?,
+                Segment::Index(i) => write!(f, "[{i}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Receives the values found by the parser, as it finds them.
+trait Sink {
+    /// A leaf value was parsed. The data stack holds the path to it.
+    fn leaf(&mut self, ds: &[Value], value: Terminal) -> io::Result<()>;
+
+    /// Another top-level value is starting, after the previous one.
+    fn next_document(&mut self) -> io::Result<()>;
+
+    /// The next top-level value is record number `n` of the input, counting from 1. Records are
+    /// told apart by their numbers rather than by blank lines.
+    fn record(&mut self, _n: u64) {}
+
+    /// Something was pushed onto the data stack: a list, an object, a key, or a value.
+    fn pushed(&mut self, _ds: &[Value]) {}
+
+    /// The data stack was popped down to the given length.
+    fn popped(&mut self, _len: usize) {}
+
+    /// The list on top of the data stack moved on to its next index.
+    fn next_index(&mut self, _ds: &[Value]) {}
+
+    /// A list or object was started. It's on top of the data stack.
+    fn start_container(&mut self, _ds: &[Value]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// A top-level value which isn't a list or object was parsed.
+    fn top_level(&mut self, _value: Terminal) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether to call `value_start` and `event`. Sinks which don't need them leave this off, so
+    /// the events don't need to be built.
+    const EVENTS: bool = false;
+
+    /// The parser saw the first byte of a value or key (or of a `\u` escape inside a string).
+    fn value_start(&mut self) {}
+
+    fn event(&mut self, _event: Event) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Whether the sink doesn't want any more values, so the rest of the input can be skipped.
+    fn done(&self) -> bool {
+        false
+    }
+
+    /// No more values are coming, because the input ended or the sink is done.
+    fn end(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes catj's flat output format.
+struct FlatWriter<W> {
+    output: W,
+    options: Options,
+    prefix: Vec<u8>, // written at the start of every line
+    separate: bool,   // whether to write a blank line between top-level values
+    sorted: Vec<gron::Statement>, // the current document's lines, if they're being sorted
+    owed: bool,       // whether a blank line is due before the next line, for Style::Gron
+    path: Vec<u8>,    // the rendered path of the data stack, kept up to date as it changes
+    marks: Vec<usize>, // where each item of the data stack starts in `path`
+}
+
+impl<W: Write> FlatWriter<W> {
+    fn new(output: W, options: Options) -> Self {
+        let prefix = options.prefix.clone().into_bytes();
+        FlatWriter { output, options, prefix, separate: true, sorted: vec![],
+                     owed: false, path: vec![], marks: vec![] }
+    }
+}
+
+impl<W: Write> FlatWriter<W> {
+    fn write_line(&mut self, ds: &[Value], value: &Terminal) -> io::Result<()> {
+        if !self.options.filter.is_empty() && !self.options.filter.check(ds) {
+            return Ok(());
+        }
+        // The rendered path may go further than the given stack, into the value itself.
+        let end = self.marks.get(ds.len()).copied().unwrap_or(self.path.len());
+        if self.options.sort && self.options.style == Style::Gron {
+            let mut line = self.prefix.clone();
+            write_statement(&self.path[.. end], value, &self.options, &mut line)?;
+            self.sorted.push(gron::Statement::new(&self.options.root, ds, value, line));
+            return Ok(());
+        }
+        self.pay_blank_line()?;
+        self.output.write_all(&self.prefix)?;
+        write_statement(&self.path[.. end], value, &self.options, &mut self.output)
+    }
+
+    /// Render the item on top of the data stack onto the end of the path.
+    fn render_top(&mut self, ds: &[Value]) {
+        let i = ds.len() - 1;
+        let item = &ds[i];
+        let is_key = i > 0 && matches!(ds[i - 1], Value::Object { .. });
+        if is_key || !matches!(item, Value::Terminal(_)) {
+            print_segment(i, item, self.options.style, &mut self.path)
+                .expect("writing to a Vec can't fail");
+        }
+    }
+
+    fn flush_sorted(&mut self) -> io::Result<()> {
+        if !self.sorted.is_empty() {
+            self.pay_blank_line()?;
+        }
+        gron::sort(&mut self.sorted);
+        for statement in self.sorted.drain(..) {
+            self.output.write_all(&statement.line)?;
+        }
+        Ok(())
+    }
+
+    fn pay_blank_line(&mut self) -> io::Result<()> {
+        if self.owed {
+            self.owed = false;
+            self.output.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_statement(path: &[u8], value: &Terminal, options: &Options, output: &mut impl Write)
+    -> io::Result<()>
+{
+    if options.style.statements() {
+        output.write_all(options.root.as_bytes())?;
+    }
+    output.write_all(path)?;
+    match options.style {
+        Style::Js => writeln!(output, " = {};", js::Literal(value)),
+        Style::Gron => writeln!(output, " = {};", gron::Literal(value)),
+        _ => writeln!(output, " = {value}"),
+    }
+}
+
+impl<W: Write> Sink for FlatWriter<W> {
+    fn pushed(&mut self, ds: &[Value]) {
+        self.marks.push(self.path.len());
+        self.render_top(ds);
+    }
+
+    fn popped(&mut self, len: usize) {
+        if let Some(&mark) = self.marks.get(len) {
+            self.path.truncate(mark);
+            self.marks.truncate(len);
+        }
+    }
+
+    fn next_index(&mut self, ds: &[Value]) {
+        self.path.truncate(self.marks[ds.len() - 1]);
+        self.render_top(ds);
+    }
+
+    fn leaf(&mut self, ds: &[Value], value: Terminal) -> io::Result<()> {
+        if self.options.style.statements()
+            && matches!(value, Terminal::EmptyList | Terminal::EmptyObject)
+        {
+            // Already initialized when it was started.
+            return Ok(());
+        }
+        self.write_line(ds, &value)
+    }
+
+    fn start_container(&mut self, ds: &[Value]) -> io::Result<()> {
+        if self.options.style.statements() {
+            let (container, path) = ds.split_last().unwrap();
+            let value = match container {
+                Value::List { .. } => Terminal::EmptyList,
+                _ => Terminal::EmptyObject,
+            };
+            self.write_line(path, &value)?;
+        }
+        Ok(())
+    }
+
+    fn top_level(&mut self, value: Terminal) -> io::Result<()> {
+        if self.options.style.statements() {
+            self.write_line(&[], &value)?;
+        }
+        Ok(())
+    }
+
+    fn next_document(&mut self) -> io::Result<()> {
+        self.flush_sorted()?;
+        if self.separate && self.options.style == Style::Gron {
+            // Like gron, don't end the output with a blank line.
+            self.owed = true;
+        } else if self.separate {
+            self.output.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, n: u64) {
+        self.separate = false;
+        self.prefix.clear();
+        write!(self.prefix, "{}[#{n}]", self.options.prefix).expect("writing to a Vec can't fail");
+    }
+
+    fn done(&self) -> bool {
+        self.options.filter.done()
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.flush_sorted()?;
+        self.output.flush()
+    }
+}
+
+/// How paths are written in the output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Style {
+    /// catj's own style, like `.a."b c"[0]`.
+    #[default]
+    Catj,
+    /// JSON Pointer (RFC 6901), like `/a/b c/0`.
+    Pointer,
+    /// Paths which can be pasted into jq filters, like `.a["b c"][0]`.
+    Jq,
+    /// The same output as `jq -c --stream`, like `[["a","b c",0],1]`, including the events jq
+    /// emits when a list or object is closed. Filters don't apply to this style, and
+    /// [`parse_lines`] uses [`Style::Jq`] instead.
+    JqStream,
+    /// JavaScript statements which can be `eval`'d to rebuild the input, like
+    /// `json.a["b c"][0] = 1;`, with the root identifier taken from [`Options::root`]. Lists and
+    /// objects are initialized before their contents, and top-level scalars are included.
+    Js,
+    /// The same output as gron, like `json.a["b c"][0] = 1;`. This is like [`Style::Js`], but
+    /// follows gron's rules for quoting keys and escaping strings. Unlike gron, lines are in the
+    /// order they appear in the input unless [`Options::sort`] is set.
+    Gron,
+}
+
+impl Style {
+    /// Whether lines are JavaScript statements, which start with [`Options::root`] and include
+    /// the lists and objects themselves.
+    fn statements(self) -> bool {
+        matches!(self, Style::Js | Style::Gron)
+    }
+}
+
+impl std::str::FromStr for Style {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "catj" => Ok(Style::Catj),
+            "pointer" => Ok(Style::Pointer),
+            "jq" => Ok(Style::Jq),
+            "jq-stream" => Ok(Style::JqStream),
+            "js" => Ok(Style::Js),
+            "gron" => Ok(Style::Gron),
+            _ => Err(format!("unknown output style {s:?}")),
+        }
+    }
+}
+
+/// What the input is written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// JSON, or JSON5 if [`Options::lenient`] is set. Input in another format which identifies
+    /// itself, like CBOR starting with the self-describe tag, is read as that format instead.
+    #[default]
+    Json,
+    /// CBOR (RFC 8949), as one item or a series of them.
+    Cbor,
+    /// MessagePack, as one value or a series of them.
+    MessagePack,
+    /// BSON documents, one after another, like `mongodump` writes. Each one is labelled with its
+    /// number, like `[#12]._id = ObjectId("...")`.
+    Bson,
+    /// A stream of YAML documents, each written like a top-level JSON value. Input which starts
+    /// with a `%YAML` directive or a `---` document marker is read as YAML anyway.
+    Yaml,
+    /// A TOML document, like `Cargo.toml`. Datetimes are written like
+    /// `OffsetDateTime("1979-05-27T07:32:00Z")`.
+    Toml,
+}
+
+impl Format {
+    /// The format the input is in, going by its first few bytes, if it says.
+    fn detect(head: &[u8]) -> Option<Format> {
+        if head.starts_with(cbor::MAGIC) {
+            Some(Format::Cbor)
+        } else if head.starts_with(b"%YAML") || head.strip_prefix(b"---")
+            .is_some_and(|rest| rest.first().is_none_or(|c| c.is_ascii_whitespace()))
+        {
+            Some(Format::Yaml)
+        } else {
+            None
+        }
+    }
+}
+
+/// Settings for how the flat output is written.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Only print lines whose paths pass this filter.
+    pub filter: Filter,
+    pub style: Style,
+    /// The variable name paths start with, for [`Style::Js`] and [`Style::Gron`].
+    pub root: String,
+    /// For [`Style::Gron`], sort each document's lines the way gron does. This means holding all
+    /// of a document's lines in memory.
+    pub sort: bool,
+    /// Written at the start of every line, like the file names `grep` puts before its matches.
+    pub prefix: String,
+    /// If the input is truncated, write what there was of the last document as if it had been
+    /// closed properly: an unfinished string is ended, an unfinished number or literal is left
+    /// out, and the open lists and objects are closed. For CBOR, MessagePack and BSON, an
+    /// unfinished value is left out. [`JsonError::Truncated`] is still returned, with the path
+    /// where the input was cut off.
+    pub repair: bool,
+    /// Accept [JSON5](https://json5.org/), which adds comments, trailing commas, unquoted keys,
+    /// single-quoted and multi-line strings, hex numbers, `Infinity` and `NaN` and so on to JSON.
+    /// Numbers are written the way JSON would have them, apart from `Infinity` and `NaN`.
+    pub lenient: bool,
+    pub format: Format,
+    /// Write binary data, like CBOR byte strings and MessagePack and BSON binary values, in hex
+    /// instead of base64.
+    pub hex: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            filter: Filter::default(),
+            style: Style::default(),
+            root: "json".to_owned(),
+            sort: false,
+            prefix: String::new(),
+            repair: false,
+            lenient: false,
+            format: Format::default(),
+            hex: false,
+        }
+    }
+}
+
+/// The parser's state in between input bytes.
+struct Parser {
+    grammar: &'static Grammar,
+    stack: Vec<u8>,
+    state: u8,
+    ds: Vec<Value>,     // data stack
+    ss: Vec<u8>,        // string stack
+    es: String,         // escape stack
+    line: u64,
+    col: u64,
+    offset: u64,
+}
+
+impl Parser {
+    fn new() -> Self {
+        Parser::with_grammar(&STRICT)
+    }
+
+    /// A parser for the grammar chosen by the options.
+    fn for_options(options: &Options) -> Self {
+        Parser::with_grammar(if options.lenient { &LENIENT } else { &STRICT })
+    }
+
+    fn with_grammar(grammar: &'static Grammar) -> Self {
+        Parser {
+            grammar,
+            stack: vec![],
+            state: 0,
+            ds: vec![],
+            ss: vec![],
+            es: String::new(),
+            line: 1,
+            col: 0,
+            offset: 0,
+        }
+    }
+
+    /// Throw away any partially-parsed input, and start over as if at the given line and byte
+    /// offset.
+    fn reset(&mut self, line: u64, offset: u64) {
+        self.stack.clear();
+        self.state = 0;
+        self.ds.clear();
+        self.ss.clear();
+        self.es.clear();
+        self.line = line;
+        self.col = 0;
+        self.offset = offset;
+    }
+
+    /// An error at the current position: the byte just parsed, or the end of the input.
+    fn error(&self, error: JsonError) -> Error {
+        Error {
+            line: self.line,
+            col: self.col,
+            offset: self.offset,
+            path: Path::partial(&self.ds),
+            snippet: None,
+            error,
+        }
+    }
+
+    fn byte<S: Sink>(&mut self, ch: u8, sink: &mut S) -> Result<(), Error> {
+        if ch == b'\n' {
+            self.line += 1;
+            self.col = 0;
+        } else if ch & 0xC0 != 0x80 {
+            // UTF-8 continuation bytes don't start a new character.
+            self.col += 1;
+        }
+        let cat = self.grammar.catcode[ch as usize];
+        self.state = parse_ch(self.grammar, cat, ch, &mut self.stack, self.state, &mut self.ds,
+                              &mut self.ss, &mut self.es, sink)
+            .map_err(|e| self.error(e))?;
+        self.offset += 1;
+        Ok(())
+    }
+
+    /// Parse a block of input, stopping early if the sink is done.
+    ///
+    /// Runs of bytes which would only be appended to the current string or number are copied in
+    /// one go, rather than going through the state table a byte at a time.
+    fn feed<S: Sink>(&mut self, input: &[u8], sink: &mut S) -> Result<(), Error> {
+        let mut i = 0;
+        while i < input.len() {
+            let rest = &input[i ..];
+            let run = match self.state {
+                // A pending escape means the next byte has to be checked, so no fast path then.
+                STRING if self.es.is_empty() => {
+                    rest.iter().position(|&c| c == b'"' || c == b'\\').unwrap_or(rest.len())
+                }
+                INTEGER | FRACTION | EXPONENT => {
+                    rest.iter().position(|c| !c.is_ascii_digit()).unwrap_or(rest.len())
+                }
+                _ => 0,
+            };
+            if run == 0 {
+                self.byte(input[i], sink)?;
+                i += 1;
+                if sink.done() {
+                    return Ok(());
+                }
+                continue;
+            }
+            let run = &rest[.. run];
+            self.ss.extend_from_slice(run);
+            let chars = |bytes: &[u8]| bytes.iter().filter(|&&c| c & 0xC0 != 0x80).count() as u64;
+            match run.iter().rposition(|&c| c == b'\n') {
+                Some(last) => {
+                    self.line += run.iter().filter(|&&c| c == b'\n').count() as u64;
+                    self.col = chars(&run[last + 1 ..]);
+                }
+                None => self.col += chars(run),
+            }
+            self.offset += run.len() as u64;
+            i += run.len();
+        }
+        Ok(())
+    }
+
+    /// Signal the end of input.
+    fn finish<S: Sink>(&mut self, sink: &mut S) -> Result<(), Error> {
+        self.finish_with(sink, false)
+    }
+
+    /// Signal the end of input. If it stopped in the middle of a value and `repair` is set, the
+    /// sink is given what there was of it as if it had been closed properly, and then
+    /// [`JsonError::Truncated`] is returned as usual, with the path where it was cut off.
+    fn finish_with<S: Sink>(&mut self, sink: &mut S, repair: bool) -> Result<(), Error> {
+        // If a line break wouldn't do to end the current token (or comment), it's incomplete. In
+        // a string it would just be part of the string.
+        let newline = self.grammar.catcode[b'\n' as usize];
+        let in_string = matches!(self.state,
+                                 STRING | SQ_STRING | DQ_CONTINUATION | SQ_CONTINUATION);
+        if !in_string && self.grammar.states[self.state as usize][newline as usize] != 0xFFFF {
+            self.state = parse_ch(self.grammar, newline, b'\n', &mut self.stack, self.state,
+                                  &mut self.ds, &mut self.ss, &mut self.es, sink)
+                .map_err(|e| self.error(e))?;
+            if self.state == 0 {
+                return Ok(());
+            }
+        }
+        let truncated = self.error(JsonError::Truncated);
+        if repair {
+            self.repair(sink).map_err(|e| self.error(e))?;
+            self.state = parse_ch(self.grammar, newline, b'\n', &mut self.stack, self.state,
+                                  &mut self.ds, &mut self.ss, &mut self.es, sink)
+                .map_err(|e| self.error(e))?;
+        }
+        Err(truncated)
+    }
+
+    /// Signal the end of input, and then the end of the sink's output if the document was whole or
+    /// has been repaired.
+    fn finish_and_end<S: Sink>(&mut self, sink: &mut S, repair: bool) -> Result<(), Error> {
+        let result = self.finish_with(sink, repair);
+        if result.is_ok() || (repair && matches!(result, Err(Error { error: JsonError::Truncated, .. })))
+        {
+            sink.end().map_err(|e| self.error(e.into()))?;
+        }
+        result
+    }
+
+    /// Finish off a truncated document: close any open string, drop any incomplete number or
+    /// literal, and close all the open lists and objects. A key with no value is dropped too.
+    fn repair<S: Sink>(&mut self, sink: &mut S) -> Result<(), JsonError> {
+        loop {
+            let ch = match self.state {
+                0 => return Ok(()),
+                STRING | DQ_CONTINUATION => b'"',
+                SQ_STRING | SQ_CONTINUATION => b'\'',
+                ESCAPE => {
+                    self.state = STRING;
+                    continue;
+                }
+                SQ_ESCAPE => {
+                    self.state = SQ_STRING;
+                    continue;
+                }
+                UNICODE_ESCAPE ..= UNICODE_ESCAPE_END | HEX_ESCAPE ..= HEX_ESCAPE_END => {
+                    self.es.clear();
+                    self.state = self.stack.pop().unwrap();
+                    continue;
+                }
+                COMMENT_START ..= COMMENT_END => {
+                    self.state = self.stack.pop().unwrap();
+                    continue;
+                }
+                INFINITY_END | NAN_END => b' ',
+                NULL ..= FALSE_END | FRACTION_START | EXPONENT_START | EXPONENT_SIGN | MINUS
+                    | HEX_START | INFINITY ..= NAN_END | PLUS =>
+                {
+                    // Drop the value, and go back to the state from before it started.
+                    self.ss.clear();
+                    self.state = match self.stack.pop().unwrap() {
+                        LIST_ELEMENT => LIST_START,
+                        OBJECT_VALUE => OBJECT_COLON,
+                        other => other,
+                    };
+                    continue;
+                }
+                LIST_START | LIST_ELEMENT => b']',
+                LIST_NEXT => {
+                    self.state = LIST_START;
+                    continue;
+                }
+                OBJECT_START | OBJECT_VALUE => b'}',
+                OBJECT_KEY | OBJECT_COLON | OBJECT_NEXT_KEY | OBJECT_NEXT_COLON => {
+                    self.ds.pop();
+                    sink.popped(self.ds.len());
+                    self.state = OBJECT_START;
+                    continue;
+                }
+                OBJECT_NEXT => {
+                    self.state = OBJECT_START;
+                    continue;
+                }
+                // A whole number, or a state which finishes something off on the next character.
+                _ => b' ',
+            };
+            let cat = self.grammar.catcode[ch as usize];
+            self.state = parse_ch(self.grammar, cat, ch, &mut self.stack, self.state,
+                                  &mut self.ds, &mut self.ss, &mut self.es, sink)?;
+        }
+    }
+}
+
+/// Parse JSON from the input and write it in flat format to the output.
+///
+/// The input is read in large blocks, so it doesn't need to be buffered. The output is written a
+/// line at a time, so it should be, and it's flushed at the end.
+pub fn parse(input: impl Read, output: impl Write) -> Result<(), Error> {
+    parse_with(input, output, Options::default())
+}
+
+/// Like [`parse`], but with non-default options.
+pub fn parse_with(input: impl Read, output: impl Write, options: Options) -> Result<(), Error> {
+    let parser = Parser::for_options(&options);
+    let (format, repair, hex) = (options.format, options.repair, options.hex);
+    match options.style {
+        Style::JqStream => {
+            let sink = jq::StreamWriter::new(output, options.prefix);
+            read_into(input, sink, parser, format, repair, hex)
+        }
+        _ => read_into(input, FlatWriter::new(output, options), parser, format, repair, hex),
+    }
+}
+
+/// Give the values in the input to the sink, decoding it as the given format, or as the format
+/// it says it's in if that's JSON.
+fn read_into(input: impl Read, sink: impl Sink, parser: Parser, format: Format, repair: bool,
+             hex: bool)
+    -> Result<(), Error>
+{
+    // Reads are as big as the buffer, so they skip it, apart from the bytes used to detect the
+    // format.
+    let mut input = io::BufReader::with_capacity(BLOCK_SIZE, input);
+    let format = match format {
+        Format::Json => loop {
+            match input.fill_buf() {
+                Ok(head) => break Format::detect(head).unwrap_or(Format::Json),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(parser.error(e.into())),
+            }
+        },
+        other => other,
+    };
+    match format {
+        Format::Json => parse_into(input, sink, parser, repair),
+        Format::Cbor => cbor::decode(input, sink, hex, repair),
+        Format::MessagePack => msgpack::decode(input, sink, hex, repair),
+        Format::Bson => bson::decode(input, sink, hex, repair),
+        Format::Yaml => yaml::decode(input, sink),
+        Format::Toml => toml::decode(input, sink),
+    }
+}
+
+/// Like [`parse_with`], but reading from a file, which is decompressed first if it's gzip or zlib
+/// data. It's read from the file's current position, like `cat` reading its standard input.
+pub fn parse_file(file: &File, output: impl Write, options: Options) -> Result<(), Error> {
+    parse_compressed(io::BufReader::with_capacity(BLOCK_SIZE, file), output, options)
+}
+
+/// Like [`parse_file`], but if it's a regular file which is at its start, it's mapped into memory
+/// instead of being read, which is faster, and if it's also a large list, its elements are parsed
+/// on several threads at once.
+///
+/// # Safety
+///
+/// Nothing else may change the file until this returns. If it's written to, the results are
+/// undefined, and if it's truncated, the process is killed by `SIGBUS`.
+pub unsafe fn parse_file_mapped(file: &File, output: impl Write, options: Options)
+    -> Result<(), Error>
+{
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    if file.metadata().is_ok_and(|m| m.is_file())
+        && (&*file).stream_position().is_ok_and(|p| p == 0)
+    {
+        // SAFETY: the caller promises not to change the file.
+        if let Ok(map) = unsafe { mmap::Mmap::new(file) } {
+            if inflate::is_compressed(&map) {
+                return parse_compressed(&map[..], output, options);
+            }
+            return parse_slice(&map, output, options);
+        }
+    }
+    parse_file(file, output, options)
+}
+
+/// Like [`parse_with`], but decompressing the input first if it's gzip or zlib data.
+pub fn parse_compressed(input: impl BufRead, output: impl Write, options: Options)
+    -> Result<(), Error>
+{
+    let input = inflate::decompress(input).map_err(|e| Parser::new().error(e.into()))?;
+    parse_with(input, output, options)
+}
+
+/// The size of the pieces a list is split into, to parse on separate threads.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+#[cfg_attr(not(all(unix, target_pointer_width = "64")), allow(dead_code))]
+fn parse_slice(data: &[u8], mut output: impl Write, options: Options) -> Result<(), Error> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    // Filters and sorting depend on seeing the whole document in order, and guessing where the
+    // list's elements are depends on it being strict JSON.
+    let parallel = threads > 1
+        && data.len() > 2 * CHUNK_SIZE
+        && options.style != Style::JqStream
+        && options.filter.is_empty()
+        && !options.sort
+        && !options.lenient
+        && options.format == Format::Json
+        && data.iter().find(|c| !c.is_ascii_whitespace()) == Some(&b'[');
+    if parallel {
+        parallel::parse(data, &mut output, &options, CHUNK_SIZE, threads)
+            .map_err(|e| e.with_snippet(data, 0))?;
+        output.flush().map_err(|e| Parser::new().error(e.into()))
+    } else {
+        parse_with(data, output, options)
+    }
+}
+
+/// How much input to read at a time.
+const BLOCK_SIZE: usize = 256 * 1024;
+
+fn parse_into(mut input: impl Read, mut sink: impl Sink, mut parser: Parser, repair: bool)
+    -> Result<(), Error>
+{
+    let mut block = vec![0; BLOCK_SIZE];
+    // The last block read, and where it started, for error messages.
+    let mut filled = 0;
+    let mut start = 0;
+    while !sink.done() {
+        let len = match input.read(&mut block) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(parser.error(e.into())),
+        };
+        filled = len;
+        start = parser.offset;
+        parser.feed(&block[.. len], &mut sink)
+            .map_err(|e| e.with_snippet(&block[.. filled], start))?;
+    }
+    if sink.done() {
+        return sink.end().map_err(|e| parser.error(e.into()));
+    }
+    parser.finish_and_end(&mut sink, repair)
+        .map_err(|e| e.with_snippet(&block[.. filled], start))
+}
+
+/// Parse newline-delimited JSON (also known as JSON Lines or NDJSON), where each line of input is a
+/// separate document. Output lines are prefixed with the line number of the record they came
+/// from, like `[#12].foo = 1`.
+///
+/// A record with an error is passed to `on_error`, and parsing continues with the next line. Any
+/// lines already printed from that record are left as-is. Returns the number of bad records.
+pub fn parse_lines(mut input: impl BufRead, output: impl Write, options: Options,
+                   mut on_error: impl FnMut(Error))
+    -> io::Result<u64>
+{
+    let mut parser = Parser::for_options(&options);
+    let repair = options.repair;
+    let mut sink = FlatWriter::new(output, options);
+    let mut record = vec![];
+    let mut line = 0;
+    let mut offset = 0;
+    let mut bad = 0;
+    loop {
+        record.clear();
+        let len = input.read_until(b'\n', &mut record)?;
+        if len == 0 {
+            break;
+        }
+        line += 1;
+        if record.last() == Some(&b'\n') {
+            record.pop();
+        }
+
+        parser.reset(line, offset);
+        offset += len as u64;
+        sink.popped(0);
+        sink.record(line);
+        let result = parser.feed(&record, &mut sink)
+            .and_then(|()| parser.finish_with(&mut sink, repair))
+            .map_err(|e| e.with_snippet(&record, offset - len as u64));
+        match result {
+            Ok(()) => (),
+            Err(Error { error: JsonError::IO(e), .. }) => return Err(e),
+            Err(e) => {
+                bad += 1;
+                on_error(e);
+            }
+        }
+        sink.end()?;
+    }
+    Ok(bad)
+}
+
+/// A parser which is fed input in pieces as it becomes available, rather than reading it all
+/// from a [`Read`]. Lines of output are written as soon as they are complete.
+///
+/// Once an error is returned, the parser is in an undefined state and shouldn't be fed any more.
+pub struct PushParser<W> {
+    parser: Parser,
+    output: FlatWriter<W>,
+}
+
+impl<W: Write> PushParser<W> {
+    pub fn new(output: W) -> Self {
+        PushParser {
+            parser: Parser::new(),
+            output: FlatWriter::new(output, Options::default()),
+        }
+    }
+
+    /// Parse the next piece of input. It can end anywhere, even in the middle of a token or a
+    /// UTF-8 sequence.
+    pub fn feed(&mut self, input: &[u8]) -> Result<(), Error> {
+        let start = self.parser.offset;
+        self.parser.feed(input, &mut self.output)
+            .map_err(|e| e.with_snippet(input, start))
+    }
+
+    /// Signal the end of input, returning [`JsonError::Truncated`] if the input stopped in the
+    /// middle of a value, or the output otherwise.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.parser.finish(&mut self.output)?;
+        self.output.end().map_err(|e| self.parser.error(e.into()))?;
+        Ok(self.output.output)
+    }
+
+    /// Get access to the output, for example to drain a buffer in between calls to `feed`.
+    pub fn output_mut(&mut self) -> &mut W {
+        &mut self.output.output
+    }
+}
+
+/// Collects values for a [`Flattener`] to hand out.
+#[derive(Default)]
+struct Queue {
+    items: VecDeque<(u64, Path, Terminal)>,
+    document: u64,
+}
+
+impl Sink for Queue {
+    fn leaf(&mut self, ds: &[Value], value: Terminal) -> io::Result<()> {
+        self.items.push_back((self.document, Path::from_stack(ds), value));
+        Ok(())
+    }
+
+    fn next_document(&mut self) -> io::Result<()> {
+        self.document += 1;
+        Ok(())
+    }
+}
+
+/// An iterator over the path and value of every line catj would print for the input.
+///
+/// ```
+/// let input = r#"{"a": [true, {"b c": "d"}], "e": {}}"#.as_bytes();
+/// let lines = catj::Flattener::new(input)
+///     .map(|item| item.map(|(path, value)| format!("{path} = {value}")))
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(lines, [".a[0] = true", r#".a[1]."b c" = "d""#, ".e = {}"]);
+/// ```
+pub struct Flattener<R> {
+    input: io::Bytes<R>,
+    parser: Parser,
+    queue: Queue,
+    document: u64,
+    done: bool,
+}
+
+impl<R: Read> Flattener<R> {
+    /// Callers are expected to pass in a buffered reader.
+    #[allow(clippy::unbuffered_bytes)]
+    pub fn new(input: R) -> Self {
+        Flattener {
+            input: input.bytes(),
+            parser: Parser::new(),
+            queue: Queue::default(),
+            document: 0,
+            done: false,
+        }
+    }
+
+    /// Which top-level value of the input (counting from zero) the most recently returned item
+    /// came from.
+    pub fn document(&self) -> u64 {
+        self.document
+    }
+}
+
+impl<R: Read> Iterator for Flattener<R> {
+    type Item = Result<(Path, Terminal), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((document, path, value)) = self.queue.items.pop_front() {
+                self.document = document;
+                return Some(Ok((path, value)));
+            }
+            if self.done {
+                return None;
+            }
+            let result = match self.input.next() {
+                Some(Ok(ch)) => self.parser.byte(ch, &mut self.queue),
+                Some(Err(e)) => Err(self.parser.error(e.into())),
+                None => {
+                    self.done = true;
+                    self.parser.finish(&mut self.queue)
+                }
+            };
+            if let Err(e) = result {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// A structural event from the [`Events`] pull parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    StartObject,
+    Key(String),
+    EndObject,
+    StartArray,
+    EndArray,
+    /// Never an empty list or object; those are reported as start and end events.
+    Scalar(Terminal),
+}
+
+/// Collects events for [`Events`] to hand out.
+#[derive(Default)]
+struct EventQueue {
+    items: VecDeque<(u64, Event)>,
+    offset: u64,
+    start: Option<u64>,
+}
+
+impl Sink for EventQueue {
+    fn leaf(&mut self, _ds: &[Value], _value: Terminal) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn next_document(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    const EVENTS: bool = true;
+
+    fn value_start(&mut self) {
+        // Keep the first one; later ones are from escapes within a string.
+        self.start.get_or_insert(self.offset);
+    }
+
+    fn event(&mut self, event: Event) -> io::Result<()> {
+        let offset = match event {
+            Event::EndObject | Event::EndArray => self.offset,
+            _ => self.start.take().unwrap_or(self.offset),
+        };
+        self.items.push_back((offset, event));
+        Ok(())
+    }
+}
+
+/// A pull parser which iterates over the structure of the input as a series of events, each with
+/// the byte offset where it starts.
+///
+/// ```
+/// use catj::{Event, Terminal};
+/// let events = catj::Events::new(r#"{"a": [1]}"#.as_bytes())
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(events, [
+///     (0, Event::StartObject),
+///     (1, Event::Key("a".to_owned())),
+///     (6, Event::StartArray),
+///     (7, Event::Scalar(Terminal::Number("1".to_owned()))),
+///     (8, Event::EndArray),
+///     (9, Event::EndObject),
+/// ]);
+/// ```
+pub struct Events<R> {
+    input: io::Bytes<R>,
+    parser: Parser,
+    queue: EventQueue,
+    done: bool,
+}
+
+impl<R: Read> Events<R> {
+    /// Callers are expected to pass in a buffered reader.
+    #[allow(clippy::unbuffered_bytes)]
+    pub fn new(input: R) -> Self {
+        Events {
+            input: input.bytes(),
+            parser: Parser::new(),
+            queue: EventQueue::default(),
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Events<R> {
+    type Item = Result<(u64, Event), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(item) = self.queue.items.pop_front() {
+                return Some(Ok(item));
+            }
+            if self.done {
+                return None;
+            }
+            let result = match self.input.next() {
+                Some(Ok(ch)) => {
+                    let result = self.parser.byte(ch, &mut self.queue);
+                    self.queue.offset += 1;
+                    result
+                }
+                Some(Err(e)) => Err(self.parser.error(e.into())),
+                None => {
+                    self.done = true;
+                    self.parser.finish(&mut self.queue)
+                }
+            };
+            if let Err(e) = result {
+                self.done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Write a JSON5 number the way JSON would: hex numbers in decimal, without a leading `+`, and
+/// with a digit on both sides of the decimal point. `Infinity` and `NaN` stay as they are, since
+/// JSON has no way to write them.
+fn json5_number(s: &str) -> String {
+    let (sign, s) = match s.as_bytes()[0] {
+        b'-' => ("-", &s[1 ..]),
+        b'+' => ("", &s[1 ..]),
+        _ => ("", s),
+    };
+    if s == "NaN" {
+        return s.to_owned();
+    }
+    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        return sign.to_owned() + &decimal(hex.chars().map(|h| h.to_digit(16).unwrap()), 16);
+    }
+    let mut out = String::from(sign);
+    if s.starts_with('.') {
+        out.push('0');
+    }
+    match s.find('.') {
+        Some(dot) if !s[dot + 1 ..].starts_with(|c: char| c.is_ascii_digit()) => {
+            out.push_str(&s[.. dot]);
+            out.push_str(&s[dot + 1 ..]);
+        }
+        _ => out.push_str(s),
+    }
+    out
+}
+
+/// Write a number given as digits in some base, most significant first, in decimal. It can be as
+/// long as it takes.
+fn decimal(input: impl IntoIterator<Item = u32>, base: u32) -> String {
+    // Decimal digits, least significant first.
+    let mut digits = vec![0u8];
+    for digit in input {
+        let mut carry = digit;
+        for d in &mut digits {
+            let n = u32::from(*d) * base + carry;
+            *d = (n % 10) as u8;
+            carry = n / 10;
+        }
+        while carry > 0 {
+            digits.push((carry % 10) as u8);
+            carry /= 10;
+        }
+    }
+    while digits.len() > 1 && digits.last() == Some(&0) {
+        digits.pop();
+    }
+    digits.iter().rev().map(|&d| char::from(b'0' + d)).collect()
+}
+
+/// Character categories which are expected together, and what to call them in error messages.
+const CATEGORY_GROUPS: [(&[u8], &str); 5] = [
+    // The lenient grammar's values and object keys.
+    (&[2, 3, 5, 6, 8, 9, 13, 19, 21, 24, 26, 28, 32, 33], "a value"),
+    (&[2, 11, 12, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 28, 31, 32, 33, 34, 35, 36], "a key"),
+    (&[2, 5, 8, 9, 13, 19, 21, 24, 26], "a value"),
+    (&[8, 9, 11, 12, 16, 17, 18, 19], "a hex digit"),
+    (&[8, 9], "a digit"),
+];
+
+/// Describe what the parser would have accepted in a state, going by which character categories
+/// have an entry in the state table, and what it found instead.
+fn syntax_error(grammar: &Grammar, state: u8, found: u8) -> JsonError {
+    let row = &grammar.states[state as usize];
+    // Whitespace and comments are allowed between tokens everywhere, so they aren't worth
+    // mentioning.
+    let newline = grammar.catcode[b'\n' as usize];
+    let mut valid: Vec<u8> = (2 .. row.len() as u8)
+        .filter(|&cat| row[cat as usize] != 0xFFFF
+                && cat != newline
+                && row[cat as usize] & 0xFF != COMMENT_START as u16)
+        .collect();
+    let mut expected = vec![];
+    for (group, name) in CATEGORY_GROUPS {
+        if group.iter().all(|cat| valid.contains(cat)) {
+            valid.retain(|cat| !group.contains(cat));
+            expected.push(name);
+        }
+    }
+    expected.extend(valid.iter().map(|&cat| CATEGORY_NAMES[cat as usize]));
+
+    let mut msg = match expected.as_slice() {
+        [one] => format!("expected {one}"),
+        [one, other] => format!("expected {one} or {other}"),
+        _ => format!("expected one of {}", expected.join(", ")),
+    };
+    let context = STATE_CONTEXTS[state as usize];
+    if !context.is_empty() {
+        msg += " ";
+        msg += context;
+    }
+    if found.is_ascii_graphic() || found == b' ' {
+        msg += &format!(", found '{}'", found as char);
+    } else if found.is_ascii() {
+        msg += &format!(", found '{}'", found.escape_ascii());
+    } else {
+        msg += ", found a non-ASCII character";
+    }
+    JsonError::Syntax(msg)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn parse_ch<S: Sink>(grammar: &Grammar, cat: u8, ch: u8, stack: &mut Vec<u8>, mut state: u8,
+                     ds: &mut Vec<Value>, ss: &mut Vec<u8>, es: &mut String, sink: &mut S)
+    -> Result<u8, JsonError>
+{
+    loop {
+        let mut code: u16 = grammar.states[state as usize][cat as usize];
+        let mut action: u8 = (code >> 8 & 0xFF) as u8;
+        code &= 0xFF;
+
+        if action == 0xFF && code == 0xFF {
+            return Err(syntax_error(grammar, state, ch));
+        } else if action >= 0x80 {
+            stack.push(grammar.gotos[state as usize]);
+            action -= 0x80;
+            if S::EVENTS {
+                sink.value_start();
+            }
+        } else if action == PUSH_STATE {
+            // The start of a comment, which comes back here when it ends.
+            stack.push(state);
+            action = 0;
+        }
+
+        if state == 0 && !ds.is_empty() {
+            // New top-level value.
+            if let Some(Value::Terminal(t)) = ds.pop() {
+                sink.top_level(t)?;
+            }
+            sink.popped(0);
+            sink.next_document()?;
+        }
+
+        if action > 0 {
+            do_action(action, ch, ds, ss, es, sink)?;
+        }
+
+        if code == 0xFF {
+            state = stack.pop().unwrap();
+        } else {
+            state = code as u8;
+            if S::EVENTS {
+                match state {
+                    LIST_END => sink.event(Event::EndArray)?,
+                    OBJECT_END => sink.event(Event::EndObject)?,
+                    _ => (),
+                }
+            }
+            return Ok(state);
+        }
+    }
+}
+
+fn do_action<S: Sink>(action: u8, ch: u8, ds: &mut Vec<Value>, ss: &mut Vec<u8>,
+                      es: &mut String, sink: &mut S)
+    -> Result<(), JsonError>
+{
+    match action {
+        0x1 => { // push list
+            ds.push(Value::List { index: 0 });
+            sink.pushed(ds);
+            sink.start_container(ds)?;
+        }
+        0x2 => { // push object
+            ds.push(Value::Object { empty: true });
+            sink.pushed(ds);
+            sink.start_container(ds)?;
+        }
+        0x3 => pop_append(ds, sink)?,
+        0x4 => pop_setitem(ds, sink)?,
+        0x5 => { // push null
+            ds.push(Terminal::Null.into());
+        }
+        0x6 => { // push true
+            ds.push(Terminal::Bool(true).into());
+        }
+        0x7 => { // push false
+            ds.push(Terminal::Bool(false).into());
+        }
+        0x8 => { // push string
+            let s = String::from_utf8(ss.clone())
+                .map_err(|e| JsonError::Unicode(e.utf8_error()))?;
+            ds.push(Terminal::String(s).into());
+            ss.clear();
+            es.clear();
+        }
+        0xF => { // push JSON5 number
+            let s = str::from_utf8(ss).map_err(JsonError::Unicode)?;
+            ds.push(Terminal::Number(json5_number(s)).into());
+            ss.clear();
+        }
+        0x9 | 0xA => { // push int, push float
+            ds.push(
+                Terminal::Number(
+                    str::from_utf8(ss)
+                        .map_err(JsonError::Unicode)?
+                        .to_owned()
+                ).into());
+            ss.clear();
+        }
+        0xB => { // push ch to ss
+            ss.push(ch);
+            if !es.is_empty() {
+                let bad = std::mem::take(es);
+                return Err(JsonError::InvalidEscape(bad));
+            }
+            es.clear();
+        }
+        0xC => { // push ch to es
+            if !ch.is_ascii_hexdigit() {
+                return Err(JsonError::InvalidEscape(
+                        format!("{:?} is not a hex digit", ch as char)));
+            }
+            es.push(ch as char);
+        }
+        0xD => { // push escape
+            let c: u8 = match ch {
+                b'b' => 8,
+                b't' => b'\t', //9,
+                b'n' => b'\n', //10,
+                b'f' => 12,
+                b'r' => b'\r', //13,
+                // The rest are only in the lenient grammar, where any other character stands for
+                // itself.
+                b'v' => 11,
+                b'0' => 0,
+                _ => ch,
+            };
+            ss.push(c);
+            es.clear();
+        }
+        0xE => { // push unicode code point
+            let codepoint = match es.len() {
+                8 => {
+                    let high_str = es.get(0..4)
+                        .ok_or_else(|| JsonError::InvalidEscape(
+                                format!("\\u{es}")))?;
+                    let high = u16::from_str_radix(high_str, 16)
+                        .map_err(|e| JsonError::InvalidEscape(
+                                format!("\\u{high_str}: {e}")))?;
+                    if !(0xD800 ..= 0xDBFF).contains(&high) {
+                        return Err(JsonError::InvalidEscape(
+                                format!("\\u{high_str}: unpaired high surrogate")));
+                    }
+
+                    let low_str = es.get(4..8)
+                        .ok_or_else(|| JsonError::InvalidEscape(
+                                format!("\\u{es}")))?;
+                    let low = u16::from_str_radix(low_str, 16)
+                        .map_err(|e| JsonError::InvalidEscape(
+                                format!("\\u{low_str}: {e}")))?;
+                    if !(0xDC00 ..= 0xDFFF).contains(&low) {
+                        return Err(JsonError::InvalidEscape(
+                                format!("\\u{low_str}: unpaired low surrogate")));
+                    }
+
+                    0x1_0000
+                        + (high as u32 - 0xD800) * 0x400
+                        + (low as u32 - 0xDC00)
+                }
+                2 => { // \x escape, from the lenient grammar
+                    u32::from_str_radix(es, 16)
+                        .map_err(|e| JsonError::InvalidEscape(format!("\\x{es}: {e}")))?
+                }
+                4 => {
+                    let two_bytes = u16::from_str_radix(es, 16)
+                        .map_err(|e| JsonError::InvalidEscape(format!("\\u{es}: {e}")))?;
+                    if (0xD800..0xDBFF).contains(&two_bytes) {
+                        // We need to read another surrogate pair to do anything. Keep the 'es'
+                        // buffer unchanged, and let more characters accumulate in it.
+                        return Ok(());
+                    }
+                    u32::from(two_bytes)
+                }
+                _ => {
+                    return Err(JsonError::InvalidEscape(
+                            format!("\\u{es}: wrong number of digits")));
+                }
+            };
+
+            if let Some(u) = char::from_u32(codepoint) {
+                // push the UTF-8 bytes of it to the string buffer
+                let mut buf = [0u8; 4];
+                u.encode_utf8(&mut buf);
+                ss.extend(&buf[0 .. u.len_utf8()]);
+            } else {
+                return Err(JsonError::InvalidEscape(format!("\\u{es} ?")));
+            }
+            es.clear();
+        }
+        _ => panic!("JSON algorithm bug"),
+    }
+
+    if let 0x5 ..= 0xA | 0xF = action {
+        sink.pushed(ds);
+    }
+
+    if S::EVENTS {
+        // Report whatever was just pushed.
+        let event = match (action, &ds[..]) {
+            (0x1, _) => Event::StartArray,
+            (0x2, _) => Event::StartObject,
+            (0x5 ..= 0xA | 0xF,
+             [.., Value::Object { .. }, Value::Terminal(Terminal::String(key))]) =>
+            {
+                Event::Key(key.clone())
+            }
+            (0x5 ..= 0xA | 0xF, [.., Value::Terminal(t)]) => Event::Scalar(t.clone()),
+            _ => return Ok(()),
+        };
+        sink.event(event)?;
+    }
+    Ok(())
+}
+
+/// Pop the finished value off the data stack and append it to the list under it.
+fn pop_append<S: Sink>(ds: &mut Vec<Value>, sink: &mut S) -> io::Result<()> {
+    let v = ds.pop().unwrap();
+    if let Value::Terminal(v) = v {
+        sink.leaf(ds, v)?;
+    }
+    sink.popped(ds.len());
+    match ds.last_mut() {
+        Some(Value::List { index }) => {
+            *index += 1;
+        }
+        other => panic!("expected list on top of the stack, not {:?}", other)
+    }
+    sink.next_index(ds);
+    Ok(())
+}
+
+/// Pop the finished value and its key off the data stack, and set it on the object under them.
+fn pop_setitem<S: Sink>(ds: &mut Vec<Value>, sink: &mut S) -> io::Result<()> {
+    let v = ds.pop().unwrap();
+    match v {
+        Value::Terminal(v) => sink.leaf(ds, v)?,
+        Value::List { index: 0 } => sink.leaf(ds, Terminal::EmptyList)?,
+        Value::Object { empty: true } => sink.leaf(ds, Terminal::EmptyObject)?,
+        Value::List { index: _ } | Value::Object { empty: false } => {
+            // already printed fields for these; nothing to do here.
+        }
+    }
+
+    // pop key, which we've now printed
+    ds.pop().unwrap();
+    sink.popped(ds.len());
+
+    if let Some(Value::Object { ref mut empty }) = ds.last_mut() {
+        *empty = false;
+    } else {
+        panic!("can't set a field on non-object: {:?}", ds.last());
+    }
+    Ok(())
+}
+
+/// Write the path segment for the item at position `i` of the data stack.
+fn print_segment(i: usize, item: &Value, style: Style, output: &mut impl Write) -> io::Result<()> {
+    match (style, item) {
+        (Style::Catj, Value::Object { .. }) => output.write_all(b".")?,
+        (Style::Catj, Value::List { index }) => write!(output, "[{index}]")?,
+        (Style::Catj, Value::Terminal(Terminal::String(s))) => write!(output, "{}", Key(s))?,
+        (Style::Pointer, Value::Object { .. }) => (),
+        (Style::Pointer, Value::List { index }) => write!(output, "/{index}")?,
+        (Style::Pointer, Value::Terminal(Terminal::String(s))) => {
+            write!(output, "/{}", PointerToken(s))?;
+        }
+        (Style::Jq | Style::JqStream, Value::Object { .. }) => (),
+        (Style::Jq | Style::JqStream, Value::List { index }) => {
+            if i == 0 {
+                output.write_all(b".")?;
+            }
+            write!(output, "[{index}]")?;
+        }
+        (Style::Jq | Style::JqStream, Value::Terminal(Terminal::String(s))) => {
+            // ds[0] is the object this is a key of
+            write!(output, "{}", jq::Key { key: s, first: i == 1 })?;
+        }
+        (Style::Js, Value::Object { .. }) => (),
+        (Style::Js, Value::List { index }) => write!(output, "[{index}]")?,
+        (Style::Js, Value::Terminal(Terminal::String(s))) => write!(output, "{}", js::Key(s))?,
+        (Style::Gron, Value::Object { .. }) => (),
+        (Style::Gron, Value::List { index }) => write!(output, "[{index}]")?,
+        (Style::Gron, Value::Terminal(Terminal::String(s))) => {
+            write!(output, "{}", gron::Key(s))?;
+        }
+        (_, Value::Terminal(other)) => panic!("invalid item in a path: {:?}", other),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(input: &str) -> String {
+        let mut input = io::Cursor::new(input);
+        let mut out = vec![];
+        parse(&mut input, &mut out).unwrap();
+        String::from_utf8(out).expect("bad utf8").trim().to_owned()
+    }
+
+    #[test]
+    fn test_empty() {
+        assert_eq!("", run(""));
+        assert_eq!("", run("{}"));
+        assert_eq!("", run("[]"));
+        assert_eq!("", run("[{}]"));
+    }
+
+    #[test]
+    fn test_not_empty() {
+        assert_eq!(".foo = []", run(r#"{"foo": []}"#));
+        assert_eq!(".\"\" = []", run(r#"{"": []}"#));
+    }
+
+    #[test]
+    fn test_simple() {
+        assert_eq!(".foo = \"bar\"", run(r#"{"foo": "bar"}"#));
+    }
+
+    #[test]
+    fn test_non_ident_keys() {
+        assert_eq!(".bare = \"a\"", run(r#"{"bare": "a"}"#));
+        assert_eq!(".\"quoted now\" = \"b\"", run(r#"{"quoted now": "b"}"#));
+    }
+
+    #[test]
+    fn test_utf8() {
+        assert_eq!(".\"⚙🖥\" = \"🦀\"", run(r#"{"⚙🖥": "🦀"}"#));
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(".smile = \"😊\"", run(r#"{"smile": "\ud83d\ude0a"}"#));
+        assert_eq!(".\"\\b_backspace\" = \"carriage\\r\\nreturn\"", run(r#"{"\b_backspace": "carriage\r\nreturn"}"#));
+    }
+
+    #[test]
+    fn test_flattener() {
+        let input = r#"{"a": [1, {"b": null}]} [[], {"c": {}}]"#;
+        let mut flat = Flattener::new(input.as_bytes());
+        let mut items = vec![];
+        while let Some(item) = flat.next() {
+            let (path, value) = item.unwrap();
+            items.push((flat.document(), path.to_string(), value));
+        }
+        assert_eq!(items, [
+            (0, ".a[0]".to_owned(), Terminal::Number("1".to_owned())),
+            (0, ".a[1].b".to_owned(), Terminal::Null),
+            (1, "[1].c".to_owned(), Terminal::EmptyObject),
+        ]);
+
+        let mut flat = Flattener::new(r#"{"a": 1, "b": ]"#.as_bytes());
+        assert!(flat.next().unwrap().is_ok());
+        let e = flat.next().unwrap().unwrap_err();
+        assert_eq!((1, 15), (e.line, e.col));
+        assert!(flat.next().is_none());
+    }
+
+    #[test]
+    fn test_events() {
+        let input = r#"[{"k\u00e9": "v", "n": -1.5e3}, []] null"#;
+        let events = Events::new(input.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(events, [
+            (0, Event::StartArray),
+            (1, Event::StartObject),
+            (2, Event::Key("ké".to_owned())),
+            (13, Event::Scalar(Terminal::String("v".to_owned()))),
+            (18, Event::Key("n".to_owned())),
+            (23, Event::Scalar(Terminal::Number("-1.5e3".to_owned()))),
+            (29, Event::EndObject),
+            (32, Event::StartArray),
+            (33, Event::EndArray),
+            (34, Event::EndArray),
+            (36, Event::Scalar(Terminal::Null)),
+        ]);
+    }
+
+    #[test]
+    fn test_push_parser() {
+        let input = r#"{"a": "\ud83d\ude0a", "b c": [12.5, true]}"#;
+        let mut parser = PushParser::new(vec![]);
+        for chunk in input.as_bytes().chunks(3) {
+            parser.feed(chunk).unwrap();
+        }
+        let out = parser.finish().unwrap();
+        assert_eq!(run(input), str::from_utf8(&out).unwrap().trim());
+
+        let mut parser = PushParser::new(vec![]);
+        parser.feed(b"[1, 23").unwrap();
+        // the number might not be over yet
+        assert_eq!(b"[0] = 1\n", &parser.output_mut()[..]);
+        parser.feed(b"4").unwrap();
+        assert!(matches!(parser.finish(), Err(Error { error: JsonError::Truncated, .. })));
+    }
+
+    #[test]
+    fn test_fast_path() {
+        // runs of string and number bytes, split at every possible place
+        let input = "{\"long key\": \"multi\nline \\\"string\\u00e9\\ud83d\\ude0a\", \"n\": [-120.0625e+10, 0]}";
+        let expected = run(input);
+        for split in 0 ..= input.len() {
+            let mut parser = PushParser::new(vec![]);
+            parser.feed(&input.as_bytes()[.. split]).unwrap();
+            parser.feed(&input.as_bytes()[split ..]).unwrap();
+            let out = parser.finish().unwrap();
+            assert_eq!(expected, str::from_utf8(&out).unwrap().trim());
+        }
+
+        // positions are still counted within runs
+        let err = parse(&b"[\"a\nbc\", 12345x]"[..], io::sink()).unwrap_err();
+        assert_eq!((2, 11), (err.line, err.col));
+        let err = parse(&b"[\"\\u0041bc\\q\"]"[..], io::sink()).unwrap_err();
+        assert_eq!((1, 12), (err.line, err.col));
+    }
+
+    #[test]
+    fn test_error_report() {
+        let input = "{\"naïve\": \"été\",\n \"x\": [1, {\"y\": tru}]}";
+        let err = parse(input.as_bytes(), io::sink()).unwrap_err();
+        assert_eq!((2, 20, 39), (err.line, err.col, err.offset));
+        assert_eq!(err.path.to_string(), ".x[1].y");
+        assert_eq!(err.to_string(), "line 2 column 20 (byte 39) in .x[1].y: expected 'e' in 'true', found '}'\n     \
+            \"x\": [1, {\"y\": tru}]}\n                       ^");
+
+        // the snippet is cut down to size on long lines, and stops at line breaks
+        let input = format!("[{}, \"a\tb\" x, {}]", "1, ".repeat(100), "2, ".repeat(100));
+        let err = parse(input.as_bytes(), io::sink()).unwrap_err();
+        let snippet = err.snippet.unwrap();
+        assert_eq!(snippet.text,
+                   " 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, , \"a b\" x, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2,");
+        assert_eq!(snippet.caret, 40);
+        let err = parse(&b"[1,\n2 3\n]"[..], io::sink()).unwrap_err();
+        assert_eq!(err.snippet.unwrap(), Snippet { text: "2 3".to_owned(), caret: 2 });
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let message = |input: &str| parse(input.as_bytes(), io::sink()).unwrap_err().error
+            .to_string();
+        assert_eq!(message(r#"{"a": 1]"#), "expected ',' or '}' after object value, found ']'");
+        assert_eq!(message("[1, 2 3]"), "expected ',' or ']' after list element, found '3'");
+        assert_eq!(message("[1, ]"), "expected a value after ',' in list, found ']'");
+        assert_eq!(message("[}"), "expected a value or ']' after '[', found '}'");
+        assert_eq!(message("{1: 2}"), r#"expected '"' or '}' after '{', found '1'"#);
+        assert_eq!(message(r#"{"a" 1}"#), "expected ':' after object key, found '1'");
+        assert_eq!(message("-x"), "expected a digit after '-', found 'x'");
+        assert_eq!(message("1.e5"), "expected a digit after '.' in number, found 'e'");
+        assert_eq!(message(r#""\x""#), "expected one of '\"', '/', '\\', 'b', 'f', 'n', 'r', \
+            't', 'u' after '\\' in string, found 'x'");
+        assert_eq!(message(r#""\u12g4""#), "expected a hex digit in '\\u' escape, found 'g'");
+        assert_eq!(message("nul\tl"), "expected 'l' in 'null', found '\\t'");
+        assert_eq!(message("1 é"), "expected a value, found a non-ASCII character");
+
+        // running out of input in the middle of a token is truncation, not a syntax error
+        assert_eq!(message("[1."), "input truncated");
+        assert_eq!(message("tr"), "input truncated");
+    }
+
+    #[test]
+    fn test_repair() {
+        let repair = |input: &str, lines: &[&str], path: &str| {
+            let options = Options { repair: true, ..Options::default() };
+            let mut out = vec![];
+            let err = parse_with(input.as_bytes(), &mut out, options).unwrap_err();
+            assert!(matches!(err.error, JsonError::Truncated), "{input}: {err}");
+            assert_eq!(err.path.to_string(), path, "{input}");
+            let mut expected = lines.join("\n");
+            expected.push_str("\n\n");
+            assert_eq!(String::from_utf8(out).unwrap(), expected, "{input}");
+        };
+        repair(r#"{"a": [1, 2, {"b": "hel"#, &[".a[0] = 1", ".a[1] = 2", r#".a[2].b = "hel""#],
+               ".a[2].b");
+        repair(r#"{"a": 1, "b": tru"#, &[".a = 1"], ".b");
+        repair(r#"{"a": 1, "b": 12"#, &[".a = 1", ".b = 12"], ".b");
+        repair(r#"{"a": 1, "b": 1.5e"#, &[".a = 1"], ".b");
+        repair(r#"{"a": {}, "b": "#, &[".a = {}"], ".b");
+        repair(r#"{"x": ["#, &[".x = []"], ".x[0]");
+        repair(r#"{"x": [1, "a\u00"#, &[".x[0] = 1", r#".x[1] = "a""#], ".x[1]");
+
+        // without repair, nothing more is written
+        let mut out = vec![];
+        parse(&br#"{"a": 1, "b": [2"#[..], &mut out).unwrap_err();
+        assert_eq!(String::from_utf8(out).unwrap(), ".a = 1\n");
+    }
+
+    #[test]
+    fn test_lenient() {
+        let input = r#"// JSON5
+            {
+              unquoted: 'single "quoted"', $key_2: "multi\
+line\x21", 'esc': '\v\0\q\'',
+              hex: [0xFf, -0x10, 0x10000000000000000],
+              numbers: [.5, 5., +1, 5.e3, -Infinity, NaN, +0], /* trailing
+              commas: */ list: [1, /**/ 2,], // the end
+            }
+            "#;
+        let options = Options { lenient: true, ..Options::default() };
+        let mut out = vec![];
+        parse_with(input.as_bytes(), &mut out, options.clone()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), r#".unquoted = "single \"quoted\""
+."$key_2" = "multiline!"
+.esc = "\u000b\u0000q'"
+.hex[0] = 255
+.hex[1] = -16
+.hex[2] = 18446744073709551616
+.numbers[0] = 0.5
+.numbers[1] = 5
+.numbers[2] = 1
+.numbers[3] = 5e3
+.numbers[4] = -Infinity
+.numbers[5] = NaN
+.numbers[6] = 0
+.list[0] = 1
+.list[1] = 2
+
+"#);
+        assert!(parse(input.as_bytes(), io::sink()).is_err());
+
+        let options = Options { style: Style::JqStream, ..options };
+        let mut out = vec![];
+        parse_with(&b"{a: [0x10, 'b']}"[..], &mut out, options.clone()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "[[\"a\",0],16]\n[[\"a\",1],\"b\"]\n[[\"a\",1]]\n[[\"a\"]]\n");
+
+        let message = |input: &str| parse_with(input.as_bytes(), io::sink(), options.clone())
+            .unwrap_err().error.to_string();
+        assert_eq!(message("{,}"), "expected a key or '}' after '{', found ','");
+        assert_eq!(message("[1 /x]"), "expected '/' or '*' after '/', found 'x'");
+        assert_eq!(message("[0x]"), "expected a hex digit after '0x', found ']'");
+        assert_eq!(message("[1,,]"), "expected a value or ']' after ',' in list, found ','");
+        assert_eq!(message("[1 /* x"), "input truncated");
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let path = std::env::temp_dir().join(format!("catj-test-{}.json", std::process::id()));
+        std::fs::write(&path, r#"{"a": [1, "b"]}"#).unwrap();
+        let mut out = vec![];
+        let result = parse_file(&File::open(&path).unwrap(), &mut out, Options::default());
+        let file = File::open(&path).unwrap();
+        // SAFETY: nothing changes the file.
+        let result = result.and(unsafe { parse_file_mapped(&file, &mut out, Options::default()) });
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!(".a[0] = 1\n.a[1] = \"b\"\n\n".repeat(2), str::from_utf8(&out).unwrap());
+
+        // like `(head -c 2 >/dev/null; catj) < file`, so only the rest of the file is read
+        std::fs::write(&path, "[1]\n[2]").unwrap();
+        let mut file = File::open(&path).unwrap();
+        file.seek(io::SeekFrom::Start(4)).unwrap();
+        let mut out = vec![];
+        // SAFETY: nothing changes the file.
+        let result = unsafe { parse_file_mapped(&file, &mut out, Options::default()) };
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+        assert_eq!("[0] = 2\n\n", str::from_utf8(&out).unwrap());
+    }
+
+    #[test]
+    fn test_prefix() {
+        let options = Options { prefix: "a.json:".to_owned(), ..Options::default() };
+        let mut out = vec![];
+        parse_with(&b"[1]"[..], &mut out, options.clone()).unwrap();
+        parse_lines(&br#"{"b": {}}"#[..], &mut out, options.clone(), |_| ()).unwrap();
+        let options = Options { style: Style::JqStream, ..options };
+        parse_with(&b"[1]"[..], &mut out, options).unwrap();
+        assert_eq!("a.json:[0] = 1\n\na.json:[#1].b = {}\na.json:[[0],1]\na.json:[[0]]\n",
+                   str::from_utf8(&out).unwrap());
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let input = "{\"id\": 1}\n\n{\"id\": \n[true]\n{\"a\": {}}";
+        let mut out = vec![];
+        let mut errors = vec![];
+        let bad = parse_lines(input.as_bytes(), &mut out, Options::default(), |e| errors.push((e.line, e.col))).unwrap();
+        assert_eq!("[#1].id = 1\n[#4][0] = true\n[#5].a = {}\n", str::from_utf8(&out).unwrap());
+        assert_eq!(1, bad);
+        assert_eq!([(3, 7)], &errors[..]);
+    }
+
+    #[test]
+    fn test_filter() {
+        let input = r#"{"a": [{"b": 1, "c": 2}, {"b": 3}], "d": {"b": 4}} {"d": {"b": 5}}"#;
+        let filtered = |include: &[&str], exclude: &[&str], stop: bool| {
+            let mut options = Options::default();
+            for p in include {
+                options.filter.include(p).unwrap();
+            }
+            for p in exclude {
+                options.filter.exclude(p).unwrap();
+            }
+            options.filter.stop_early(stop);
+            let mut out = vec![];
+            parse_with(input.as_bytes(), &mut out, options).unwrap();
+            String::from_utf8(out).unwrap().trim().to_owned()
+        };
+        assert_eq!(".a[0].b = 1\n.a[1].b = 3", filtered(&[".a[*].b"], &[], false));
+        assert_eq!(".a[0].b = 1\n.a[1].b = 3\n.d.b = 4\n\n.d.b = 5", filtered(&["..b"], &[], false));
+        assert_eq!(".a[0].c = 2", filtered(&[], &["..b"], false));
+        assert_eq!(".d.b = 4\n\n.d.b = 5", filtered(&[".d.b"], &[], false));
+        assert_eq!(".a[1].b = 3", filtered(&[".a[1].b"], &[], true));
+    }
+
+    #[test]
+    fn test_pointer_style() {
+        let input = r#"{"a": {"b c": [1], "x/y~z": true, "": {}}}"#;
+        let mut out = vec![];
+        let options = Options { style: Style::Pointer, ..Options::default() };
+        parse_with(input.as_bytes(), &mut out, options).unwrap();
+        assert_eq!("/a/b c/0 = 1\n/a/x~1y~0z = true\n/a/ = {}\n\n", str::from_utf8(&out).unwrap());
+
+        let path = Path(vec![Segment::Key("~/".to_owned()), Segment::Index(2)]);
+        assert_eq!("/~0~1/2", path.pointer());
+    }
+
+    #[test]
+    fn test_jq_styles() {
+        let input = r#"{"a": [{"quoted now": 1, "if": [], "_x1": "\u007f"}, [], [[2]]], "123": {}} 3 []"#;
+        let styled = |style| {
+            let mut out = vec![];
+            parse_with(input.as_bytes(), &mut out, Options { style, ..Options::default() })
+                .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(
+            ".a[0][\"quoted now\"] = 1\n.a[0][\"if\"] = []\n.a[0]._x1 = \"\x7f\"\n.a[2][0][0] = 2\n\
+             .[\"123\"] = {}\n\n\n\n",
+            styled(Style::Jq));
+        assert_eq!(
+            "[[\"a\",0,\"quoted now\"],1]\n[[\"a\",0,\"if\"],[]]\n[[\"a\",0,\"_x1\"],\"\\u007f\"]\n\
+             [[\"a\",0,\"_x1\"]]\n[[\"a\",1],[]]\n[[\"a\",2,0,0],2]\n[[\"a\",2,0,0]]\n[[\"a\",2,0]]\n\
+             [[\"a\",2]]\n[[\"123\"],{}]\n[[\"123\"]]\n[[],3]\n[[],[]]\n",
+            styled(Style::JqStream));
+    }
+
+    /// A [`FlatWriter`] which checks its cached path against one rendered from the whole data
+    /// stack, every time it writes a line.
+    struct CheckedWriter(FlatWriter<Vec<u8>>);
+
+    impl CheckedWriter {
+        fn check(&self, ds: &[Value]) {
+            let mut expected = vec![];
+            for (i, item) in ds.iter().enumerate() {
+                let is_key = i > 0 && matches!(ds[i - 1], Value::Object { .. });
+                if is_key || !matches!(item, Value::Terminal(_)) {
+                    print_segment(i, item, self.0.options.style, &mut expected).unwrap();
+                }
+            }
+            let end = self.0.marks.get(ds.len()).copied().unwrap_or(self.0.path.len());
+            assert_eq!(str::from_utf8(&self.0.path[.. end]).unwrap(),
+                       str::from_utf8(&expected).unwrap());
+        }
+    }
+
+    impl Sink for CheckedWriter {
+        fn leaf(&mut self, ds: &[Value], value: Terminal) -> io::Result<()> {
+            self.check(ds);
+            self.0.leaf(ds, value)
+        }
+
+        fn next_document(&mut self) -> io::Result<()> {
+            self.0.next_document()
+        }
+
+        fn pushed(&mut self, ds: &[Value]) {
+            self.0.pushed(ds);
+        }
+
+        fn popped(&mut self, len: usize) {
+            self.0.popped(len);
+        }
+
+        fn next_index(&mut self, ds: &[Value]) {
+            self.0.next_index(ds);
+        }
+
+        fn start_container(&mut self, ds: &[Value]) -> io::Result<()> {
+            self.check(&ds[.. ds.len() - 1]);
+            self.0.start_container(ds)
+        }
+
+        fn top_level(&mut self, value: Terminal) -> io::Result<()> {
+            self.0.top_level(value)
+        }
+    }
+
+    #[test]
+    fn test_path_cache() {
+        // deep nesting, indexes which go past 9 and back to 0, keys which need quoting, and going
+        // back up several levels at once
+        let input = format!(
+            r#"{{"a": {{"b c": [[{}], {{"x.y": [{{"": {{"1": []}}}}, {{}}]}}, [[[["deep"]]]]]}},
+                "é\n": [{{"k": 1}}, {{"k": 2, "j": [true, null]}}]}} [1, [2]] "top" {{"z": 0}}"#,
+            (0 .. 12).map(|i| i.to_string()).collect::<Vec<_>>().join(", "));
+        for style in [Style::Catj, Style::Pointer, Style::Jq, Style::Js, Style::Gron] {
+            let options = Options { style, ..Options::default() };
+            let parser = Parser::for_options(&options);
+            let sink = CheckedWriter(FlatWriter::new(vec![], options));
+            read_into(input.as_bytes(), sink, parser, Format::Json, false, false).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_js_style() {
+        let input = "{\"a\": [[], {\"x y\": 1, \"123abc\": \"\\u2028\", \"if\": null, \"été\": {}}]} \"s\"";
+        let mut out = vec![];
+        let options = Options { style: Style::Js, root: "data".to_owned(), ..Options::default() };
+        parse_with(input.as_bytes(), &mut out, options).unwrap();
+        assert_eq!("\
+            data = {};\n\
+            data.a = [];\n\
+            data.a[0] = [];\n\
+            data.a[1] = {};\n\
+            data.a[1][\"x y\"] = 1;\n\
+            data.a[1][\"123abc\"] = \"\\u2028\";\n\
+            data.a[1][\"if\"] = null;\n\
+            data.a[1].été = {};\n\
+            \n\
+            data = \"s\";\n\
+            \n",
+            str::from_utf8(&out).unwrap());
+    }
+
+    #[test]
+    fn test_gron_style() {
+        let input = r#"{"b": [10, 9, {"z": 1, "a": {}}], "a-b": "\u007f", "a": 2.5}"#;
+        let mut out = vec![];
+        let options = Options { style: Style::Gron, ..Options::default() };
+        parse_with(input.as_bytes(), &mut out, options).unwrap();
+        assert_eq!("\
+            json = {};\n\
+            json.b = [];\n\
+            json.b[0] = 10;\n\
+            json.b[1] = 9;\n\
+            json.b[2] = {};\n\
+            json.b[2].z = 1;\n\
+            json.b[2].a = {};\n\
+            json[\"a-b\"] = \"\\u007F\";\n\
+            json.a = 2.5;\n",
+            str::from_utf8(&out).unwrap());
+
+        let input = format!("[{}]", (0 .. 11).map(|i| i.to_string()).collect::<Vec<_>>().join(","));
+        let mut out = vec![];
+        let options = Options { style: Style::Gron, sort: true, ..Options::default() };
+        parse_with(input.as_bytes(), &mut out, options).unwrap();
+        assert!(str::from_utf8(&out).unwrap().starts_with("json = [];\njson[0] = 0;\njson[1] = 1;\njson[2] = 2;\n"));
+
+        let mut out = vec![];
+        let options = Options { style: Style::Gron, sort: true, ..Options::default() };
+        parse_with(r#"{"b": [10, 9, {"z": 1, "a": {}}], "a-b": "x", "a": 2.5} true"#.as_bytes(),
+                   &mut out, options).unwrap();
+        assert_eq!("\
+            json = {};\n\
+            json.a = 2.5;\n\
+            json.b = [];\n\
+            json.b[0] = 10;\n\
+            json.b[1] = 9;\n\
+            json.b[2] = {};\n\
+            json.b[2].a = {};\n\
+            json.b[2].z = 1;\n\
+            json[\"a-b\"] = \"x\";\n\
+            \n\
+            json = true;\n",
+            str::from_utf8(&out).unwrap());
+    }
+}
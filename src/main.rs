@@ -1,420 +1,437 @@
 #![deny(rust_2018_idioms)]
 
-/// catj, rust streaming parser edition
-///
-/// Displays JSON files in a flat format.
-///
-/// https://github.com/wfraser/catj-rss
-///
-/// Copyright 2019-2023 William R. Fraser
-
-use std::char;
-use std::cmp::min;
-use std::io::{self, Read, Write};
+//! catj, rust streaming parser edition
+//!
+//! Displays JSON files in a flat format.
+//!
+//! https://github.com/wfraser/catj-rss
+//!
+//! Copyright 2019-2023 William R. Fraser
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::str::{self, Utf8Error};
 
-mod tables;
-use tables::{STATES, GOTOS, CATCODE};
-
-#[derive(Debug)]
-enum JsonError {
-    Truncated,
-    Syntax,
-    InvalidEscape(String),
-    Unicode(Utf8Error),
-    IO(io::Error),
+use catj::JsonError;
+use catj::inflate::decompress;
+use catj::zip::Archive;
+use catj::ungron::{self, UngronError};
+
+fn usage() -> ! {
+    eprintln!("usage: {} [options] [FILE | DIRECTORY | -]...", std::env::args().next().unwrap());
+    eprintln!("Displays JSON files in a flat format.");
+    eprintln!("Reads the given files, or standard input if there are none or for '-', and writes");
+    eprintln!("to standard output. Directories are searched recursively for files with one of");
+    eprintln!("the --ext extensions. With more than one input, each line starts with the name");
+    eprintln!("of the file it came from. Input compressed with gzip or zlib is decompressed,");
+    eprintln!("and zip archives are read as the files in them with one of the --ext extensions.");
+    eprintln!();
+    eprintln!("options:");
+    eprintln!("  -V, --version           print version information and exit");
+    eprintln!("  -u, --ungron            reverse mode: read catj (or gron) output and rebuild the");
+    eprintln!("                          JSON");
+    eprintln!("  -l, --lines             newline-delimited JSON: parse each line as a separate");
+    eprintln!("                          record, and keep going after records with errors");
+    eprintln!("  -e, --ext EXT           the file extension to look for in directories");
+    eprintln!("                          (repeatable; default: json and json.gz)");
+    eprintln!("  -s, --style STYLE       how to write paths:");
+    eprintln!("                            catj       the default, like .a.\"b c\"[0]");
+    eprintln!("                            pointer    JSON Pointer, like /a/b c/0");
+    eprintln!("                            jq         jq filters, like .a[\"b c\"][0]");
+    eprintln!("                            jq-stream  the same as 'jq -c --stream'");
+    eprintln!("                            js         JavaScript, like json.a[\"b c\"][0] = 1;");
+    eprintln!("                            gron       the same as gron, in input order");
+    eprintln!("      --root NAME         the variable name for --style js/gron (default: json)");
+    eprintln!("      --sort              sort lines like gron does, for --style gron (this holds");
+    eprintln!("                          the whole input in memory)");
+    eprintln!("  -i, --include PATTERN   only print paths matching the pattern (repeatable)");
+    eprintln!("  -x, --exclude PATTERN   don't print paths matching the pattern (repeatable)");
+    eprintln!("      --stop-early        stop reading once past all the --include paths, if");
+    eprintln!("                          they have no wildcards");
+    eprintln!("      --json5             accept JSON5, with comments, trailing commas, unquoted");
+    eprintln!("                          keys, single quotes and so on");
+    eprintln!("      --repair            if the input is cut off, finish the output as if it");
+    eprintln!("                          had been closed there (it's still an error)");
+    eprintln!("      --cbor              read CBOR instead of JSON (CBOR which starts with the");
+    eprintln!("                          self-describe tag is recognised anyway)");
+    eprintln!("      --msgpack           read MessagePack instead of JSON");
+    eprintln!("      --bson              read BSON documents, like mongodump writes, instead of");
+    eprintln!("                          JSON");
+    eprintln!("      --yaml              read YAML instead of JSON (YAML which starts with");
+    eprintln!("                          '---' or '%YAML' is recognised anyway)");
+    eprintln!("      --toml              read TOML instead of JSON");
+    eprintln!("      --hex               write binary data in hex instead of base64");
+    eprintln!("      --no-mmap           read files instead of mapping them into memory, for");
+    eprintln!("                          files which may change while catj is reading them");
+    eprintln!();
+    eprintln!("Patterns look like paths, with wildcards: '.*' and '[*]' match any one key or");
+    eprintln!("index, and '.**' matches any number of levels, e.g. '.items[*].metadata.**'.");
+    eprintln!("They can also be JSON Pointers, e.g. '/items/*/metadata/**'.");
+    eprintln!();
+    eprintln!("see https://github.com/wfraser/catj-rss");
+    exit(1);
 }
 
-impl From<io::Error> for JsonError {
-    fn from(e: io::Error) -> Self {
-        JsonError::IO(e)
+const OUTPUT_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Flush the output, so it's all written before any error message.
+fn flush(out: &mut impl Write) {
+    if let Err(e) = out.flush() {
+        eprintln!("I/O error: {e}");
+        exit(2);
     }
 }
 
-#[derive(Debug)]
-enum Value {
-    Object { empty: bool }, // empty: whether we've seen any fields yet while parsing
-    List { index: u64 }, // index: the current size of the list while parsing
-    Terminal(Terminal),
+/// Standard input as a file, so that it can be memory mapped if it's redirected from one.
+#[cfg(unix)]
+fn stdin_file() -> Option<File> {
+    use std::os::fd::AsFd;
+    io::stdin().as_fd().try_clone_to_owned().ok().map(File::from)
 }
 
-/// Things we print a line for.
-#[derive(Debug)]
-enum Terminal {
-    Null,
-    Bool(bool),
-    Number(String),
-    String(String),
+#[cfg(not(unix))]
+fn stdin_file() -> Option<File> {
+    None
 }
 
-impl std::fmt::Display for Terminal {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// Somewhere to read input from.
+enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    /// The name to put before each line of output, when there's more than one input.
+    fn prefix(&self) -> String {
         match self {
-            Terminal::Null => f.write_str("null"),
-            Terminal::Bool(v) => write!(f, "{v:?}"),
-            Terminal::Number(s) => f.write_str(s),
-            Terminal::String(s) => {
-                f.write_str("\"")?;
-                let mut tmp = [0u8; 4];
-                for c in s.chars() {
-                    match c {
-                        '"' => f.write_str("\\\"")?,
-                        '\\' => f.write_str("\\\\")?,
-                        '\x08' => f.write_str("\\b")?,
-                        '\t' => f.write_str("\\t")?,
-                        '\x0C' => f.write_str("\\f")?,
-                        '\n' => f.write_str("\\n")?,
-                        '\r' => f.write_str("\\r")?,
-                        c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
-                        // to emit astral plane characters as escaped surrogate pairs:
-                        /*c if (c as u32) > 0xFFFF => {
-                            let mut pair = [0u16; 2];
-                            c.encode_utf16(&mut pair);
-                            write!(f, "\\u{:04x}\\u{:04x}", pair[0], pair[1])?;
-                        }*/
-                        c => f.write_str(c.encode_utf8(&mut tmp))?,
-                    }
-                }
-                f.write_str("\"")
-            }
+            Input::Stdin => "(standard input):".to_owned(),
+            Input::File(path) => format!("{}:", path.display()),
         }
     }
-}
 
-impl From<Terminal> for Value {
-    fn from(t: Terminal) -> Self {
-        Value::Terminal(t)
+    /// The name to use in error messages.
+    fn name(&self) -> String {
+        match self {
+            Input::Stdin => "input".to_owned(),
+            Input::File(path) => path.display().to_string(),
+        }
     }
 }
 
-fn parse(input: impl Read, mut output: impl Write) -> Result<(), (u64, u64, JsonError)> {
-    let mut stack = vec![];
-    let mut state = 0;
-    let mut ds: Vec<Value> = vec![];    // data stack
-    let mut ss: Vec<u8> = vec![];       // string stack
-    let mut es = String::new();         // escape stack
-    let mut line = 1;
-    let mut col = 0;
-    for maybe_ch in input.bytes() {
-        let ch = maybe_ch.map_err(|e| (line, col, JsonError::IO(e)))?;
-        if ch == b'\n' {
-            line += 1;
-            col = 0;
-        } else {
-            col += 1;
+/// Whether a file name ends in `.` and one of the extensions.
+fn has_ext(name: &str, exts: &[String]) -> bool {
+    exts.iter().any(|ext| name.len() > ext.len() + 1
+        && name.ends_with(ext.as_str())
+        && name[.. name.len() - ext.len()].ends_with('.'))
+}
+
+/// Add the files under a directory with any of the given extensions to the inputs, in order of
+/// their names. Symbolic links to directories aren't followed, to avoid going in circles.
+fn walk(dir: &Path, exts: &[String], inputs: &mut Vec<Input>) -> io::Result<()> {
+    let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if let Err(e) = walk(&path, exts, inputs) {
+                eprintln!("catj: {}: {e}", path.display());
+            }
+        } else if has_ext(&entry.file_name().to_string_lossy(), exts) {
+            inputs.push(Input::File(path));
         }
-        let cat = CATCODE[min(ch, 0x7e) as usize];
-        state = parse_ch(cat, ch, &mut stack, state, &mut ds, &mut ss, &mut es, &mut output)
-            .map_err(|e| (line, col, e))?;
-    }
-    state = parse_ch(CATCODE[32], b'?', &mut stack, state, &mut ds, &mut ss, &mut es, &mut output)
-        .map_err(|e| (line, col, e))?;
-    if state != 0 {
-        return Err((line, col, JsonError::Truncated));
     }
     Ok(())
 }
 
-#[allow(clippy::too_many_arguments)]
-fn parse_ch(cat: u8, ch: u8, stack: &mut Vec<u8>, mut state: u8, ds: &mut Vec<Value>,
-            ss: &mut Vec<u8>, es: &mut String, mut output: impl Write)
-    -> Result<u8, JsonError>
-{
-    loop {
-        let mut code: u16 = STATES[state as usize][cat as usize];
-        let mut action: u8 = (code >> 8 & 0xFF) as u8;
-        code &= 0xFF;
+enum Mode {
+    Flat,
+    Lines,
+    Ungron,
+}
 
-        if action == 0xFF && code == 0xFF {
-            return Err(JsonError::Syntax);
-        } else if action >= 0x80 {
-            stack.push(GOTOS[state as usize]);
-            action -= 0x80;
+/// Read one input and write its output. Returns whether it was free of errors, which have been
+/// reported already if not. `bad` is increased by the number of bad records in `Mode::Lines`. If
+/// `mmap` is set, a regular file is mapped into memory in `Mode::Flat` rather than being read.
+///
+/// A zip archive is read as the members in it with any of the extensions, one after another, with
+/// a prefix like `archive.zip!member.json:` on every line.
+fn run(input: &Input, mode: &Mode, exts: &[String], options: catj::Options, mmap: bool,
+       bad: &mut u64, out: &mut impl Write)
+    -> bool
+{
+    let name = input.name();
+    let file = match input {
+        Input::Stdin => None,
+        Input::File(path) => match File::open(path) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                eprintln!("catj: {name}: {e}");
+                return false;
+            }
+        },
+    };
+
+    // Only a regular file can be a zip archive, since finding the members means seeking, which
+    // pipes can't do.
+    if let Some(file) = file.as_ref().filter(|file| file.metadata().is_ok_and(|m| m.is_file())) {
+        match Archive::new(file) {
+            Ok(Some(archive)) => return run_archive(archive, &name, mode, exts, options, bad, out),
+            Ok(None) => (),
+            Err(e) => {
+                eprintln!("catj: {name}: {e}");
+                return false;
+            }
         }
+    }
 
-        if state == 0 && !ds.is_empty() {
-            // New top-level value.
-            output.write_all(b"\n")?;
-            ds.pop();
-        }
+    match (mode, file) {
+        (Mode::Flat, file) => match file.or_else(stdin_file) {
+            Some(file) => {
+                let repair = options.repair;
+                let result = if mmap {
+                    // SAFETY: the usage says to give --no-mmap for files which may change.
+                    unsafe { catj::parse_file_mapped(&file, &mut *out, options) }
+                } else {
+                    catj::parse_file(&file, &mut *out, options)
+                };
+                flush(out);
+                if let Err(e) = result {
+                    report(&name, &e, repair);
+                    return false;
+                }
+                true
+            }
+            None => run_reader(io::stdin().lock(), &name, mode, options, bad, out),
+        },
+        (_, Some(file)) => run_reader(BufReader::new(file), &name, mode, options, bad, out),
+        (_, None) => run_reader(io::stdin().lock(), &name, mode, options, bad, out),
+    }
+}
 
-        if action > 0 {
-            do_action(action, ch, ds, ss, es, &mut output)?;
+fn run_archive(mut archive: Archive<&File>, name: &str, mode: &Mode, exts: &[String],
+               options: catj::Options, bad: &mut u64, out: &mut impl Write)
+    -> bool
+{
+    let mut ok = true;
+    for i in 0 .. archive.members().len() {
+        let member = format!("{name}!{}", archive.members()[i].name);
+        if !has_ext(&member, exts) {
+            continue;
         }
+        let mut options = options.clone();
+        options.prefix = format!("{member}:");
+        ok &= match archive.open(i) {
+            Ok(reader) => run_reader(BufReader::new(reader), &member, mode, options, bad, out),
+            Err(e) => {
+                eprintln!("catj: {member}: {e}");
+                false
+            }
+        };
+    }
+    ok
+}
 
-        if code == 0xFF {
-            state = stack.pop().unwrap();
-        } else {
-            state = code as u8;
-            return Ok(state);
-        }
+/// Print a parse error, and say so if the output was repaired.
+fn report(name: &str, e: &catj::Error, repair: bool) {
+    eprintln!("Error in {name} at {e}");
+    if repair && matches!(e.error, JsonError::Truncated) {
+        eprintln!("The output for {name} was completed as if the input had ended there.");
     }
 }
 
-fn do_action(action: u8, ch: u8, ds: &mut Vec<Value>, ss: &mut Vec<u8>, es: &mut String,
-             mut output: impl Write)
-    -> Result<(), JsonError>
+/// Like `run`, but for input which can only be read from start to end.
+fn run_reader(input: impl BufRead, name: &str, mode: &Mode, options: catj::Options,
+              bad: &mut u64, out: &mut impl Write)
+    -> bool
 {
-    match action {
-        0x1 => { // push list
-            ds.push(Value::List { index: 0 });
+    let input = match decompress(input) {
+        Ok(input) => input,
+        Err(e) => {
+            eprintln!("catj: {name}: {e}");
+            return false;
         }
-        0x2 => { // push object
-            ds.push(Value::Object { empty: true });
-        }
-        0x3 => { // pop & append
-            let v = ds.pop().unwrap();
-            if let Value::Terminal(v) = v {
-                print_path(ds, &mut output)?;
-                writeln!(&mut output, " = {v}")?;
-            }
-            match ds.last_mut() {
-                Some(Value::List { index }) => {
-                    *index += 1;
+    };
+    match mode {
+        Mode::Ungron => {
+            let result = ungron::ungron(input, &mut *out);
+            flush(out);
+            if let Err((line, e)) = result {
+                eprint!("Error in {name} at line {line}: ");
+                match e {
+                    UngronError::Syntax(e) => eprintln!("invalid syntax: {e}"),
+                    UngronError::Conflict(e) => eprintln!("conflicting paths: {e}"),
+                    UngronError::Sparse(e) => eprintln!("{e}"),
+                    UngronError::IO(e) => eprintln!("I/O error: {e}"),
                 }
-                other => panic!("expected list on top of the stack, not {:?}", other)
+                return false;
             }
         }
-        0x4 => { // pop pop & setitem
-            let v = ds.pop().unwrap();
-
-            let mut print_lhs = || -> io::Result<()> {
-                print_path(&*ds, &mut output)?;
-                output.write_all(b" = ")?;
-                Ok(())
-            };
-            match v {
-                Value::Terminal(v) => {
-                    print_lhs()?;
-                    writeln!(&mut output, "{v}")?;
+        Mode::Lines => {
+            let repair = options.repair;
+            let on_error = |e| report(name, &e, repair);
+            let result = catj::parse_lines(input, &mut *out, options, on_error);
+            flush(out);
+            match result {
+                Ok(0) => (),
+                Ok(n) => {
+                    *bad += n;
+                    return false;
                 }
-                Value::List { index: 0 } => {
-                    print_lhs()?;
-                    output.write_all(b"[]\n")?;
+                Err(e) => {
+                    eprintln!("I/O error reading {name}: {e}");
+                    return false;
                 }
-                Value::Object { empty: true } => {
-                    print_lhs()?;
-                    output.write_all(b"{}\n")?;
-                }
-                Value::List { index: _ } | Value::Object { empty: false } => {
-                    // already printed fields for these; nothing to do here.
-                }
-            }
-
-            // pop key, which we've now printed
-            ds.pop().unwrap();
-
-            if let Some(Value::Object { ref mut empty }) = ds.last_mut() {
-                *empty = false;
-            } else {
-                panic!("can't set a field on non-object: {:?}", ds.last());
             }
         }
-        0x5 => { // push null
-            ds.push(Terminal::Null.into());
-        }
-        0x6 => { // push true
-            ds.push(Terminal::Bool(true).into());
-        }
-        0x7 => { // push false
-            ds.push(Terminal::Bool(false).into());
-        }
-        0x8 => { // push string
-            let s = String::from_utf8(ss.clone())
-                .map_err(|e| JsonError::Unicode(e.utf8_error()))?;
-            ds.push(Terminal::String(s).into());
-            ss.clear();
-            es.clear();
-        }
-        0x9 | 0xA => { // push int, push float
-            ds.push(
-                Terminal::Number(
-                    str::from_utf8(ss)
-                        .map_err(JsonError::Unicode)?
-                        .to_owned()
-                ).into());
-            ss.clear();
-        }
-        0xB => { // push ch to ss
-            ss.push(ch);
-            if !es.is_empty() {
-                let bad = std::mem::take(es);
-                return Err(JsonError::InvalidEscape(bad));
-            }
-            es.clear();
-        }
-        0xC => { // push ch to es
-            if !ch.is_ascii_hexdigit() {
-                return Err(JsonError::InvalidEscape(
-                        format!("{:?} is not a hex digit", ch as char)));
+        Mode::Flat => {
+            let repair = options.repair;
+            let result = catj::parse_with(input, &mut *out, options);
+            flush(out);
+            if let Err(e) = result {
+                report(name, &e, repair);
+                return false;
             }
-            es.push(ch as char);
-        }
-        0xD => { // push escape
-            let c: u8 = match ch {
-                b'b' => 8,
-                b't' => b'\t', //9,
-                b'n' => b'\n', //10,
-                b'f' => 12,
-                b'r' => b'\r', //13,
-                _ => { return Err(JsonError::InvalidEscape(format!("\\{}", ch as char))); },
-            };
-            ss.push(c);
-            es.clear();
         }
-        0xE => { // push unicode code point
-            let codepoint = match es.len() {
-                8 => {
-                    let high_str = es.get(0..4)
-                        .ok_or_else(|| JsonError::InvalidEscape(
-                                format!("\\u{es}")))?;
-                    let high = u16::from_str_radix(high_str, 16)
-                        .map_err(|e| JsonError::InvalidEscape(
-                                format!("\\u{high_str}: {e}")))?;
-                    if !(0xD800 ..= 0xDBFF).contains(&high) {
-                        return Err(JsonError::InvalidEscape(
-                                format!("\\u{high_str}: unpaired high surrogate")));
-                    }
-
-                    let low_str = es.get(4..8)
-                        .ok_or_else(|| JsonError::InvalidEscape(
-                                format!("\\u{es}")))?;
-                    let low = u16::from_str_radix(low_str, 16)
-                        .map_err(|e| JsonError::InvalidEscape(
-                                format!("\\u{low_str}: {e}")))?;
-                    if !(0xDC00 ..= 0xDFFF).contains(&low) {
-                        return Err(JsonError::InvalidEscape(
-                                format!("\\u{low_str}: unpaired low surrogate")));
-                    }
-
-                    0x1_0000
-                        + (high as u32 - 0xD800) * 0x400
-                        + (low as u32 - 0xDC00)
-                }
-                4 => {
-                    let two_bytes = u16::from_str_radix(es, 16)
-                        .map_err(|e| JsonError::InvalidEscape(format!("\\u{es}: {e}")))?;
-                    if (0xD800..0xDBFF).contains(&two_bytes) {
-                        // We need to read another surrogate pair to do anything. Keep the 'es'
-                        // buffer unchanged, and let more characters accumulate in it.
-                        return Ok(());
-                    }
-                    u32::from(two_bytes)
-                }
-                _ => {
-                    return Err(JsonError::InvalidEscape(
-                            format!("\\u{es}: wrong number of digits")));
-                }
-            };
-
-            if let Some(u) = char::from_u32(codepoint) {
-                // push the UTF-8 bytes of it to the string buffer
-                let mut buf = [0u8; 4];
-                u.encode_utf8(&mut buf);
-                ss.extend(&buf[0 .. u.len_utf8()]);
-            } else {
-                return Err(JsonError::InvalidEscape(format!("\\u{es} ?")));
-            }
-            es.clear();
-        }
-        _ => panic!("JSON algorithm bug"),
     }
-    Ok(())
+    true
 }
 
-fn print_path(ds: &[Value], output: &mut impl Write) -> io::Result<()> {
-    for item in ds {
-        match item {
-            Value::Object { .. } => output.write_all(b".")?,
-            Value::List { index } => write!(output, "[{index}]")?,
-            Value::Terminal(term @ Terminal::String(s)) => {
-                if !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
-                    // Write it as a bare string
-                    write!(output, "{s}")?;
+fn main() {
+    let mut mode = Mode::Flat;
+    let mut options = catj::Options::default();
+    let mut exts = vec![];
+    let mut mmap = true;
+    let mut paths = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--version" | "-V" => {
+                eprintln!("catj-rss v{}", env!("CARGO_PKG_VERSION"));
+                eprintln!("Copyright 2019-2023 William R. Fraser");
+                eprintln!("https://github.com/wfraser/catj-rss");
+                exit(1);
+            }
+            "--ungron" | "-u" => mode = Mode::Ungron,
+            "--lines" | "-l" => mode = Mode::Lines,
+            "--include" | "-i" | "--exclude" | "-x" => {
+                let pattern = args.next().unwrap_or_else(|| usage());
+                let result = if arg.starts_with("--i") || arg == "-i" {
+                    options.filter.include(&pattern)
                 } else {
-                    // Write it with quotes, escapes, etc. as if it were a value
-                    write!(output, "{term}")?;
+                    options.filter.exclude(&pattern)
+                };
+                if let Err(e) = result {
+                    eprintln!("{e}");
+                    exit(1);
                 }
             }
-            Value::Terminal(other) => panic!("invalid item in a path: {:?}", other),
+            "--stop-early" => options.filter.stop_early(true),
+            "--repair" => options.repair = true,
+            "--json5" => options.lenient = true,
+            "--cbor" => options.format = catj::Format::Cbor,
+            "--msgpack" => options.format = catj::Format::MessagePack,
+            "--bson" => options.format = catj::Format::Bson,
+            "--yaml" => options.format = catj::Format::Yaml,
+            "--toml" => options.format = catj::Format::Toml,
+            "--hex" => options.hex = true,
+            "--no-mmap" => mmap = false,
+            "--root" => options.root = args.next().unwrap_or_else(|| usage()),
+            "--sort" => options.sort = true,
+            "--style" | "-s" => {
+                let style = args.next().unwrap_or_else(|| usage());
+                options.style = style.parse().unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    exit(1);
+                });
+            }
+            "--ext" | "-e" => {
+                let ext = args.next().unwrap_or_else(|| usage());
+                exts.push(ext.trim_start_matches('.').to_owned());
+            }
+            "--" => paths.extend(args.by_ref()),
+            "-" => paths.push(arg),
+            _ if !arg.starts_with('-') => paths.push(arg),
+            _ => usage(),
         }
     }
-    Ok(())
-}
 
-fn main() {
-    if let Some(arg) = std::env::args().nth(1) {
-        if arg == "--version" || arg == "-V" {
-            eprintln!("catj-rss v{}", env!("CARGO_PKG_VERSION"));
-            eprintln!("Copyright 2019-2023 William R. Fraser");
-            eprintln!("https://github.com/wfraser/catj-rss");
-        } else {
-            eprintln!("usage: {} [-V | --version] < some_file.json", std::env::args().next().unwrap());
-            eprintln!("Displays JSON files in a flat format.");
-            eprintln!("Reads from standard input, writes to standard output.");
-            eprintln!("see https://github.com/wfraser/catj-rss");
-        }
+    if options.style == catj::Style::JqStream
+        && (matches!(mode, Mode::Lines) || !options.filter.is_empty())
+    {
+        eprintln!("--style jq-stream can't be used with --lines, --include, or --exclude");
         exit(1);
     }
 
-    if let Err((line, col, e)) = parse(io::stdin().lock(), io::stdout().lock()) {
-        eprint!("Error in input at line {line} column {col}: ");
-        match e {
-            JsonError::Truncated => eprintln!("JSON truncated"),
-            JsonError::Syntax => eprintln!("invalid JSON syntax"),
-            JsonError::InvalidEscape(e) => eprintln!("invalid string escape sequence: {e}"),
-            JsonError::Unicode(e) => eprintln!("invalid UTF-8: {e}"),
-            JsonError::IO(e) => eprintln!("I/O error: {e}"),
-        }
-        exit(2);
+    if options.format != catj::Format::Json && !matches!(mode, Mode::Flat) {
+        eprintln!("--lines and --ungron can only be used with JSON input");
+        exit(1);
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
 
-    fn run(input: &str) -> String {
-        let mut input = io::Cursor::new(input);
-        let mut out = vec![];
-        parse(&mut input, &mut out).unwrap();
-        String::from_utf8(out).expect("bad utf8").trim().to_owned()
+    if options.sort && options.style != catj::Style::Gron {
+        eprintln!("--sort can only be used with --style gron");
+        exit(1);
     }
 
-    #[test]
-    fn test_empty() {
-        assert_eq!("", run(""));
-        assert_eq!("", run("{}"));
-        assert_eq!("", run("[]"));
-        assert_eq!("", run("[{}]"));
+    if exts.is_empty() {
+        exts.extend(["json".to_owned(), "json.gz".to_owned()]);
     }
 
-    #[test]
-    fn test_not_empty() {
-        assert_eq!(".foo = []", run(r#"{"foo": []}"#));
-        assert_eq!(".\"\" = []", run(r#"{"": []}"#));
+    let mut ok = true;
+    let mut inputs = vec![];
+    for path in &paths {
+        if path == "-" {
+            inputs.push(Input::Stdin);
+        } else if Path::new(path).is_dir() {
+            if let Err(e) = walk(Path::new(path), &exts, &mut inputs) {
+                eprintln!("catj: {path}: {e}");
+                ok = false;
+            }
+        } else {
+            inputs.push(Input::File(PathBuf::from(path)));
+        }
     }
-
-    #[test]
-    fn test_simple() {
-        assert_eq!(".foo = \"bar\"", run(r#"{"foo": "bar"}"#));
+    let prefixes = paths.len() > 1
+        || inputs.len() > 1
+        || paths.iter().any(|p| Path::new(p).is_dir());
+    if paths.is_empty() {
+        inputs.push(Input::Stdin);
     }
 
-    #[test]
-    fn test_non_ident_keys() {
-        assert_eq!(".bare = \"a\"", run(r#"{"bare": "a"}"#));
-        assert_eq!(".\"quoted now\" = \"b\"", run(r#"{"quoted now": "b"}"#));
+    // Standard output is line buffered, which would mean a system call for every line.
+    let mut out = BufWriter::with_capacity(OUTPUT_BUFFER_SIZE, io::stdout().lock());
+
+    let mut bad = 0;
+    for input in &inputs {
+        let mut options = options.clone();
+        if prefixes {
+            options.prefix = input.prefix();
+        }
+        ok &= run(input, &mode, &exts, options, mmap, &mut bad, &mut out);
     }
 
-    #[test]
-    fn test_utf8() {
-        assert_eq!(".\"⚙🖥\" = \"🦀\"", run(r#"{"⚙🖥": "🦀"}"#));
+    if bad != 0 {
+        eprintln!("{bad} bad record{}", if bad == 1 { "" } else { "s" });
+    }
+    if !ok {
+        exit(2);
     }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use super::*;
+    use std::os::fd::AsRawFd;
 
     #[test]
-    fn test_escapes() {
-        assert_eq!(".smile = \"😊\"", run(r#"{"smile": "\ud83d\ude0a"}"#));
-        assert_eq!(".\"\\b_backspace\" = \"carriage\\r\\nreturn\"", run(r#"{"\b_backspace": "carriage\r\nreturn"}"#));
+    fn test_pipe() {
+        // Like `catj <(echo '{"a": 1}')`, which can't be checked for being a zip archive.
+        let (reader, mut writer) = io::pipe().unwrap();
+        writer.write_all(b"{\"a\": 1}").unwrap();
+        drop(writer);
+        let input = Input::File(PathBuf::from(format!("/dev/fd/{}", reader.as_raw_fd())));
+        let mut out = vec![];
+        assert!(run(&input, &Mode::Flat, &[], catj::Options::default(), true, &mut 0, &mut out));
+        assert_eq!(String::from_utf8(out).unwrap(), ".a = 1\n\n");
     }
 }
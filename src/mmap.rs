@@ -0,0 +1,68 @@
+//! Read-only memory maps of files, using the C library directly rather than a dependency.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64)
+        -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+}
+
+const PROT_READ: c_int = 1;
+const MAP_PRIVATE: c_int = 2;
+
+pub struct Mmap {
+    ptr: *mut c_void,
+    len: usize,
+}
+
+// The mapping is read-only, so it can be shared like a `&[u8]`.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Map the whole file.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may change the file while it's mapped. If it's written to, the contents seen
+    /// through the map can change underneath the `&[u8]`, which is undefined behaviour, and if
+    /// it's truncated, reading past its new end kills the process with `SIGBUS`.
+    pub unsafe fn new(file: &File) -> io::Result<Self> {
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::other("file too large to map"))?;
+        if len == 0 {
+            // Zero-length maps aren't allowed.
+            return Ok(Mmap { ptr: ptr::null_mut(), len });
+        }
+        let ptr = unsafe { mmap(ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe { munmap(self.ptr, self.len) };
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Decoding MessagePack into the same values JSON has.
+//!
+//! Binary values become base64 (or hex) strings, and map keys which aren't strings are written as
+//! JSON, like `.1` or `."[1,2]"`. Extension values are shown as an object with one key, like
+//! `.x."ext(5)" = "AQI="`, and timestamps (extension type -1) are written as RFC 3339 strings. A
+//! series of values is read like a series of top-level JSON values.
+
+use std::io::BufRead;
+
+use crate::decode::{encode_bytes, float, timestamp, Builder, Bytes};
+use crate::{Error, JsonError, Sink, Terminal};
+
+/// How deeply arrays and maps can be nested within a map key.
+const MAX_KEY_DEPTH: usize = 64;
+
+/// The extension type of timestamps.
+const TIMESTAMP: i8 = -1;
+
+/// An array or map which is being decoded.
+enum Frame {
+    /// An array, with how many more items it has.
+    List(u64),
+    /// A map, with how many more keys it has, and whether a key is next rather than a value.
+    Map { left: u64, key: bool },
+}
+
+/// What a value's first byte says it is.
+enum Head {
+    Scalar(Terminal),
+    Array(u64),
+    Map(u64),
+    Ext(i8, Vec<u8>),
+}
+
+struct Decoder<R, S> {
+    input: Bytes<R>,
+    builder: Builder<S>,
+    frames: Vec<Frame>,
+    start: u64, // the offset of the value being decoded
+    hex: bool,
+}
+
+/// Decode a series of MessagePack values and give them to the sink. Binary values are written in
+/// hex if `hex` is set, or base64 otherwise. If `repair` is set, the arrays and maps which are
+/// open when the input is cut off are closed.
+pub(crate) fn decode<S: Sink>(input: impl BufRead, sink: S, hex: bool, repair: bool)
+    -> Result<(), Error>
+{
+    let mut decoder = Decoder {
+        input: Bytes::new(input),
+        builder: Builder::new(sink),
+        frames: vec![],
+        start: 0,
+        hex,
+    };
+    let result = decoder.run();
+    decoder.builder.finish(result, decoder.start, repair)
+}
+
+impl<R: BufRead, S: Sink> Decoder<R, S> {
+    fn run(&mut self) -> Result<(), JsonError> {
+        loop {
+            while let Some(Frame::List(0) | Frame::Map { left: 0, key: true }) = self.frames.last() {
+                self.frames.pop();
+                self.builder.end()?;
+                self.value_done();
+            }
+            if self.builder.done() || (self.frames.is_empty() && self.input.at_end()?) {
+                return Ok(());
+            }
+
+            self.start = self.input.offset;
+            let head = self.head()?;
+            match self.frames.last_mut() {
+                Some(Frame::List(left)) => *left -= 1,
+                Some(Frame::Map { left, key: key @ true }) => {
+                    *left -= 1;
+                    *key = false;
+                    let key = self.key(head, 0)?;
+                    self.builder.key(key)?;
+                    continue;
+                }
+                _ => (),
+            }
+            match head {
+                Head::Scalar(value) => {
+                    self.builder.scalar(value)?;
+                    self.value_done();
+                }
+                Head::Array(len) => {
+                    self.builder.start_list()?;
+                    self.frames.push(Frame::List(len));
+                }
+                Head::Map(len) => {
+                    self.builder.start_object()?;
+                    self.frames.push(Frame::Map { left: len, key: true });
+                }
+                Head::Ext(kind, data) => {
+                    let value = self.ext(kind, &data)?;
+                    if kind != TIMESTAMP {
+                        self.builder.start_object()?;
+                        self.builder.key(format!("ext({kind})"))?;
+                        self.builder.scalar(value)?;
+                        self.builder.end()?;
+                    } else {
+                        self.builder.scalar(value)?;
+                    }
+                    self.value_done();
+                }
+            }
+        }
+    }
+
+    /// A value is finished, so if it was in a map, a key is next.
+    fn value_done(&mut self) {
+        if let Some(Frame::Map { key, .. }) = self.frames.last_mut() {
+            *key = true;
+        }
+    }
+
+    /// Read the start of a value: all of it, unless it's an array or map.
+    fn head(&mut self) -> Result<Head, JsonError> {
+        let number = |n: &dyn ToString| Head::Scalar(Terminal::Number(n.to_string()));
+        let first = self.input.u8()?;
+        Ok(match first {
+            0x00 ..= 0x7F => number(&first),
+            0x80 ..= 0x8F => Head::Map(u64::from(first & 0x0F)),
+            0x90 ..= 0x9F => Head::Array(u64::from(first & 0x0F)),
+            0xA0 ..= 0xBF => Head::Scalar(self.text(u64::from(first & 0x1F))?),
+            0xC0 => Head::Scalar(Terminal::Null),
+            0xC2 => Head::Scalar(Terminal::Bool(false)),
+            0xC3 => Head::Scalar(Terminal::Bool(true)),
+            0xC4 ..= 0xC6 => {
+                let len = self.length(first - 0xC4)?;
+                let data = self.input.vec(len)?;
+                Head::Scalar(Terminal::String(encode_bytes(&data, self.hex)))
+            }
+            0xC7 ..= 0xC9 => {
+                let len = self.length(first - 0xC7)?;
+                let kind = self.input.u8()? as i8;
+                Head::Ext(kind, self.input.vec(len)?)
+            }
+            0xCA => Head::Scalar(float(f32::from_be_bytes(self.input.array()?))),
+            0xCB => Head::Scalar(float(f64::from_be_bytes(self.input.array()?))),
+            0xCC => number(&self.input.u8()?),
+            0xCD => number(&u16::from_be_bytes(self.input.array()?)),
+            0xCE => number(&u32::from_be_bytes(self.input.array()?)),
+            0xCF => number(&u64::from_be_bytes(self.input.array()?)),
+            0xD0 => number(&(self.input.u8()? as i8)),
+            0xD1 => number(&i16::from_be_bytes(self.input.array()?)),
+            0xD2 => number(&i32::from_be_bytes(self.input.array()?)),
+            0xD3 => number(&i64::from_be_bytes(self.input.array()?)),
+            0xD4 ..= 0xD8 => {
+                let kind = self.input.u8()? as i8;
+                Head::Ext(kind, self.input.vec(1 << (first - 0xD4))?)
+            }
+            0xD9 ..= 0xDB => {
+                let len = self.length(first - 0xD9)?;
+                Head::Scalar(self.text(len)?)
+            }
+            0xDC | 0xDD => Head::Array(self.length(first - 0xDC + 1)?),
+            0xDE | 0xDF => Head::Map(self.length(first - 0xDE + 1)?),
+            0xE0 ..= 0xFF => number(&(first as i8)),
+            0xC1 => return Err(JsonError::Syntax("invalid MessagePack value 0xc1".to_owned())),
+        })
+    }
+
+    /// Read a big-endian length of 1, 2 or 4 bytes, for `size` 0, 1 or 2.
+    fn length(&mut self, size: u8) -> Result<u64, JsonError> {
+        Ok(match size {
+            0 => u64::from(self.input.u8()?),
+            1 => u64::from(u16::from_be_bytes(self.input.array()?)),
+            _ => u64::from(u32::from_be_bytes(self.input.array()?)),
+        })
+    }
+
+    fn text(&mut self, len: u64) -> Result<Terminal, JsonError> {
+        let s = String::from_utf8(self.input.vec(len)?)
+            .map_err(|e| JsonError::Unicode(e.utf8_error()))?;
+        Ok(Terminal::String(s))
+    }
+
+    /// The value of an extension: the time for a timestamp, and the data otherwise.
+    fn ext(&self, kind: i8, data: &[u8]) -> Result<Terminal, JsonError> {
+        if kind != TIMESTAMP {
+            return Ok(Terminal::String(encode_bytes(data, self.hex)));
+        }
+        let (secs, nanos) = match data.len() {
+            4 => (i64::from(u32::from_be_bytes(data.try_into().unwrap())), 0),
+            8 => {
+                let n = u64::from_be_bytes(data.try_into().unwrap());
+                ((n & 0x3_FFFF_FFFF) as i64, (n >> 34) as u32)
+            }
+            12 => (i64::from_be_bytes(data[4 ..].try_into().unwrap()),
+                   u32::from_be_bytes(data[.. 4].try_into().unwrap())),
+            n => return Err(JsonError::Syntax(format!("invalid {n}-byte MessagePack timestamp"))),
+        };
+        if nanos >= 1_000_000_000 {
+            return Err(JsonError::Syntax("invalid MessagePack timestamp".to_owned()));
+        }
+        Ok(Terminal::String(timestamp(secs, nanos)))
+    }
+
+    /// The text of a map key: a string or binary value as it would be written as a value,
+    /// without the quotes, and anything else as JSON.
+    fn key(&mut self, head: Head, depth: usize) -> Result<String, JsonError> {
+        match head {
+            Head::Scalar(Terminal::String(s)) => Ok(s),
+            head => {
+                let mut s = String::new();
+                self.key_json(head, &mut s, depth)?;
+                Ok(s)
+            }
+        }
+    }
+
+    /// Write a value in a map key as JSON, with extensions written like `ext(5, "AQI=")`.
+    fn key_json(&mut self, head: Head, out: &mut String, depth: usize) -> Result<(), JsonError> {
+        if depth > MAX_KEY_DEPTH {
+            return Err(JsonError::Syntax("MessagePack map key nested too deeply".to_owned()));
+        }
+        match head {
+            Head::Scalar(value) => out.push_str(&value.to_string()),
+            Head::Array(len) => {
+                out.push('[');
+                for i in 0 .. len {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    let item = self.head()?;
+                    self.key_json(item, out, depth + 1)?;
+                }
+                out.push(']');
+            }
+            Head::Map(len) => {
+                out.push('{');
+                for i in 0 .. len {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    let key = self.head()?;
+                    let key = self.key(key, depth + 1)?;
+                    out.push_str(&Terminal::String(key).to_string());
+                    out.push(':');
+                    let value = self.head()?;
+                    self.key_json(value, out, depth + 1)?;
+                }
+                out.push('}');
+            }
+            Head::Ext(kind, data) => {
+                let value = self.ext(kind, &data)?;
+                if kind == TIMESTAMP {
+                    out.push_str(&value.to_string());
+                } else {
+                    out.push_str(&format!("ext({kind}, {value})"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{parse_with, Format, Options};
+
+    fn run(input: &[u8], options: Options) -> Result<String, crate::Error> {
+        let mut out = vec![];
+        let options = Options { format: Format::MessagePack, ..options };
+        parse_with(input, &mut out, options)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_msgpack() {
+        // {"a": [1, -3, 300, -129, 1.5, nil], 2: bin(01 02), "e": ext(5, 01), "t": timestamp(0),
+        //  [1]: {}} "x"
+        let input = b"\x85\xa1\x61\x96\x01\xfd\xcd\x01\x2c\xd1\xff\x7f\xcb\x3f\xf8\x00\x00\x00\x00\
+            \x00\x00\xc0\x02\xc4\x02\x01\x02\xa1\x65\xd4\x05\x01\xa1\x74\xd6\xff\x51\x4b\x67\xb0\
+            \x91\x01\x80\xa1\x78";
+        assert_eq!(run(input, Options::default()).unwrap(), "\
+            .a[0] = 1\n\
+            .a[1] = -3\n\
+            .a[2] = 300\n\
+            .a[3] = -129\n\
+            .a[4] = 1.5\n\
+            .a[5] = null\n\
+            .2 = \"AQI=\"\n\
+            .e.\"ext(5)\" = \"AQ==\"\n\
+            .t = \"2013-03-21T20:04:00Z\"\n\
+            .\"[1]\" = {}\n\
+            \n\
+            \n");
+
+        let options = Options { hex: true, style: crate::Style::Js, ..Options::default() };
+        assert_eq!(run(b"\x92\xc4\x01\xff\xc3", options).unwrap(),
+                   "json = [];\njson[0] = \"ff\";\njson[1] = true;\n\n");
+
+        let e = run(b"\x81\xa1\x6b\x92\x01", Options::default()).unwrap_err();
+        assert_eq!((e.offset, e.path.to_string(), e.error.to_string()),
+                   (5, ".k[1]".to_owned(), "input truncated".to_owned()));
+    }
+}
@@ -0,0 +1,304 @@
+//! Parsing one large top-level list on several threads at once.
+//!
+//! The input is cut into chunks just after commas, guessing that each one falls in between two
+//! elements of the list. Every chunk is parsed and written on its own thread, numbering the list's
+//! elements from 0, and noting where those numbers are in its output so they can be changed once
+//! it's known how many elements came before it. A guess is known to be right when the chunk
+//! before it ends in between elements. If it's wrong (say the comma was in a string), that part of
+//! the input is parsed again on one thread, until it gets back to a place in between elements.
+
+use std::io::{self, Write};
+use std::thread;
+
+use crate::tables::LIST_NEXT;
+use crate::{Error, FlatWriter, Options, Parser, Sink, Terminal, Value};
+
+/// Writes a chunk's output, noting where the indices of the top-level list are in it.
+struct ChunkWriter {
+    writer: FlatWriter<Vec<u8>>,
+    indices: Vec<(usize, u64)>, // where each index is written in the output, and what it is
+    new_document: bool,         // whether another top-level value was started
+}
+
+impl ChunkWriter {
+    fn new(options: &Options) -> Self {
+        ChunkWriter { writer: FlatWriter::new(vec![], options.clone()), indices: vec![],
+                      new_document: false }
+    }
+
+    /// Note where the index is in the line written since `start`, if it's one of the list's.
+    fn note_index(&mut self, start: usize, path: &[Value]) {
+        let Some(&Value::List { index }) = path.first() else { return };
+        if self.new_document || self.writer.output.len() == start {
+            return;
+        }
+        let mut at = start + self.writer.prefix.len();
+        if self.writer.options.style.statements() {
+            at += self.writer.options.root.len();
+        }
+        // The path starts with the list's segment, like `[12]` or `/12`.
+        at += self.writer.path.iter().position(u8::is_ascii_digit).expect("a list index");
+        self.indices.push((at, index));
+    }
+
+    /// Write the output, with `base` added to each of the list's indices.
+    fn write_to(&self, output: &mut impl Write, base: u64) -> io::Result<()> {
+        let chunk = &self.writer.output;
+        if base == 0 {
+            return output.write_all(chunk);
+        }
+        let mut done = 0;
+        for &(at, index) in &self.indices {
+            output.write_all(&chunk[done .. at])?;
+            write!(output, "{}", base + index)?;
+            done = at + index.checked_ilog10().map_or(1, |n| n as usize + 1);
+        }
+        output.write_all(&chunk[done ..])
+    }
+}
+
+impl Sink for ChunkWriter {
+    fn leaf(&mut self, ds: &[Value], value: Terminal) -> io::Result<()> {
+        let start = self.writer.output.len();
+        self.writer.leaf(ds, value)?;
+        self.note_index(start, ds);
+        Ok(())
+    }
+
+    fn next_document(&mut self) -> io::Result<()> {
+        self.new_document = true;
+        self.writer.next_document()
+    }
+
+    fn pushed(&mut self, ds: &[Value]) {
+        self.writer.pushed(ds);
+    }
+
+    fn popped(&mut self, len: usize) {
+        self.writer.popped(len);
+    }
+
+    fn next_index(&mut self, ds: &[Value]) {
+        self.writer.next_index(ds);
+    }
+
+    fn start_container(&mut self, ds: &[Value]) -> io::Result<()> {
+        let start = self.writer.output.len();
+        self.writer.start_container(ds)?;
+        self.note_index(start, &ds[.. ds.len() - 1]);
+        Ok(())
+    }
+
+    fn top_level(&mut self, value: Terminal) -> io::Result<()> {
+        self.writer.top_level(value)
+    }
+
+    fn end(&mut self) -> io::Result<()> {
+        self.writer.end()
+    }
+}
+
+impl Parser {
+    /// A parser which is just after a comma in a top-level list, having seen `index` elements,
+    /// `offset` bytes into the input.
+    fn in_list(index: u64, line: u64, col: u64, offset: u64) -> Self {
+        let mut parser = Parser::new();
+        parser.stack.push(0); // where to go after the list: back to the top level
+        parser.state = LIST_NEXT;
+        parser.ds.push(Value::List { index });
+        parser.line = line;
+        parser.col = col;
+        parser.offset = offset;
+        parser
+    }
+
+    fn between_elements(&self) -> bool {
+        self.state == LIST_NEXT && self.stack == [0] && self.ds.len() == 1
+    }
+
+    fn index(&self) -> u64 {
+        match self.ds.first() {
+            Some(Value::List { index }) => *index,
+            _ => 0,
+        }
+    }
+}
+
+/// Where a chunk starts, and the state to start parsing it in.
+#[derive(Debug, Clone, Copy)]
+struct Start {
+    pos: usize,
+    index: u64,
+    line: u64,
+    col: u64,
+}
+
+impl Start {
+    fn parser(&self) -> Parser {
+        if self.pos == 0 {
+            Parser::new()
+        } else {
+            Parser::in_list(self.index, self.line, self.col, self.pos as u64)
+        }
+    }
+
+    /// Where the parser has got to after starting here and parsing up to `pos`.
+    fn after(&self, parser: &Parser, pos: usize) -> Start {
+        Start { pos, index: parser.index(), line: parser.line, col: parser.col }
+    }
+}
+
+/// The first guess at a chunk boundary at or after `from`.
+fn next_boundary(data: &[u8], from: usize) -> Option<usize> {
+    let i = data.get(from ..)?.iter().position(|&c| c == b',')?;
+    Some(from + i + 1)
+}
+
+/// Parse the input, which should be a list, and write its output in the same order as if it were
+/// parsed on one thread.
+pub(crate) fn parse(data: &[u8], output: &mut impl Write, options: &Options, chunk_size: usize,
+                    threads: usize)
+    -> Result<(), Error>
+{
+    let mut start = Start { pos: 0, index: 0, line: 1, col: 0 };
+    loop {
+        // Guess where the next few chunks end.
+        let mut ends = vec![];
+        let mut pos = start.pos;
+        while ends.len() < threads {
+            pos = next_boundary(data, pos + chunk_size).unwrap_or(data.len());
+            ends.push(pos);
+            if pos == data.len() {
+                break;
+            }
+        }
+
+        // Parse and write each chunk, assuming it starts in between elements.
+        let checked: Vec<Result<(Parser, ChunkWriter), Error>> = thread::scope(|scope| {
+            let handles: Vec<_> = ends.iter()
+                .enumerate()
+                .map(|(i, &end)| {
+                    let begin = if i == 0 { start.pos } else { ends[i - 1] };
+                    let mut parser = if i == 0 { start.parser() } else { Parser::in_list(0, 1, 0, 0) };
+                    scope.spawn(move || {
+                        let mut writer = ChunkWriter::new(options);
+                        if begin != 0 {
+                            writer.pushed(&parser.ds);
+                        }
+                        parser.feed(&data[begin .. end], &mut writer)?;
+                        if end == data.len() {
+                            parser.finish(&mut writer)?;
+                            writer.end().expect("writing to a Vec can't fail");
+                        }
+                        Ok((parser, writer))
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().expect("parser thread panicked")).collect()
+        });
+
+        // Find out which guesses were right, and where each good chunk starts.
+        let mut starts = vec![start];
+        for (i, result) in checked.iter().enumerate() {
+            let end = ends[i];
+            let good = match result {
+                Ok(_) if end == data.len() => true,
+                Ok((parser, _)) => parser.between_elements(),
+                Err(_) => false,
+            };
+            if !good {
+                break;
+            }
+            let (parser, writer) = result.as_ref().unwrap();
+            let begin = starts[i];
+            let next = if i == 0 {
+                begin.after(parser, end)
+            } else {
+                // The counts are relative to the start of the chunk, unless the list ended and
+                // another one started.
+                Start {
+                    pos: end,
+                    index: if writer.new_document { 0 } else { begin.index } + parser.index(),
+                    line: begin.line + parser.line - 1,
+                    col: if parser.line == 1 { begin.col + parser.col } else { parser.col },
+                }
+            };
+            starts.push(next);
+        }
+        let good = starts.len() - 1;
+
+        // Write the output of the good chunks, the first of which was started with the right
+        // index already.
+        for (i, result) in checked.iter().take(good).enumerate() {
+            let (_, writer) = result.as_ref().unwrap();
+            let base = if i == 0 { 0 } else { starts[i].index };
+            writer.write_to(output, base)
+                .map_err(|e| start.parser().error(e.into()))?;
+        }
+
+        start = starts[good];
+        if start.pos == data.len() {
+            return Ok(());
+        }
+        if good == ends.len() {
+            continue;
+        }
+
+        // A guess was wrong, or there's an error. Go one chunk at a time from the last good
+        // chunk, until the parser is in between elements again.
+        let mut parser = start.parser();
+        let mut writer = FlatWriter::new(&mut *output, options.clone());
+        if start.pos != 0 {
+            writer.pushed(&parser.ds);
+        }
+        loop {
+            let end = next_boundary(data, start.pos + chunk_size).unwrap_or(data.len());
+            parser.feed(&data[start.pos .. end], &mut writer)?;
+            start = start.after(&parser, end);
+            if end == data.len() {
+                return parser.finish_and_end(&mut writer, options.repair);
+            }
+            if parser.between_elements() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str;
+
+    fn compare(input: &str) {
+        for (style, prefix) in [("catj", ""), ("js", ""), ("gron", ""), ("pointer", "in1:")] {
+            let options = Options { style: style.parse().unwrap(), prefix: prefix.to_owned(),
+                                    root: "j2".to_owned(), ..Options::default() };
+            let mut expected = vec![];
+            let expected_result = crate::parse_with(input.as_bytes(), &mut expected, options.clone());
+            for chunk_size in [1, 5, 16, 1000] {
+                let mut out = vec![];
+                let result = parse(input.as_bytes(), &mut out, &options, chunk_size, 3);
+                assert_eq!(str::from_utf8(&expected).unwrap(), str::from_utf8(&out).unwrap());
+                match (&expected_result, result) {
+                    (Ok(()), Ok(())) => (),
+                    (Err(a), Err(b)) => {
+                        assert_eq!((a.line, a.col, a.offset), (b.line, b.col, b.offset));
+                    }
+                    (a, b) => panic!("{a:?} != {b:?}"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_same_output() {
+        compare(r#"[{"a": 1, "b": [2, 3]}, "x, y", [], {"c, [": {"d": null}}, 4.5, true] [1, 2]"#);
+        compare("[\n  1,\n  \"a,\n  b\",\n  {\"c\": [2, 3]}\n]\n");
+        compare("[1, 2, {\"a\": [3,\n 4]}, 5,\n 6x, 7]");
+        compare("[1, 2, 3");
+        compare("[]");
+        let items: Vec<String> = (0 .. 120).map(|i| format!("{{\"a{i}\": [{i}, []]}}")).collect();
+        compare(&format!("[{}]", items.join(", ")));
+    }
+}
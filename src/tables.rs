@@ -45,3 +45,401 @@ pub const STATES: [[u16; 28]; 42] = [
 ];
 pub const GOTOS: [u8; 42] = [0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 15, 255, 15, 255, 19, 255, 21, 255, 23, 255, 21, 255, 255, 26, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255];
 pub const CATCODE: [u8; 127] = [0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 3, 4, 5, 6, 7, 8, 9, 9, 9, 9, 9, 9, 9, 9, 9, 10, 0, 0, 0, 0, 0, 0, 11, 11, 11, 11, 12, 11, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 13, 14, 15, 0, 0, 0, 16, 17, 11, 11, 18, 19, 0, 0, 0, 0, 0, 20, 0, 21, 0, 0, 0, 22, 23, 24, 25, 0, 0, 0, 0, 0, 26, 0, 27, 0];
+
+// States for the literals null, true and false, from the first letter to the last.
+pub const NULL: u8 = 1;
+pub const FALSE_END: u8 = 13;
+
+// States in a list: after the opening bracket, after an element, and just after a comma.
+pub const LIST_START: u8 = 14;
+pub const LIST_ELEMENT: u8 = 15;
+pub const LIST_NEXT: u8 = 16;
+
+// States in an object: after the opening brace, the first key, its colon, and a value, and then
+// after a comma, the next key, and its colon.
+pub const OBJECT_START: u8 = 18;
+pub const OBJECT_KEY: u8 = 19;
+pub const OBJECT_COLON: u8 = 20;
+pub const OBJECT_VALUE: u8 = 21;
+pub const OBJECT_NEXT: u8 = 22;
+pub const OBJECT_NEXT_KEY: u8 = 23;
+pub const OBJECT_NEXT_COLON: u8 = 24;
+
+// States entered on the closing bracket of a list or object.
+pub const LIST_END: u8 = 17;
+pub const OBJECT_END: u8 = 25;
+
+// States in a string escape: after the backslash, and the digits of a `\u` escape.
+pub const ESCAPE: u8 = 27;
+pub const UNICODE_ESCAPE: u8 = 28;
+pub const UNICODE_ESCAPE_END: u8 = 31;
+// After the last digit, where the code point is pushed.
+pub const CODE_POINT: u8 = 32;
+
+// States in a number which need more to come: after a decimal point, an exponent's `e`, its sign,
+// and a leading minus sign. `ZERO` is after a leading zero, which is a whole number.
+pub const FRACTION_START: u8 = 35;
+pub const EXPONENT_START: u8 = 37;
+pub const EXPONENT_SIGN: u8 = 38;
+pub const ZERO: u8 = 40;
+pub const MINUS: u8 = 41;
+
+// States which loop on ordinary bytes: inside a string, and the digits of a number's integer part,
+// fraction, and exponent.
+pub const STRING: u8 = 26;
+pub const INTEGER: u8 = 34;
+pub const FRACTION: u8 = 36;
+pub const EXPONENT: u8 = 39;
+
+// What each character category is, for error messages. Some categories are several characters
+// (like the digits 1 to 9), but those are only ever expected together with others, and are
+// described as a group, like "a digit", by `expected` in lib.rs.
+pub const CATEGORY_NAMES: [&str; CATEGORIES] = [
+    "a control character", "whitespace", "'\"'", "'+'", "','", "'-'", "'.'", "'/'", "'0'",
+    "'1'-'9'", "':'", "a hex digit", "'E'", "'['", "'\\'", "']'", "'a'", "'b'", "'e'", "'f'",
+    "'l'", "'n'", "'r'", "'s'", "'t'", "'u'", "'{'", "'}'",
+    "'\''", "'*'", "a line break", "'x'", "'I'", "'N'", "'i'", "'y'", "a letter",
+];
+
+// Where the parser is in each state, for error messages.
+pub const STATE_CONTEXTS: [&str; LENIENT_STATE_COUNT] = [
+    "",
+    "in 'null'", "in 'null'", "in 'null'", "in 'null'",
+    "in 'true'", "in 'true'", "in 'true'", "in 'true'",
+    "in 'false'", "in 'false'", "in 'false'", "in 'false'", "in 'false'",
+    "after '['", "after list element", "after ',' in list", "after ']'",
+    "after '{'", "after object key", "after ':'", "after object value", "after ',' in object",
+    "after object key", "after ':'", "after '}'",
+    "in string", "after '\\' in string",
+    "in '\\u' escape", "in '\\u' escape", "in '\\u' escape", "in '\\u' escape", "in '\\u' escape",
+    "after string",
+    "in number", "after '.' in number", "in number", "in exponent", "after exponent sign",
+    "in exponent", "in number", "after '-'",
+    // The rest are only in the lenient grammar.
+    "in string", "after '\\' in string", "in string", "in string",
+    "in '\\x' escape", "in '\\x' escape",
+    "after '/'", "in comment", "in comment", "in comment", "after comment",
+    "in object key", "after '0x'", "in hex number",
+    "in 'Infinity'", "in 'Infinity'", "in 'Infinity'", "in 'Infinity'", "in 'Infinity'",
+    "in 'Infinity'", "in 'Infinity'", "in 'Infinity'",
+    "in 'NaN'", "in 'NaN'", "in 'NaN'",
+    "after '+'",
+];
+
+// The number of character categories. The strict table only uses the first 28; the rest are split
+// out of "other characters" and whitespace for the lenient grammar.
+pub const CATEGORIES: usize = 37;
+
+// Character categories, for building the lenient table.
+mod cat {
+    pub const WHITESPACE: usize = 1;
+    pub const DOUBLE_QUOTE: usize = 2;
+    pub const PLUS: usize = 3;
+    pub const DOT: usize = 6;
+    pub const SLASH: usize = 7;
+    pub const ZERO: usize = 8;
+    pub const DIGIT: usize = 9;
+    pub const BACKSLASH: usize = 14;
+    pub const RIGHT_BRACKET: usize = 15;
+    pub const A: usize = 16;
+    pub const F: usize = 19;
+    pub const N: usize = 21;
+    pub const T: usize = 24;
+    pub const RIGHT_BRACE: usize = 27;
+    pub const SINGLE_QUOTE: usize = 28;
+    pub const STAR: usize = 29;
+    pub const NEWLINE: usize = 30;
+    pub const X: usize = 31;
+    pub const CAPITAL_I: usize = 32;
+    pub const CAPITAL_N: usize = 33;
+    pub const I: usize = 34;
+    pub const Y: usize = 35;
+    pub const LETTER: usize = 36;
+
+    pub const HEX_DIGITS: [usize; 8] = [8, 9, 11, 12, 16, 17, 18, 19];
+    // Letters, `$`, `_` and non-ASCII characters, which can start an unquoted key.
+    pub const KEY: [usize; 18] = [11, 12, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 31, 32, 33, 34, 35,
+                                  36];
+}
+
+/// A state table, with its gotos and the categories of all the bytes.
+pub struct Grammar {
+    pub states: &'static [[u16; CATEGORIES]],
+    pub gotos: &'static [u8],
+    pub catcode: [u8; 256],
+}
+
+/// Standard JSON.
+pub static STRICT: Grammar = Grammar {
+    states: &widen(STATES),
+    gotos: &GOTOS,
+    catcode: strict_catcode(),
+};
+
+/// JSON5, which is also a superset of JSON with comments (JSONC).
+pub static LENIENT: Grammar = Grammar {
+    states: &LENIENT_STATES,
+    gotos: &LENIENT_GOTOS,
+    catcode: LENIENT_CATCODE,
+};
+
+const fn widen(states: [[u16; 28]; 42]) -> [[u16; CATEGORIES]; 42] {
+    let mut wide = [[0xffff; CATEGORIES]; 42];
+    let mut state = 0;
+    while state < 42 {
+        let mut cat = 0;
+        while cat < 28 {
+            wide[state][cat] = states[state][cat];
+            cat += 1;
+        }
+        state += 1;
+    }
+    wide
+}
+
+const fn strict_catcode() -> [u8; 256] {
+    let mut catcode = [0; 256];
+    let mut ch = 0;
+    while ch < CATCODE.len() {
+        catcode[ch] = CATCODE[ch];
+        ch += 1;
+    }
+    catcode
+}
+
+// States only in the lenient grammar.
+pub const SQ_STRING: u8 = 42;
+pub const SQ_ESCAPE: u8 = 43;
+// After a backslash and a carriage return or line feed, which continues a string on the next line.
+pub const DQ_CONTINUATION: u8 = 44;
+pub const SQ_CONTINUATION: u8 = 45;
+pub const HEX_ESCAPE: u8 = 46;
+pub const HEX_ESCAPE_END: u8 = 47;
+pub const COMMENT_START: u8 = 48;
+pub const LINE_COMMENT: u8 = 49;
+pub const BLOCK_COMMENT: u8 = 50;
+pub const BLOCK_COMMENT_STAR: u8 = 51;
+pub const COMMENT_END: u8 = 52;
+pub const IDENTIFIER: u8 = 53;
+pub const HEX_START: u8 = 54;
+pub const HEX_NUMBER: u8 = 55;
+// After each letter of `Infinity` and `NaN`.
+pub const INFINITY: u8 = 56;
+pub const INFINITY_END: u8 = 63;
+pub const NAN: u8 = 64;
+pub const NAN_END: u8 = 66;
+pub const PLUS: u8 = 67;
+
+pub const LENIENT_STATE_COUNT: usize = 68;
+
+// Actions only in the lenient grammar.
+// Push a number, written the way JSON would have it.
+pub const PUSH_JSON5_NUMBER: u8 = 0x0F;
+// Push the current state, to come back to at the end of a comment.
+pub const PUSH_STATE: u8 = 0x10;
+
+const PUSH: u8 = 0x80;
+const PUSH_STRING: u8 = 0x08;
+const PUSH_CHAR: u8 = 0x0B;
+const PUSH_HEX_DIGIT: u8 = 0x0C;
+const PUSH_ESCAPE: u8 = 0x0D;
+const POP: u8 = 0xFF;
+
+const fn code(action: u8, state: u8) -> u16 {
+    (action as u16) << 8 | state as u16
+}
+
+const fn set(row: &mut [u16; CATEGORIES], cats: &[usize], code: u16) {
+    let mut i = 0;
+    while i < cats.len() {
+        row[cats[i]] = code;
+        i += 1;
+    }
+}
+
+// Set every entry in a row which goes to one state to go to another instead.
+const fn retarget(row: &mut [u16; CATEGORIES], from: u8, to: u8) {
+    let mut cat = 0;
+    while cat < CATEGORIES {
+        if row[cat] != 0xffff && row[cat] & 0xff == from as u16 {
+            row[cat] = row[cat] & 0xff00 | to as u16;
+        }
+        cat += 1;
+    }
+}
+
+/// The strict table, extended with JSON5: comments, trailing commas, unquoted keys, single-quoted
+/// and multi-line strings, more escapes, hex numbers, leading and trailing decimal points, leading
+/// `+`, and `Infinity` and `NaN`.
+pub const LENIENT_STATES: [[u16; CATEGORIES]; LENIENT_STATE_COUNT] = {
+    let mut t = [[0xffff; CATEGORIES]; LENIENT_STATE_COUNT];
+    // Start with the strict table, where the new categories act like the ones they were split from.
+    let strict = widen(STATES);
+    let mut state = 0;
+    while state < 42 {
+        t[state] = strict[state];
+        let other = t[state][0];
+        set(&mut t[state], &[cat::SINGLE_QUOTE, cat::STAR, cat::X, cat::CAPITAL_I, cat::CAPITAL_N,
+                             cat::I, cat::Y, cat::LETTER], other);
+        t[state][cat::NEWLINE] = t[state][cat::WHITESPACE];
+        // All numbers are pushed by the JSON5 action, which tidies them up.
+        let mut c = 0;
+        while c < CATEGORIES {
+            if t[state][c] == 0x09ff || t[state][c] == 0x0aff {
+                t[state][c] = code(PUSH_JSON5_NUMBER, POP);
+            }
+            c += 1;
+        }
+        state += 1;
+    }
+
+    // Comments can go anywhere whitespace can go between tokens.
+    let mut state = 0;
+    while state < LENIENT_STATE_COUNT {
+        if t[state][cat::WHITESPACE] == state as u16 {
+            t[state][cat::SLASH] = code(PUSH_STATE, COMMENT_START);
+        }
+        state += 1;
+    }
+    t[COMMENT_START as usize][cat::SLASH] = code(0, LINE_COMMENT);
+    t[COMMENT_START as usize][cat::STAR] = code(0, BLOCK_COMMENT);
+    t[LINE_COMMENT as usize] = [code(0, LINE_COMMENT); CATEGORIES];
+    t[LINE_COMMENT as usize][cat::NEWLINE] = code(0, POP);
+    t[BLOCK_COMMENT as usize] = [code(0, BLOCK_COMMENT); CATEGORIES];
+    t[BLOCK_COMMENT as usize][cat::STAR] = code(0, BLOCK_COMMENT_STAR);
+    t[BLOCK_COMMENT_STAR as usize] = [code(0, BLOCK_COMMENT); CATEGORIES];
+    t[BLOCK_COMMENT_STAR as usize][cat::STAR] = code(0, BLOCK_COMMENT_STAR);
+    t[BLOCK_COMMENT_STAR as usize][cat::SLASH] = code(0, COMMENT_END);
+    t[COMMENT_END as usize] = [code(0, POP); CATEGORIES];
+
+    // Values: single-quoted strings, `Infinity`, `NaN`, and numbers starting with `+` or `.`.
+    let values = [0, LIST_START, LIST_NEXT, OBJECT_COLON, OBJECT_NEXT_COLON];
+    let mut i = 0;
+    while i < values.len() {
+        let row = &mut t[values[i] as usize];
+        row[cat::SINGLE_QUOTE] = code(PUSH, SQ_STRING);
+        row[cat::PLUS] = code(PUSH | PUSH_CHAR, PLUS);
+        row[cat::DOT] = code(PUSH | PUSH_CHAR, FRACTION_START);
+        row[cat::CAPITAL_I] = code(PUSH | PUSH_CHAR, INFINITY);
+        row[cat::CAPITAL_N] = code(PUSH | PUSH_CHAR, NAN);
+        i += 1;
+    }
+
+    // Trailing commas.
+    t[LIST_NEXT as usize][cat::RIGHT_BRACKET] = code(0, LIST_END);
+    t[OBJECT_NEXT as usize][cat::RIGHT_BRACE] = code(0, OBJECT_END);
+
+    // Keys can be single-quoted, or not quoted at all if they look like identifiers.
+    let mut i = 0;
+    while i < 2 {
+        let row = &mut t[[OBJECT_START, OBJECT_NEXT][i] as usize];
+        row[cat::SINGLE_QUOTE] = code(PUSH, SQ_STRING);
+        set(row, &cat::KEY, code(PUSH | PUSH_CHAR, IDENTIFIER));
+        i += 1;
+    }
+    let row = &mut t[IDENTIFIER as usize];
+    *row = [code(PUSH_STRING, POP); CATEGORIES];
+    set(row, &cat::KEY, code(PUSH_CHAR, IDENTIFIER));
+    set(row, &[cat::ZERO, cat::DIGIT], code(PUSH_CHAR, IDENTIFIER));
+    row[cat::BACKSLASH] = 0xffff;
+
+    // Any character can be escaped, standing for itself if it has no other meaning, except for
+    // digits other than `\0`. `\x` is followed by two hex digits, and a backslash at the end of a
+    // line continues the string on the next one.
+    let row = &mut t[ESCAPE as usize];
+    let mut c = 0;
+    while c < CATEGORIES {
+        if row[c] == 0xffff && c != cat::DIGIT {
+            row[c] = code(PUSH_ESCAPE, STRING);
+        }
+        c += 1;
+    }
+    row[cat::X] = code(PUSH, HEX_ESCAPE);
+    row[cat::NEWLINE] = code(0, DQ_CONTINUATION);
+    set(&mut t[HEX_ESCAPE as usize], &cat::HEX_DIGITS, code(PUSH_HEX_DIGIT, HEX_ESCAPE_END));
+    set(&mut t[HEX_ESCAPE_END as usize], &cat::HEX_DIGITS, code(PUSH_HEX_DIGIT, CODE_POINT));
+    // After a backslash and a line break, a line feed is skipped, for CRLF line endings.
+    t[DQ_CONTINUATION as usize] = t[STRING as usize];
+    t[DQ_CONTINUATION as usize][cat::NEWLINE] = code(0, STRING);
+
+    // Single-quoted strings are the same as double-quoted ones with the quotes swapped.
+    t[SQ_STRING as usize] = t[STRING as usize];
+    retarget(&mut t[SQ_STRING as usize], STRING, SQ_STRING);
+    t[SQ_STRING as usize][cat::DOUBLE_QUOTE] = code(PUSH_CHAR, SQ_STRING);
+    t[SQ_STRING as usize][cat::SINGLE_QUOTE] = t[STRING as usize][cat::DOUBLE_QUOTE];
+    t[SQ_STRING as usize][cat::BACKSLASH] = code(0, SQ_ESCAPE);
+    t[SQ_ESCAPE as usize] = t[ESCAPE as usize];
+    retarget(&mut t[SQ_ESCAPE as usize], STRING, SQ_STRING);
+    t[SQ_ESCAPE as usize][cat::NEWLINE] = code(0, SQ_CONTINUATION);
+    t[SQ_CONTINUATION as usize] = t[SQ_STRING as usize];
+    t[SQ_CONTINUATION as usize][cat::NEWLINE] = code(0, SQ_STRING);
+
+    // Numbers: a leading `+`, a trailing decimal point, and hex.
+    t[PLUS as usize] = t[MINUS as usize];
+    let mut i = 0;
+    while i < 2 {
+        let row = &mut t[[MINUS, PLUS][i] as usize];
+        row[cat::DOT] = code(PUSH_CHAR, FRACTION_START);
+        row[cat::CAPITAL_I] = code(PUSH_CHAR, INFINITY);
+        row[cat::CAPITAL_N] = code(PUSH_CHAR, NAN);
+        i += 1;
+    }
+    t[INTEGER as usize][cat::DOT] = code(PUSH_CHAR, FRACTION);
+    t[ZERO as usize][cat::DOT] = code(PUSH_CHAR, FRACTION);
+    t[ZERO as usize][cat::X] = code(PUSH_CHAR, HEX_START);
+    set(&mut t[HEX_START as usize], &cat::HEX_DIGITS, code(PUSH_CHAR, HEX_NUMBER));
+    t[HEX_NUMBER as usize] = [code(PUSH_JSON5_NUMBER, POP); CATEGORIES];
+    set(&mut t[HEX_NUMBER as usize], &cat::HEX_DIGITS, code(PUSH_CHAR, HEX_NUMBER));
+
+    // `Infinity` and `NaN`, which are pushed as numbers.
+    let letters = [cat::N, cat::F, cat::I, cat::N, cat::I, cat::T, cat::Y];
+    let mut i = 0;
+    while i < letters.len() {
+        t[INFINITY as usize + i][letters[i]] = code(PUSH_CHAR, INFINITY + i as u8 + 1);
+        i += 1;
+    }
+    t[INFINITY_END as usize] = [code(PUSH_JSON5_NUMBER, POP); CATEGORIES];
+    t[NAN as usize][cat::A] = code(PUSH_CHAR, NAN + 1);
+    t[NAN as usize + 1][cat::CAPITAL_N] = code(PUSH_CHAR, NAN_END);
+    t[NAN_END as usize] = [code(PUSH_JSON5_NUMBER, POP); CATEGORIES];
+
+    t
+};
+
+pub const LENIENT_GOTOS: [u8; LENIENT_STATE_COUNT] = {
+    let mut gotos = [255; LENIENT_STATE_COUNT];
+    let mut state = 0;
+    while state < GOTOS.len() {
+        gotos[state] = GOTOS[state];
+        state += 1;
+    }
+    // Escapes in single-quoted strings go back to them.
+    gotos[SQ_ESCAPE as usize] = SQ_STRING;
+    gotos
+};
+
+pub const LENIENT_CATCODE: [u8; 256] = {
+    let mut catcode = strict_catcode();
+    let mut ch = 0;
+    while ch < 256 {
+        if ch >= 0x80 || ((ch as u8).is_ascii_alphabetic() && catcode[ch] == 0) {
+            catcode[ch] = cat::LETTER as u8;
+        }
+        ch += 1;
+    }
+    catcode[b'$' as usize] = cat::LETTER as u8;
+    catcode[b'_' as usize] = cat::LETTER as u8;
+    catcode[b'\'' as usize] = cat::SINGLE_QUOTE as u8;
+    catcode[b'*' as usize] = cat::STAR as u8;
+    catcode[b'\n' as usize] = cat::NEWLINE as u8;
+    catcode[b'\r' as usize] = cat::NEWLINE as u8;
+    catcode[b'x' as usize] = cat::X as u8;
+    catcode[b'X' as usize] = cat::X as u8;
+    catcode[b'I' as usize] = cat::CAPITAL_I as u8;
+    catcode[b'N' as usize] = cat::CAPITAL_N as u8;
+    catcode[b'i' as usize] = cat::I as u8;
+    catcode[b'y' as usize] = cat::Y as u8;
+    catcode[0x0B] = cat::WHITESPACE as u8; // vertical tab
+    catcode[0x0C] = cat::WHITESPACE as u8; // form feed
+    catcode
+};
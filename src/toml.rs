@@ -0,0 +1,810 @@
+//! Reading TOML. The whole document is resolved into nested tables first, since a table's keys
+//! can be spread over the file, and then written out like one JSON object.
+//!
+//! Datetimes are written as strings marked with their kind, like
+//! `OffsetDateTime("1979-05-27T07:32:00Z")`, `LocalDateTime(...)`, `LocalDate(...)` and
+//! `LocalTime(...)`. Integers in hex, octal and binary are written in decimal, and `inf` and `nan`
+//! are written `Infinity` and `NaN`.
+
+use std::io::{self, BufRead};
+
+use crate::decode::{read_text, Builder, Mark, Text};
+use crate::{decimal, Error, JsonError, Segment, Sink, Terminal};
+
+/// How deeply arrays and inline tables can be nested.
+const MAX_DEPTH: usize = 256;
+
+/// How a table came to exist, which decides what can be added to it later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// Only as the parent of a `[table]` header, so it can still have a header of its own.
+    Implicit,
+    /// By a `[table]` header, or as the root.
+    Header,
+    /// By dotted keys, like `a.b = 1`.
+    Dotted,
+    /// By an inline table, which can't be added to.
+    Inline,
+}
+
+#[derive(Debug)]
+struct Table {
+    entries: Vec<(String, Item)>,
+    kind: Kind,
+}
+
+#[derive(Debug)]
+enum Item {
+    Value(Terminal),
+    Array(Vec<Item>),
+    Table(Table),
+    /// An array of tables, made with `[[header]]`s.
+    Tables(Vec<Table>),
+}
+
+impl Table {
+    fn new(kind: Kind) -> Self {
+        Table { entries: vec![], kind }
+    }
+
+    fn get_mut(&mut self, key: &str) -> Option<&mut Item> {
+        self.entries.iter_mut().find(|(k, _)| k == key).map(|(_, item)| item)
+    }
+
+    /// The table at `key`, which is created if it isn't there, or the last one in an array of
+    /// tables. It's an error if there's something else there.
+    fn child(&mut self, key: &str, kind: Kind) -> Result<&mut Table, String> {
+        if self.get_mut(key).is_none() {
+            self.entries.push((key.to_owned(), Item::Table(Table::new(kind))));
+        }
+        match self.get_mut(key).unwrap() {
+            Item::Table(table) => Ok(table),
+            Item::Tables(tables) => Ok(tables.last_mut().unwrap()),
+            _ => Err(format!("key {key:?} already has a value which isn't a table")),
+        }
+    }
+}
+
+struct Parser {
+    text: Text,
+    path: Vec<Segment>, // where in the document we are, for errors
+    depth: usize,
+}
+
+fn bare(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+impl Parser {
+    fn error(&self, error: JsonError) -> Error {
+        self.text.error(&self.path, error)
+    }
+
+    fn syntax(&self, msg: &str) -> Error {
+        self.error(JsonError::Syntax(msg.to_owned()))
+    }
+
+    /// An error at an earlier position, like the start of a key which turned out to be a
+    /// duplicate.
+    fn syntax_at(&mut self, at: Mark, msg: &str) -> Error {
+        self.text.at = at;
+        self.syntax(msg)
+    }
+
+    fn expect(&mut self, c: char, what: &str) -> Result<(), Error> {
+        match self.text.peek() {
+            Some(found) if found == c => {
+                self.text.advance();
+                Ok(())
+            }
+            Some('\n') | None => {
+                Err(self.syntax(&format!("expected {what}, found the end of the line")))
+            }
+            Some(found) => Err(self.syntax(&format!("expected {what}, found '{found}'"))),
+        }
+    }
+
+    /// Skip whitespace, comments and line breaks.
+    fn skip_blank(&mut self) {
+        loop {
+            self.text.skip_space();
+            match self.text.peek() {
+                Some('#') => self.text.skip_line(),
+                Some('\n') => {
+                    self.text.advance();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Skip the rest of the line, which can only have a comment on it.
+    fn end_of_line(&mut self) -> Result<(), Error> {
+        self.text.skip_space();
+        if self.text.peek() == Some('#') {
+            self.text.skip_line();
+        }
+        match self.text.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.text.advance();
+                Ok(())
+            }
+            Some(_) => Err(self.syntax("expected the end of the line")),
+        }
+    }
+
+    fn document(&mut self) -> Result<Table, Error> {
+        let mut root = Table::new(Kind::Header);
+        let mut current: Vec<String> = vec![]; // the keys of the last table header
+        loop {
+            self.skip_blank();
+            let start = self.text.at;
+            match self.text.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    self.text.advance();
+                    let array = self.text.peek() == Some('[');
+                    if array {
+                        self.text.advance();
+                    }
+                    self.text.skip_space();
+                    let keys = self.key()?;
+                    self.expect(']', "']'")?;
+                    if array {
+                        self.expect(']', "']]'")?;
+                    }
+                    self.end_of_line()?;
+                    self.path.clear();
+                    self.open(&mut root, &keys, array)
+                        .map_err(|msg| self.syntax_at(start, &msg))?;
+                    current = keys;
+                }
+                Some(_) => {
+                    let keys = self.key()?;
+                    self.expect('=', "'=' after a key")?;
+                    self.text.skip_space();
+                    let depth = self.path.len();
+                    self.path.extend(keys.iter().map(|key| Segment::Key(key.clone())));
+                    let value = self.value()?;
+                    self.end_of_line()?;
+                    self.path.truncate(depth);
+                    let table = table(&mut root, &current);
+                    insert(table, &keys, value).map_err(|msg| self.syntax_at(start, &msg))?;
+                }
+            }
+        }
+    }
+
+    /// Start the table, or the next table in the array of tables, for a header. The path for
+    /// errors is set to it.
+    fn open(&mut self, root: &mut Table, keys: &[String], array: bool) -> Result<(), String> {
+        let (last, parents) = keys.split_last().unwrap();
+        let mut table = root;
+        for key in parents {
+            self.path.push(Segment::Key(key.clone()));
+            if let Some(Item::Tables(tables)) = table.get_mut(key) {
+                self.path.push(Segment::Index(tables.len() as u64 - 1));
+            }
+            table = table.child(key, Kind::Implicit)?;
+            if table.kind == Kind::Inline {
+                return Err(format!("can't add to the inline table {}", dotted(keys)));
+            }
+        }
+        self.path.push(Segment::Key(last.clone()));
+        let name = dotted(keys);
+        match (table.get_mut(last), array) {
+            (None, false) => {
+                table.entries.push((last.clone(), Item::Table(Table::new(Kind::Header))));
+            }
+            (None, true) => {
+                table.entries.push((last.clone(), Item::Tables(vec![Table::new(Kind::Header)])));
+                self.path.push(Segment::Index(0));
+            }
+            (Some(Item::Table(existing)), false) => match existing.kind {
+                Kind::Implicit => existing.kind = Kind::Header,
+                Kind::Header => return Err(format!("duplicate table [{name}]")),
+                Kind::Dotted => return Err(format!("table [{name}] was already made by dotted keys")),
+                Kind::Inline => return Err(format!("table [{name}] was already defined inline")),
+            },
+            (Some(Item::Tables(tables)), true) => {
+                tables.push(Table::new(Kind::Header));
+                self.path.push(Segment::Index(tables.len() as u64 - 1));
+            }
+            (Some(Item::Tables(_)), false) => {
+                return Err(format!("[{name}] is already an array of tables"));
+            }
+            (Some(_), _) => return Err(format!("key {name} already has a value")),
+        }
+        Ok(())
+    }
+
+    /// Parse a key, which can be dotted, like `a."b.c".d`.
+    fn key(&mut self) -> Result<Vec<String>, Error> {
+        let mut keys = vec![];
+        loop {
+            let key = match self.text.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                Some(c) if bare(c) => {
+                    let mut key = String::new();
+                    while let Some(c) = self.text.peek().filter(|&c| bare(c)) {
+                        key.push(c);
+                        self.text.advance();
+                    }
+                    key
+                }
+                Some('\n') | None => {
+                    return Err(self.syntax("expected a key, found the end of the line"));
+                }
+                Some(c) => return Err(self.syntax(&format!("expected a key, found '{c}'"))),
+            };
+            keys.push(key);
+            self.text.skip_space();
+            if self.text.peek() != Some('.') {
+                return Ok(keys);
+            }
+            self.text.advance();
+            self.text.skip_space();
+        }
+    }
+
+    fn value(&mut self) -> Result<Item, Error> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.syntax("TOML nested too deeply"));
+        }
+        let value = match self.text.peek() {
+            Some('"') => Item::Value(Terminal::String(self.basic_string()?)),
+            Some('\'') => Item::Value(Terminal::String(self.literal_string()?)),
+            Some('[') => self.array()?,
+            Some('{') => self.inline_table()?,
+            _ => Item::Value(self.scalar()?),
+        };
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn array(&mut self) -> Result<Item, Error> {
+        self.text.advance();
+        let mut items = vec![];
+        loop {
+            self.skip_blank();
+            if self.text.peek() == Some(']') {
+                self.text.advance();
+                return Ok(Item::Array(items));
+            }
+            self.path.push(Segment::Index(items.len() as u64));
+            items.push(self.value()?);
+            self.path.pop();
+            self.skip_blank();
+            match self.text.peek() {
+                Some(',') => {
+                    self.text.advance();
+                }
+                Some(']') => (),
+                None => return Err(self.syntax("expected ',' or ']', found the end of the input")),
+                Some(c) => return Err(self.syntax(&format!("expected ',' or ']', found '{c}'"))),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Item, Error> {
+        self.text.advance();
+        let mut table = Table::new(Kind::Inline);
+        self.text.skip_space();
+        if self.text.peek() == Some('}') {
+            self.text.advance();
+            return Ok(Item::Table(table));
+        }
+        loop {
+            self.text.skip_space();
+            let start = self.text.at;
+            let keys = self.key()?;
+            self.expect('=', "'=' after a key")?;
+            self.text.skip_space();
+            let depth = self.path.len();
+            self.path.extend(keys.iter().map(|key| Segment::Key(key.clone())));
+            let value = self.value()?;
+            self.path.truncate(depth);
+            insert(&mut table, &keys, value).map_err(|msg| self.syntax_at(start, &msg))?;
+            self.text.skip_space();
+            match self.text.peek() {
+                Some(',') => {
+                    self.text.advance();
+                }
+                Some('}') => {
+                    self.text.advance();
+                    return Ok(Item::Table(table));
+                }
+                Some('\n') | None => {
+                    return Err(self.syntax("expected ',' or '}', found the end of the line"));
+                }
+                Some(c) => return Err(self.syntax(&format!("expected ',' or '}}', found '{c}'"))),
+            }
+        }
+    }
+
+    /// Parse a string in double quotes, which can have escapes, or three of them, which can
+    /// also go over several lines.
+    fn basic_string(&mut self) -> Result<String, Error> {
+        let multiline = self.open_quotes('"');
+        let mut s = String::new();
+        loop {
+            match self.text.peek() {
+                None => return Err(self.syntax("unterminated string")),
+                Some('"') => {
+                    if self.close_quotes('"', multiline, &mut s) {
+                        return Ok(s);
+                    }
+                }
+                Some('\\') => {
+                    self.text.advance();
+                    self.escape(&mut s, multiline)?;
+                }
+                Some(c) => self.string_char(c, multiline, &mut s)?,
+            }
+        }
+    }
+
+    /// Parse a string in single quotes, or three of them, without escapes.
+    fn literal_string(&mut self) -> Result<String, Error> {
+        let multiline = self.open_quotes('\'');
+        let mut s = String::new();
+        loop {
+            match self.text.peek() {
+                None => return Err(self.syntax("unterminated string")),
+                Some('\'') => {
+                    if self.close_quotes('\'', multiline, &mut s) {
+                        return Ok(s);
+                    }
+                }
+                Some(c) => self.string_char(c, multiline, &mut s)?,
+            }
+        }
+    }
+
+    /// Skip the opening quotes of a string, returning whether there were three. A line break
+    /// straight after three is left out.
+    fn open_quotes(&mut self, quote: char) -> bool {
+        self.text.advance();
+        if self.text.peek() != Some(quote) || self.text.peek_at(1) != Some(quote) {
+            return false;
+        }
+        self.text.advance();
+        self.text.advance();
+        if self.text.peek() == Some('\n') {
+            self.text.advance();
+        }
+        true
+    }
+
+    /// Whether the quote at the current position ends the string, and if so, skip past it. A
+    /// multi-line string can end with up to five quotes, the first two of which are part of it.
+    fn close_quotes(&mut self, quote: char, multiline: bool, s: &mut String) -> bool {
+        if !multiline {
+            self.text.advance();
+            return true;
+        }
+        let n = (0 .. 5).take_while(|&i| self.text.peek_at(i) == Some(quote)).count();
+        if n < 3 {
+            (0 .. n).for_each(|_| s.push(quote));
+        } else {
+            (3 .. n).for_each(|_| s.push(quote));
+        }
+        (0 .. n).for_each(|_| { self.text.advance(); });
+        n >= 3
+    }
+
+    fn string_char(&mut self, c: char, multiline: bool, s: &mut String) -> Result<(), Error> {
+        if c == '\n' && !multiline {
+            return Err(self.syntax("unterminated string"));
+        }
+        if c.is_control() && c != '\t' && c != '\n' {
+            return Err(self.syntax(&format!("control character {c:?} in a string")));
+        }
+        self.text.advance();
+        s.push(c);
+        Ok(())
+    }
+
+    /// Parse an escape sequence in a basic string, after the `\`.
+    fn escape(&mut self, s: &mut String, multiline: bool) -> Result<(), Error> {
+        let Some(c) = self.text.advance() else {
+            return Err(self.syntax("unterminated string"));
+        };
+        let len = match c {
+            'b' | 't' | 'n' | 'f' | 'r' | 'e' | '"' | '\\' => {
+                s.push(match c {
+                    'b' => '\x08',
+                    't' => '\t',
+                    'n' => '\n',
+                    'f' => '\x0C',
+                    'r' => '\r',
+                    'e' => '\x1B',
+                    _ => c,
+                });
+                return Ok(());
+            }
+            'x' => 2,
+            'u' => 4,
+            'U' => 8,
+            ' ' | '\t' | '\n' if multiline => {
+                // A backslash at the end of a line trims the whitespace after it.
+                let mut at_end = c == '\n';
+                while let Some(c @ (' ' | '\t' | '\n')) = self.text.peek() {
+                    at_end |= c == '\n';
+                    self.text.advance();
+                }
+                if !at_end {
+                    let error = JsonError::InvalidEscape("\\ followed by a space".to_owned());
+                    return Err(self.error(error));
+                }
+                return Ok(());
+            }
+            _ => return Err(self.error(JsonError::InvalidEscape(format!("\\{c}")))),
+        };
+        let mut hex = String::new();
+        for _ in 0 .. len {
+            match self.text.peek() {
+                Some(h) if h.is_ascii_hexdigit() => hex.push(h),
+                _ => {
+                    return Err(self.error(JsonError::InvalidEscape(
+                        format!("\\{c}{hex}: expected {len} hex digits"))));
+                }
+            }
+            self.text.advance();
+        }
+        let code = u32::from_str_radix(&hex, 16).unwrap();
+        let Some(code) = char::from_u32(code) else {
+            let error = JsonError::InvalidEscape(format!("\\{c}{hex} is not a character"));
+            return Err(self.error(error));
+        };
+        s.push(code);
+        Ok(())
+    }
+
+    /// Parse a boolean, number or datetime.
+    fn scalar(&mut self) -> Result<Terminal, Error> {
+        let start = self.text.at;
+        let mut token = String::new();
+        while let Some(c) = self.text.peek() {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+' | '.' | ':') {
+                token.push(c);
+            } else if c == ' ' && date(&token)
+                && self.text.peek_at(1).is_some_and(|c| c.is_ascii_digit())
+            {
+                // A datetime can have a space between the date and the time.
+                token.push('T');
+            } else {
+                break;
+            }
+            self.text.advance();
+        }
+        let value = match token.as_str() {
+            "true" => Some(Terminal::Bool(true)),
+            "false" => Some(Terminal::Bool(false)),
+            "inf" | "+inf" => Some(Terminal::Number("Infinity".to_owned())),
+            "-inf" => Some(Terminal::Number("-Infinity".to_owned())),
+            "nan" | "+nan" | "-nan" => Some(Terminal::Number("NaN".to_owned())),
+            _ => number(&token).map(Terminal::Number).or_else(|| datetime(&token)),
+        };
+        value.ok_or_else(|| match token.as_str() {
+            "" => match self.text.peek() {
+                Some('\n') | None => self.syntax("expected a value, found the end of the line"),
+                Some(c) => self.syntax(&format!("expected a value, found '{c}'")),
+            },
+            _ => self.syntax_at(start, &format!("invalid value {token:?}")),
+        })
+    }
+}
+
+/// The table which a header has already been opened for.
+fn table<'a>(root: &'a mut Table, keys: &[String]) -> &'a mut Table {
+    keys.iter().fold(root, |table, key| table.child(key, Kind::Implicit).unwrap())
+}
+
+/// Add a value to a table, at a key which can be dotted.
+fn insert(table: &mut Table, keys: &[String], value: Item) -> Result<(), String> {
+    let (last, parents) = keys.split_last().unwrap();
+    let mut table = table;
+    for (i, key) in parents.iter().enumerate() {
+        let existing = table.get_mut(key).is_some();
+        table = table.child(key, Kind::Dotted)?;
+        if existing && table.kind != Kind::Dotted {
+            return Err(format!("table {} was already defined, so dotted keys can't add to it",
+                               dotted(&keys[..= i])));
+        }
+    }
+    if table.get_mut(last).is_some() {
+        return Err(format!("duplicate key {}", dotted(keys)));
+    }
+    table.entries.push((last.clone(), value));
+    Ok(())
+}
+
+/// Keys as they'd be written in TOML, like `a."b c"`.
+fn dotted(keys: &[String]) -> String {
+    let quoted = |key: &String| if !key.is_empty() && key.chars().all(bare) {
+        key.clone()
+    } else {
+        Terminal::String(key.clone()).to_string()
+    };
+    keys.iter().map(quoted).collect::<Vec<_>>().join(".")
+}
+
+/// Digits with underscores between them, without the underscores, if they're all valid.
+fn digits(s: &str, radix: u32) -> Option<String> {
+    let valid = !s.is_empty() && !s.starts_with('_') && !s.ends_with('_') && !s.contains("__")
+        && s.chars().all(|c| c == '_' || c.is_digit(radix));
+    valid.then(|| s.replace('_', ""))
+}
+
+/// An integer or float, as a JSON number.
+fn number(s: &str) -> Option<String> {
+    for (prefix, radix) in [("0x", 16), ("0o", 8), ("0b", 2)] {
+        if let Some(rest) = s.strip_prefix(prefix) {
+            let n = digits(rest, radix)?;
+            return Some(decimal(n.chars().map(|c| c.to_digit(radix).unwrap()), radix));
+        }
+    }
+    let (sign, body) = match s.strip_prefix('-') {
+        Some(body) => ("-", body),
+        None => ("", s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (mantissa, exponent) = match body.find(['e', 'E']) {
+        Some(i) => (&body[.. i], Some(&body[i + 1 ..])),
+        None => (body, None),
+    };
+    let (int, fraction) = match mantissa.split_once('.') {
+        Some((int, fraction)) => (int, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let int = digits(int, 10)?;
+    if int.len() > 1 && int.starts_with('0') {
+        return None; // no leading zeros
+    }
+    let mut n = format!("{sign}{int}");
+    if let Some(fraction) = fraction {
+        n.push('.');
+        n.push_str(&digits(fraction, 10)?);
+    }
+    if let Some(exponent) = exponent {
+        let (sign, e) = match exponent.strip_prefix(['-', '+']) {
+            Some(e) => (&exponent[.. 1], e),
+            None => ("", exponent),
+        };
+        n.push('e');
+        n.push_str(sign);
+        n.push_str(&digits(e, 10)?);
+    }
+    Some(n)
+}
+
+/// Whether `s` starts with a date, like `1979-05-27`, and is no more than that.
+fn date(s: &str) -> bool {
+    let b = s.as_bytes();
+    let num = |range: std::ops::Range<usize>| s[range].parse::<u32>().ok();
+    b.len() == 10 && b[4] == b'-' && b[7] == b'-'
+        && b.iter().enumerate().all(|(i, c)| i == 4 || i == 7 || c.is_ascii_digit())
+        && num(5 .. 7).is_some_and(|m| (1 ..= 12).contains(&m))
+        && num(8 .. 10).is_some_and(|d| (1 ..= 31).contains(&d))
+}
+
+/// How long the time at the start of `s` is, like `07:32:00.999`, if it starts with one.
+fn time(s: &str) -> Option<usize> {
+    let b = s.as_bytes();
+    let two = |i: usize, max: u8| {
+        b.len() >= i + 2 && b[i].is_ascii_digit() && b[i + 1].is_ascii_digit()
+            && (b[i] - b'0') * 10 + (b[i + 1] - b'0') <= max
+    };
+    if !(two(0, 23) && b.get(2) == Some(&b':') && two(3, 59)) {
+        return None;
+    }
+    let mut len = 5;
+    if b.get(5) == Some(&b':') && two(6, 60) {
+        len = 8;
+        if b.get(8) == Some(&b'.') {
+            let fraction = b[9 ..].iter().take_while(|c| c.is_ascii_digit()).count();
+            if fraction == 0 {
+                return None;
+            }
+            len = 9 + fraction;
+        }
+    }
+    Some(len)
+}
+
+/// A datetime, date or time, as a string marked with its kind.
+fn datetime(s: &str) -> Option<Terminal> {
+    let typed = |kind: &str, s: &str| {
+        Some(Terminal::Typed(format!("{kind}({})", Terminal::String(s.to_owned()))))
+    };
+    if let Some(len) = time(s) {
+        return if len == s.len() { typed("LocalTime", s) } else { None };
+    }
+    if s.len() < 10 || !date(&s[.. 10]) {
+        return None;
+    }
+    if s.len() == 10 {
+        return typed("LocalDate", s);
+    }
+    let s = s.replacen('t', "T", 1);
+    let rest = s[10 ..].strip_prefix('T')?;
+    let len = time(rest)?;
+    match &rest[len ..] {
+        "" => typed("LocalDateTime", &s),
+        "Z" | "z" => typed("OffsetDateTime", &s.to_uppercase()),
+        offset if offset.len() == 6 && offset.starts_with(['+', '-'])
+            && time(&offset[1 ..]) == Some(5) => typed("OffsetDateTime", &s),
+        _ => None,
+    }
+}
+
+/// Give a table to the builder, like a JSON object.
+fn emit<S: Sink>(table: &Table, builder: &mut Builder<S>) -> io::Result<()> {
+    builder.start_object()?;
+    for (key, item) in &table.entries {
+        if builder.done() {
+            return Ok(());
+        }
+        builder.key(key.clone())?;
+        emit_item(item, builder)?;
+    }
+    builder.end()
+}
+
+fn emit_item<S: Sink>(item: &Item, builder: &mut Builder<S>) -> io::Result<()> {
+    match item {
+        Item::Value(value) => builder.scalar(value.clone()),
+        Item::Table(table) => emit(table, builder),
+        Item::Array(items) => {
+            builder.start_list()?;
+            for item in items {
+                emit_item(item, builder)?;
+            }
+            builder.end()
+        }
+        Item::Tables(tables) => {
+            builder.start_list()?;
+            for table in tables {
+                emit(table, builder)?;
+            }
+            builder.end()
+        }
+    }
+}
+
+/// Read a TOML document and give it to the sink, like a JSON object.
+pub(crate) fn decode<S: Sink>(input: impl BufRead, sink: S) -> Result<(), Error> {
+    let text = read_text(input)?;
+    let mut parser = Parser { text: Text::new(&text), path: vec![], depth: 0 };
+    let root = parser.document().map_err(|e| e.with_snippet(text.as_bytes(), 0))?;
+    let mut builder = Builder::new(sink);
+    emit(&root, &mut builder)
+        .and_then(|()| builder.sink.end())
+        .map_err(|e| parser.error(e.into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parse_with, Format, Options};
+
+    fn run(input: &str) -> Result<String, crate::Error> {
+        let mut out = vec![];
+        let options = Options { format: Format::Toml, ..Options::default() };
+        parse_with(input.as_bytes(), &mut out, options)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_toml() {
+        let input = r#"
+# a manifest
+[package]
+name = "catj"
+version = "0.3.3"
+authors = ["A <a@example.com>",
+           'B',]  # trailing comma
+description = """
+Flat output \
+    for ""JSON""."""
+path = 'C:\temp'
+
+[dependencies]
+serde = { version = "1", features = ["derive"] }
+point.x = 1_000
+point."y z" = 0x1F
+
+[[bin]]
+name = "catj"
+
+[[bin]]
+name = "ungron"
+test = false
+
+[dates]
+offset = 1979-05-27 07:32:00.999-08:00
+utc = 1979-05-27t07:32:00z
+local = 1979-05-27T07:32:00
+day = 1979-05-27
+time = 07:32:00
+floats = [+1.5, -0.0, 5e+22, 1E-3, inf, nan]
+"#;
+        assert_eq!(run(input).unwrap(), r#".package.name = "catj"
+.package.version = "0.3.3"
+.package.authors[0] = "A <a@example.com>"
+.package.authors[1] = "B"
+.package.description = "Flat output for \"\"JSON\"\"."
+.package.path = "C:\\temp"
+.dependencies.serde.version = "1"
+.dependencies.serde.features[0] = "derive"
+.dependencies.point.x = 1000
+.dependencies.point."y z" = 31
+.bin[0].name = "catj"
+.bin[1].name = "ungron"
+.bin[1].test = false
+.dates.offset = OffsetDateTime("1979-05-27T07:32:00.999-08:00")
+.dates.utc = OffsetDateTime("1979-05-27T07:32:00Z")
+.dates.local = LocalDateTime("1979-05-27T07:32:00")
+.dates.day = LocalDate("1979-05-27")
+.dates.time = LocalTime("07:32:00")
+.dates.floats[0] = 1.5
+.dates.floats[1] = -0.0
+.dates.floats[2] = 5e+22
+.dates.floats[3] = 1e-3
+.dates.floats[4] = Infinity
+.dates.floats[5] = NaN
+
+"#);
+    }
+
+    #[test]
+    fn test_js_style() {
+        // datetimes are strings, so that the output is still JavaScript
+        let mut out = vec![];
+        let options = Options { format: Format::Toml, style: crate::Style::Js, ..Options::default() };
+        parse_with(&b"when = 1979-05-27T07:32:00Z"[..], &mut out, options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "json = {};\njson.when = \"OffsetDateTime(\\\"1979-05-27T07:32:00Z\\\")\";\n\n");
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input: &str| {
+            let e = run(input).unwrap_err();
+            (e.line, e.col, e.path.to_string(), e.error.to_string())
+        };
+        assert_eq!(error("[a]\nx = 1\n\n[a]\n"),
+                   (4, 1, ".a".to_owned(), "duplicate table [a]".to_owned()));
+        assert_eq!(error("[a]\nb.c = 1\n[a.b]\n"),
+                   (3, 1, ".a.b".to_owned(),
+                    "table [a.b] was already made by dotted keys".to_owned()));
+        assert_eq!(error("[[t]]\n[[t]]\nk = 1\n  k = 2\n"),
+                   (4, 3, ".t[1]".to_owned(), "duplicate key k".to_owned()));
+        assert_eq!(error("[a.b]\n[a]\nb.c = 1\n"),
+                   (3, 1, ".a".to_owned(),
+                    "table b was already defined, so dotted keys can't add to it".to_owned()));
+        assert_eq!(error("a = {b = 1}\n[a]\n"),
+                   (2, 1, ".a".to_owned(), "table [a] was already defined inline".to_owned()));
+        assert_eq!(error("n = 012\n"),
+                   (1, 5, ".n".to_owned(), "invalid value \"012\"".to_owned()));
+        assert_eq!(error("s = \"abc\nt = 1\n"),
+                   (1, 9, ".s".to_owned(), "unterminated string".to_owned()));
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(number("+99").as_deref(), Some("99"));
+        assert_eq!(number("0b1010").as_deref(), Some("10"));
+        assert_eq!(number("0o777").as_deref(), Some("511"));
+        assert_eq!(number("6.626e-34").as_deref(), Some("6.626e-34"));
+        assert_eq!(number("1__0"), None);
+        assert_eq!(number("1."), None);
+        assert_eq!(number(".5"), None);
+        assert!(datetime("1979-13-27").is_none());
+        assert_eq!(datetime("07:32").map(|t| t.to_string()).as_deref(),
+                   Some("LocalTime(\"07:32\")"));
+    }
+}
@@ -0,0 +1,631 @@
+//! Reverse mode: rebuilds JSON documents from catj's flat output.
+//!
+//! Unlike the forward direction, this has to hold each document in memory, because the lines can
+//! come in any order.
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufRead, Write};
+
+use crate::{Segment, Terminal};
+
+#[derive(Debug)]
+pub enum UngronError {
+    Syntax(String),
+    Conflict(String),
+    /// A list index so far past the values given that writing the gap as nulls would never end.
+    Sparse(String),
+    IO(io::Error),
+}
+
+impl From<io::Error> for UngronError {
+    fn from(e: io::Error) -> Self {
+        UngronError::IO(e)
+    }
+}
+
+/// How many more items a list can have than there were lines in its document. The rest are
+/// filled with nulls, so this stops a line like `json[99999999999999] = 1;` writing that many.
+const MAX_GAP: u64 = 1000;
+
+#[derive(Debug)]
+enum Node {
+    Terminal(Terminal),
+    Object(Object),
+    List(BTreeMap<u64, Node>), // can be sparse; gaps are filled with nulls on output
+    /// A list made for a number in a JSON Pointer, which could turn out to be an object's key.
+    Guessed(BTreeMap<u64, Node>),
+}
+
+/// An object which remembers the order its keys were first seen in.
+#[derive(Debug, Default)]
+struct Object {
+    index: HashMap<String, usize>,
+    fields: Vec<(String, Node)>,
+}
+
+impl Node {
+    fn container_for(seg: &Segment, pointer: bool) -> Node {
+        match seg {
+            Segment::Key(_) => Node::Object(Object::default()),
+            Segment::Index(_) if pointer => Node::Guessed(BTreeMap::new()),
+            Segment::Index(_) => Node::List(BTreeMap::new()),
+        }
+    }
+
+    /// Turn a guessed list into the object it really was, with its indices as keys.
+    fn make_object(&mut self) {
+        if let Node::Guessed(list) = self {
+            let mut obj = Object::default();
+            for (i, value) in std::mem::take(list) {
+                obj.index.insert(i.to_string(), obj.fields.len());
+                obj.fields.push((i.to_string(), value));
+            }
+            *self = Node::Object(obj);
+        }
+    }
+
+    /// Check that no list has an index more than [`MAX_GAP`] past the number of lines which made
+    /// the document, since the gaps would be written out as nulls.
+    fn check_gaps(&self, lines: u64) -> Result<(), UngronError> {
+        match self {
+            Node::Terminal(_) => Ok(()),
+            Node::Object(obj) => {
+                obj.fields.iter().try_for_each(|(_, value)| value.check_gaps(lines))
+            }
+            Node::List(list) | Node::Guessed(list) => {
+                if let Some(&last) = list.keys().next_back() {
+                    if last > lines.saturating_add(MAX_GAP) {
+                        return Err(UngronError::Sparse(format!(
+                            "list index [{last}] is too big for a document of {lines} lines")));
+                    }
+                }
+                list.values().try_for_each(|value| value.check_gaps(lines))
+            }
+        }
+    }
+
+    /// Decide what the guessed lists in a finished document were. Lists are always written
+    /// without gaps, so one with gaps must have been an object, like `{"1": true}`.
+    fn settle(&mut self) {
+        match self {
+            Node::Terminal(_) => (),
+            Node::Object(obj) => obj.fields.iter_mut().for_each(|(_, value)| value.settle()),
+            Node::List(list) => list.values_mut().for_each(Node::settle),
+            Node::Guessed(list) => {
+                list.values_mut().for_each(Node::settle);
+                if list.keys().enumerate().any(|(n, &i)| n as u64 != i) {
+                    self.make_object();
+                }
+            }
+        }
+    }
+
+    /// Get the child at the given segment, inserting one made by `make` if it doesn't exist.
+    /// Returns whether it was inserted.
+    fn child(&mut self, seg: &Segment, make: impl FnOnce() -> Node)
+        -> Result<(&mut Node, bool), UngronError>
+    {
+        match (self, seg) {
+            (Node::Object(obj), Segment::Key(key)) => {
+                if let Some(&i) = obj.index.get(key) {
+                    Ok((&mut obj.fields[i].1, false))
+                } else {
+                    obj.index.insert(key.clone(), obj.fields.len());
+                    obj.fields.push((key.clone(), make()));
+                    Ok((&mut obj.fields.last_mut().unwrap().1, true))
+                }
+            }
+            (Node::List(list) | Node::Guessed(list), Segment::Index(i)) => {
+                let mut inserted = false;
+                let child = list.entry(*i).or_insert_with(|| {
+                    inserted = true;
+                    make()
+                });
+                Ok((child, inserted))
+            }
+            (Node::Terminal(t), _) => Err(UngronError::Conflict(
+                format!("can't set a field on non-container value {t}"))),
+            (Node::Object(_), Segment::Index(i)) => Err(UngronError::Conflict(
+                format!("can't set index [{i}] on an object"))),
+            (Node::List(_) | Node::Guessed(_), Segment::Key(key)) => Err(UngronError::Conflict(
+                format!("can't set field {} on a list", Terminal::String(key.clone())))),
+        }
+    }
+
+    /// JSON Pointer paths don't say whether a number is a list index or an object key. They're
+    /// parsed as indices, and turned back into keys here if the container is an object. A list
+    /// which was only guessed becomes an object if a key which isn't a number is put in it.
+    fn resolve<'s>(&mut self, seg: &'s Segment, pointer: bool) -> Cow<'s, Segment> {
+        match (&*self, seg) {
+            (Node::Object(_), Segment::Index(i)) if pointer => {
+                Cow::Owned(Segment::Key(i.to_string()))
+            }
+            (Node::Guessed(_), Segment::Key(_)) => {
+                self.make_object();
+                Cow::Borrowed(seg)
+            }
+            _ => Cow::Borrowed(seg),
+        }
+    }
+
+    /// Assign a value at the given path under this node, creating containers along the way.
+    fn assign(&mut self, path: &[Segment], pointer: bool, value: Node) -> Result<(), UngronError> {
+        let (last, init) = path.split_last().expect("empty path");
+        let mut node = self;
+        for (i, seg) in init.iter().enumerate() {
+            let next = &path[i + 1];
+            let seg = node.resolve(seg, pointer);
+            node = node.child(&seg, || Node::container_for(next, pointer))?.0;
+        }
+        let mut value = Some(value);
+        let last = node.resolve(last, pointer);
+        let (slot, inserted) = node.child(&last, || value.take().unwrap())?;
+        if !inserted {
+            slot.replace(value.take().unwrap());
+        }
+        Ok(())
+    }
+
+    /// Replace this node with a new value for the same path.
+    fn replace(&mut self, new: Node) {
+        match (&mut *self, new) {
+            // Empty containers only declare the type, and don't clobber existing contents.
+            (Node::Object(_), Node::Object(new)) if new.fields.is_empty() => (),
+            (Node::List(_), Node::List(new)) if new.is_empty() => (),
+            (Node::Guessed(_), Node::Object(new)) if new.fields.is_empty() => self.make_object(),
+            (Node::Guessed(list), Node::List(new)) if new.is_empty() => {
+                *self = Node::List(std::mem::take(list));
+            }
+            (_, new) => *self = new,
+        }
+    }
+
+    fn write(&self, indent: usize, output: &mut impl Write) -> io::Result<()> {
+        match self {
+            Node::Terminal(t) => write!(output, "{t}"),
+            Node::Object(obj) if obj.fields.is_empty() => output.write_all(b"{}"),
+            Node::List(list) | Node::Guessed(list) if list.is_empty() => output.write_all(b"[]"),
+            Node::Object(obj) => {
+                output.write_all(b"{")?;
+                for (i, (key, value)) in obj.fields.iter().enumerate() {
+                    if i != 0 {
+                        output.write_all(b",")?;
+                    }
+                    newline(indent + 1, output)?;
+                    // the Display impl takes care of quoting and escaping
+                    write!(output, "{}: ", Terminal::String(key.clone()))?;
+                    value.write(indent + 1, output)?;
+                }
+                newline(indent, output)?;
+                output.write_all(b"}")
+            }
+            Node::List(list) | Node::Guessed(list) => {
+                output.write_all(b"[")?;
+                let mut next = 0;
+                for (&i, value) in list {
+                    while next <= i {
+                        if next != 0 {
+                            output.write_all(b",")?;
+                        }
+                        newline(indent + 1, output)?;
+                        if next != i {
+                            output.write_all(b"null")?;
+                        }
+                        next += 1;
+                    }
+                    value.write(indent + 1, output)?;
+                }
+                newline(indent, output)?;
+                output.write_all(b"]")
+            }
+        }
+    }
+}
+
+fn newline(indent: usize, output: &mut impl Write) -> io::Result<()> {
+    output.write_all(b"\n")?;
+    for _ in 0 .. indent {
+        output.write_all(b"  ")?;
+    }
+    Ok(())
+}
+
+/// A cursor over a single line of input. Also used for parsing filter patterns.
+pub(crate) struct Line<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Line<'a> {
+    pub(crate) fn new(s: &'a str) -> Self {
+        Line { s, pos: 0 }
+    }
+
+    pub(crate) fn peek(&self) -> Option<u8> {
+        self.s.as_bytes().get(self.pos).copied()
+    }
+
+    pub(crate) fn rest(&self) -> &'a str {
+        &self.s[self.pos ..]
+    }
+
+    pub(crate) fn eat(&mut self, prefix: &str) -> bool {
+        if self.rest().starts_with(prefix) {
+            self.pos += prefix.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn expect(&mut self, prefix: &str) -> Result<(), UngronError> {
+        if self.eat(prefix) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected {prefix:?}")))
+        }
+    }
+
+    pub(crate) fn error(&self, what: &str) -> UngronError {
+        UngronError::Syntax(format!("{what} at column {}", self.pos + 1))
+    }
+
+    pub(crate) fn take_while(&mut self, f: impl Fn(u8) -> bool) -> &'a str {
+        let start = self.pos;
+        while self.peek().is_some_and(&f) {
+            self.pos += 1;
+        }
+        &self.s[start .. self.pos]
+    }
+
+    /// Parse a path in catj's style, or in gron's (which starts with a root identifier, and can
+    /// have keys in brackets), which is only allowed to be empty in the latter case.
+    fn path(&mut self) -> Result<Vec<Segment>, UngronError> {
+        // Non-ASCII bytes are allowed so that gron's Unicode identifiers get through.
+        let ident = |c: u8| c.is_ascii_alphanumeric() || c == b'_' || c == b'$' || c >= 0x80;
+        let root = !self.take_while(ident).is_empty();
+        let mut path = vec![];
+        loop {
+            match self.peek() {
+                Some(b'.') => {
+                    self.pos += 1;
+                    if self.peek() == Some(b'"') {
+                        path.push(Segment::Key(self.string()?));
+                    } else {
+                        let key = self.take_while(ident);
+                        if key.is_empty() {
+                            return Err(self.error("expected a key"));
+                        }
+                        path.push(Segment::Key(key.to_owned()));
+                    }
+                }
+                Some(b'[') => {
+                    self.pos += 1;
+                    if self.peek() == Some(b'"') {
+                        path.push(Segment::Key(self.string()?));
+                        self.expect("]")?;
+                        continue;
+                    }
+                    let digits = self.take_while(|c| c.is_ascii_digit());
+                    let index = digits.parse()
+                        .map_err(|_| self.error("expected a list index"))?;
+                    self.expect("]")?;
+                    path.push(Segment::Index(index));
+                }
+                _ if path.is_empty() && !root => return Err(self.error("expected '.' or '['")),
+                _ => return Ok(path),
+            }
+        }
+    }
+
+    /// Parse a double-quoted string with JSON escapes.
+    pub(crate) fn string(&mut self) -> Result<String, UngronError> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        loop {
+            let run = self.take_while(|c| c != b'"' && c != b'\\');
+            s.push_str(run);
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some(b'\\') => {
+                    self.pos += 1;
+                    let c = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\x08',
+                        Some(b'f') => '\x0C',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.pos += 1;
+                            let c = self.unicode_escape()?;
+                            s.push(c);
+                            continue;
+                        }
+                        _ => return Err(self.error("invalid string escape sequence")),
+                    };
+                    self.pos += 1;
+                    s.push(c);
+                }
+                _ => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, UngronError> {
+        let digits = self.rest().get(0 .. 4)
+            .filter(|d| d.bytes().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("expected four hex digits"))?;
+        self.pos += 4;
+        Ok(u32::from_str_radix(digits, 16).unwrap())
+    }
+
+    fn unicode_escape(&mut self) -> Result<char, UngronError> {
+        let high = self.hex4()?;
+        let codepoint = if (0xD800 ..= 0xDBFF).contains(&high) {
+            if !self.eat("\\u") {
+                return Err(self.error("unpaired high surrogate"));
+            }
+            let low = self.hex4()?;
+            if !(0xDC00 ..= 0xDFFF).contains(&low) {
+                return Err(self.error("unpaired high surrogate"));
+            }
+            0x1_0000 + (high - 0xD800) * 0x400 + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(codepoint).ok_or_else(|| self.error("invalid unicode escape"))
+    }
+
+    fn number(&mut self) -> Result<String, UngronError> {
+        let start = self.pos;
+        self.eat("-");
+        if !self.eat("0") && self.take_while(|c| c.is_ascii_digit()).is_empty() {
+            return Err(self.error("expected a value"));
+        }
+        if self.eat(".") && self.take_while(|c| c.is_ascii_digit()).is_empty() {
+            return Err(self.error("expected digits after decimal point"));
+        }
+        if self.eat("e") || self.eat("E") {
+            let _ = self.eat("+") || self.eat("-");
+            if self.take_while(|c| c.is_ascii_digit()).is_empty() {
+                return Err(self.error("expected digits in exponent"));
+            }
+        }
+        Ok(self.s[start .. self.pos].to_owned())
+    }
+
+    fn value(&mut self) -> Result<Node, UngronError> {
+        let node = if self.eat("null") {
+            Node::Terminal(Terminal::Null)
+        } else if self.eat("true") {
+            Node::Terminal(Terminal::Bool(true))
+        } else if self.eat("false") {
+            Node::Terminal(Terminal::Bool(false))
+        } else if self.eat("{}") {
+            Node::Object(Object::default())
+        } else if self.eat("[]") {
+            Node::List(BTreeMap::new())
+        } else if self.peek() == Some(b'"') {
+            Node::Terminal(Terminal::String(self.string()?))
+        } else {
+            Node::Terminal(Terminal::Number(self.number()?))
+        };
+        Ok(node)
+    }
+}
+
+/// Undo JSON Pointer escaping in a reference token: `~1` is `/` and `~0` is `~`.
+pub(crate) fn unescape_pointer_token(token: &str) -> Result<String, String> {
+    let mut s = String::with_capacity(token.len());
+    let mut chars = token.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '~' => match chars.next() {
+                Some('0') => s.push('~'),
+                Some('1') => s.push('/'),
+                _ => return Err(format!("invalid escape in JSON Pointer token {token:?}")),
+            },
+            c => s.push(c),
+        }
+    }
+    Ok(s)
+}
+
+/// Parse a JSON Pointer. Tokens which look like list indices are returned as indices.
+fn parse_pointer(s: &str) -> Result<Vec<Segment>, UngronError> {
+    s.split('/')
+        .skip(1)
+        .map(|token| {
+            if token == "0" || (!token.starts_with('0') && !token.is_empty()
+                                && token.bytes().all(|c| c.is_ascii_digit()))
+            {
+                if let Ok(i) = token.parse() {
+                    return Ok(Segment::Index(i));
+                }
+            }
+            unescape_pointer_token(token).map(Segment::Key).map_err(UngronError::Syntax)
+        })
+        .collect()
+}
+
+/// Parse one line of the form `<path> = <value>`, where the path is a JSON Pointer. Keys aren't
+/// quoted in this form, so they can contain " = " too; the line is split at the first one which
+/// is followed by a valid value.
+fn parse_pointer_line(s: &str) -> Result<(Vec<Segment>, Node), UngronError> {
+    let mut error = None;
+    for (i, _) in s.match_indices(" = ") {
+        let mut line = Line::new(&s[i + 3 ..]);
+        match line.value() {
+            Ok(value) if line.rest().trim().is_empty() => {
+                return Ok((parse_pointer(&s[.. i])?, value));
+            }
+            Ok(_) => (),
+            Err(e) => error = Some(e),
+        }
+    }
+    Err(error.unwrap_or_else(|| UngronError::Syntax("expected \" = \"".to_owned())))
+}
+
+/// Parse one line of the form `<path> = <value>`, or gron's `<root><path> = <value>;`.
+fn parse_line(s: &str) -> Result<(Vec<Segment>, Node), UngronError> {
+    if s.starts_with('/') {
+        return parse_pointer_line(s);
+    }
+    let mut line = Line::new(s);
+    let path = line.path()?;
+    line.expect(" = ")?;
+    let value = line.value()?;
+    line.eat(";");
+    if !line.rest().trim().is_empty() {
+        return Err(line.error("unexpected trailing characters"));
+    }
+    Ok((path, value))
+}
+
+fn finish_document(root: &mut Option<Node>, lines: &mut u64, output: &mut impl Write)
+    -> Result<(), UngronError>
+{
+    let lines = std::mem::take(lines);
+    if let Some(mut node) = root.take() {
+        node.settle();
+        node.check_gaps(lines)?;
+        node.write(0, output)?;
+        output.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Read lines of catj output and write out the JSON documents they describe. Blank lines separate
+/// top-level documents. Paths can be in catj's style, JSON Pointer style, or the JavaScript style
+/// that gron uses.
+pub fn ungron(mut input: impl BufRead, mut output: impl Write) -> Result<(), (u64, UngronError)> {
+    let mut root: Option<Node> = None;
+    let mut buf = String::new();
+    let mut line = 0;
+    let mut lines = 0; // in the current document
+    loop {
+        buf.clear();
+        line += 1;
+        if input.read_line(&mut buf).map_err(|e| (line, e.into()))? == 0 {
+            break;
+        }
+        let text = buf.trim_end_matches(['\n', '\r']);
+        if text.trim().is_empty() {
+            finish_document(&mut root, &mut lines, &mut output).map_err(|e| (line, e))?;
+            continue;
+        }
+        let (path, value) = parse_line(text).map_err(|e| (line, e))?;
+        lines += 1;
+        match (&mut root, path.first()) {
+            (Some(node), None) => node.replace(value),
+            (None, None) => root = Some(value),
+            (_, Some(first)) => {
+                let pointer = text.starts_with('/');
+                root.get_or_insert_with(|| Node::container_for(first, pointer))
+                    .assign(&path, pointer, value)
+                    .map_err(|e| (line, e))?;
+            }
+        }
+    }
+    finish_document(&mut root, &mut lines, &mut output).map_err(|e| (line, e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(input: &str) -> String {
+        let mut out = vec![];
+        ungron(input.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).expect("bad utf8")
+    }
+
+    fn error_line(input: &str) -> u64 {
+        ungron(input.as_bytes(), io::sink()).unwrap_err().0
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut flat = vec![];
+        crate::parse(r#"{"a": {"b c": [1, "two"], "\u0001": null}, "d": []}"#.as_bytes(), &mut flat)
+            .unwrap();
+        let flat = String::from_utf8(flat).unwrap();
+        assert_eq!(
+            "{\n  \"a\": {\n    \"b c\": [\n      1,\n      \"two\"\n    ],\n    \"\\u0001\": null\n  },\n  \"d\": []\n}\n",
+            run(&flat));
+    }
+
+    #[test]
+    fn test_pointer_round_trip() {
+        let flat = |json: &str, style| {
+            let mut out = vec![];
+            let options = crate::Options { style, ..crate::Options::default() };
+            crate::parse_with(json.as_bytes(), &mut out, options).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        // numbers which are object keys, in objects which also have other keys or would be
+        // sparse lists
+        let json = r#"{"0": 1, "a": [{"1": [2, {"3": null}]}, {"x": {}}], "": {"b": {"2": []}}}"#;
+        assert_eq!(run(&flat(json, crate::Style::Catj)), run(&flat(json, crate::Style::Pointer)));
+        assert_eq!("{\n  \"0\": 1,\n  \"a\": 2\n}\n", run("/0 = 1\n/a = 2\n"));
+        assert_eq!("{\n  \"x\": {\n    \"1\": 1\n  }\n}\n", run("/x/1 = 1\n"));
+
+        // An object whose keys are 0, 1 and so on can't be told apart from a list.
+        assert_eq!("[\n  \"a\"\n]\n", run(&flat(r#"{"0": "a"}"#, crate::Style::Pointer)));
+    }
+
+    #[test]
+    fn test_out_of_order_and_sparse() {
+        assert_eq!("[\n  null,\n  {\n    \"x\": true\n  },\n  null,\n  7\n]\n",
+                   run("[3] = 7\n[1].x = true\n"));
+    }
+
+    #[test]
+    fn test_multiple_documents() {
+        assert_eq!("{\n  \"a\": 1\n}\n[\n  \"b\"\n]\n", run(".a = 1\n\n\n[0] = \"b\"\n"));
+    }
+
+    #[test]
+    fn test_empty_containers_merge() {
+        assert_eq!("{\n  \"a\": {\n    \"b\": 1\n  }\n}\n", run(".a.b = 1\n.a = {}\n"));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(2, error_line(".a = 1\n.a b = 2\n"));
+        assert_eq!(3, error_line(".a = 1\n.b = \"x\"\n.a.c = 2\n"));
+        assert_eq!(1, error_line("[0] = 01x\n"));
+        assert_eq!(2, error_line(".a = 1\n[0] = 2\n"));
+
+        // the gap before a huge index isn't filled in with nulls
+        let e = ungron(&b"json[99999999999999] = 1;\n"[..], io::sink()).unwrap_err().1;
+        assert!(matches!(e, UngronError::Sparse(_)), "{e:?}");
+        assert_eq!(2, error_line("[1500] = 1\n\n[5] = 2\n"));
+    }
+
+    #[test]
+    fn test_pointer() {
+        // numbers are list indices unless the object already exists
+        assert_eq!("{\n  \"a\": [\n    {\n      \"x = y/~\": \"=\",\n      \"0\": 1\n    }\n  ]\n}\n",
+                   run("/a/0/x = y~1~0 = \"=\"\n/a/0/0 = 1\n"));
+        assert_eq!(1, error_line("/a/~2 = 1\n"));
+    }
+
+    #[test]
+    fn test_gron() {
+        let input = "json = {};\njson.a = [];\njson.a[0] = \"x\";\njson[\"b c\"] = {};\n\
+                     json[\"b c\"].été = \"\\u007F\";\n";
+        assert_eq!("{\n  \"a\": [\n    \"x\"\n  ],\n  \"b c\": {\n    \"été\": \"\x7F\"\n  }\n}\n",
+                   run(input));
+        assert_eq!("1\n", run("json = 1;\n"));
+        assert_eq!(1, error_line("json = 1;;\n"));
+    }
+}
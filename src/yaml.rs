@@ -0,0 +1,1210 @@
+//! Reading YAML 1.2: block mappings and sequences, flow collections, plain, quoted and block
+//! scalars, anchors and aliases, and streams of several documents.
+//!
+//! Each document is parsed into a tree, since aliases can refer back to any earlier part of it,
+//! and then written out like a top-level JSON value, as long as aliases don't repeat parts of it
+//! into more than about a million extra nodes. Plain scalars are resolved with the core schema, so
+//! `true`, `~` and `0x1F` become `true`, `null` and `31`, and anything else is a string. The core
+//! schema's tags, like `!!str` and `!!int`, are checked and applied, and other tags are ignored.
+//! Map keys which aren't scalars are written as JSON, and `<<` merge keys are applied, like most
+//! YAML tools do.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::rc::Rc;
+
+use crate::decode::{read_text, Builder, Text};
+use crate::{decimal, json5_number, Error, JsonError, Segment, Sink, Terminal};
+
+/// How deeply collections can be nested.
+const MAX_DEPTH: usize = 256;
+
+/// How many more nodes than there are characters in the input can be written out, counting each
+/// time an alias repeats one, so that a few nested aliases can't expand to billions of them.
+const MAX_ALIASED_NODES: usize = 1_000_000;
+
+fn too_many_nodes() -> JsonError {
+    JsonError::Syntax("YAML aliases expand to too many nodes".to_owned())
+}
+
+#[derive(Debug)]
+enum Node {
+    /// A plain scalar, whose type depends on what it looks like.
+    Plain(String),
+    Scalar(Terminal),
+    Seq(Vec<Rc<Node>>),
+    Map(Vec<(String, Rc<Node>)>),
+}
+
+impl Node {
+    fn empty() -> Rc<Node> {
+        Rc::new(Node::Plain(String::new()))
+    }
+
+    /// The text of the node as a map key: a scalar as it's written, and anything else as JSON.
+    /// Each node written uses up one of `budget`.
+    fn key(&self, budget: &mut usize) -> Result<String, JsonError> {
+        match self {
+            Node::Plain(s) | Node::Scalar(Terminal::String(s)) => Ok(s.clone()),
+            other => {
+                let mut s = String::new();
+                other.json(&mut s, budget)?;
+                Ok(s)
+            }
+        }
+    }
+
+    fn json(&self, out: &mut String, budget: &mut usize) -> Result<(), JsonError> {
+        *budget = budget.checked_sub(1).ok_or_else(too_many_nodes)?;
+        match self {
+            Node::Plain(s) => out.push_str(&resolve(s).to_string()),
+            Node::Scalar(t) => out.push_str(&t.to_string()),
+            Node::Seq(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    item.json(out, budget)?;
+                }
+                out.push(']');
+            }
+            Node::Map(pairs) => {
+                out.push('{');
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i != 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&Terminal::String(key.clone()).to_string());
+                    out.push(':');
+                    value.json(out, budget)?;
+                }
+                out.push('}');
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The value of a plain scalar, by the YAML 1.2 core schema.
+fn resolve(s: &str) -> Terminal {
+    match s {
+        "" | "~" | "null" | "Null" | "NULL" => Terminal::Null,
+        "true" | "True" | "TRUE" => Terminal::Bool(true),
+        "false" | "False" | "FALSE" => Terminal::Bool(false),
+        ".inf" | ".Inf" | ".INF" | "+.inf" | "+.Inf" | "+.INF" => {
+            Terminal::Number("Infinity".to_owned())
+        }
+        "-.inf" | "-.Inf" | "-.INF" => Terminal::Number("-Infinity".to_owned()),
+        ".nan" | ".NaN" | ".NAN" => Terminal::Number("NaN".to_owned()),
+        _ => number(s).map_or_else(|| Terminal::String(s.to_owned()), Terminal::Number),
+    }
+}
+
+/// A plain scalar as a JSON number, if it's an integer or float by the core schema.
+fn number(s: &str) -> Option<String> {
+    let digits = |s: &str, radix| !s.is_empty() && s.chars().all(|c| c.is_digit(radix));
+    if let Some(octal) = s.strip_prefix("0o").filter(|o| digits(o, 8)) {
+        return Some(decimal(octal.chars().map(|c| c.to_digit(8).unwrap()), 8));
+    }
+    if let Some(hex) = s.strip_prefix("0x").filter(|h| digits(h, 16)) {
+        return Some(decimal(hex.chars().map(|c| c.to_digit(16).unwrap()), 16));
+    }
+    let (sign, body) = match s.strip_prefix('-') {
+        Some(body) => ("-", body),
+        None => ("", s.strip_prefix('+').unwrap_or(s)),
+    };
+    let (mantissa, exponent) = match body.find(['e', 'E']) {
+        Some(i) => (&body[.. i], Some(&body[i + 1 ..])),
+        None => (body, None),
+    };
+    let (int, fraction) = match mantissa.split_once('.') {
+        Some((int, fraction)) => (int, Some(fraction)),
+        None => (mantissa, None),
+    };
+    let valid = (digits(int, 10) || (int.is_empty() && fraction.is_some_and(|f| digits(f, 10))))
+        && fraction.is_none_or(|f| f.is_empty() || digits(f, 10))
+        && exponent.is_none_or(|e| digits(e.strip_prefix(['-', '+']).unwrap_or(e), 10));
+    if !valid {
+        return None;
+    }
+    let trimmed = body.trim_start_matches('0');
+    let body = if trimmed.starts_with(|c: char| c.is_ascii_digit()) || int.is_empty() {
+        trimmed
+    } else {
+        // Keep one zero before a decimal point or exponent, or on its own.
+        &body[body.len() - trimmed.len() - 1 ..]
+    };
+    Some(format!("{sign}{}", json5_number(body)))
+}
+
+/// Apply `<<` merge keys: the keys of the map (or list of maps) given for `<<` are added where it
+/// is, unless the map already has them.
+fn merge(pairs: Vec<(String, Rc<Node>)>) -> Vec<(String, Rc<Node>)> {
+    if !pairs.iter().any(|(key, _)| key == "<<") {
+        return pairs;
+    }
+    let own: Vec<String> = pairs.iter()
+        .filter(|(key, _)| key != "<<")
+        .map(|(key, _)| key.clone())
+        .collect();
+    let mut out: Vec<(String, Rc<Node>)> = vec![];
+    for (key, value) in pairs {
+        if key != "<<" {
+            out.push((key, value));
+            continue;
+        }
+        let maps = match &*value {
+            Node::Map(_) => vec![value.clone()],
+            Node::Seq(items) => items.clone(),
+            _ => {
+                out.push((key, value));
+                continue;
+            }
+        };
+        for map in maps {
+            if let Node::Map(merged) = &*map {
+                for (key, value) in merged {
+                    if !own.contains(key) && !out.iter().any(|(k, _)| k == key) {
+                        out.push((key.clone(), value.clone()));
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+struct Parser {
+    text: Text,
+    anchors: HashMap<String, Rc<Node>>,
+    path: Vec<Segment>, // where in the document we are, for errors
+    depth: usize,
+    budget: usize, // how many more nodes can be written out, in keys and documents
+}
+
+/// Whether a character ends a token in block context.
+fn blank(c: Option<char>) -> bool {
+    matches!(c, None | Some(' ' | '\t' | '\n'))
+}
+
+fn flow_indicator(c: Option<char>) -> bool {
+    matches!(c, Some(',' | '[' | ']' | '{' | '}'))
+}
+
+impl Parser {
+    fn new(text: &str) -> Self {
+        Parser {
+            text: Text::new(text),
+            anchors: HashMap::new(),
+            path: vec![],
+            depth: 0,
+            budget: text.len() + MAX_ALIASED_NODES,
+        }
+    }
+
+    /// An error at the current position.
+    fn error(&self, error: JsonError) -> Error {
+        self.text.error(&self.path, error)
+    }
+
+    fn syntax(&self, msg: &str) -> Error {
+        self.error(JsonError::Syntax(msg.to_owned()))
+    }
+
+    /// Skip whitespace, comments and line breaks.
+    fn skip_blank(&mut self) {
+        loop {
+            self.text.skip_space();
+            match self.text.peek() {
+                Some('#') => self.text.skip_line(),
+                Some('\n') => {
+                    self.text.advance();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    /// Skip the rest of the line, which can only have a comment on it.
+    fn end_of_line(&mut self) -> Result<(), Error> {
+        self.text.skip_space();
+        if self.text.peek() == Some('#') {
+            self.text.skip_line();
+        }
+        match self.text.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.text.advance();
+                Ok(())
+            }
+            Some(_) => Err(self.syntax("expected the end of the line")),
+        }
+    }
+
+    /// Whether a block collection whose entries start at column `col` has ended.
+    fn ended(&self, col: usize) -> bool {
+        self.text.peek().is_none() || self.at_marker() || self.text.at.col < col
+    }
+
+    /// Whether the next character is `c`, followed by whitespace.
+    fn indicator(&self, c: char) -> bool {
+        self.text.peek() == Some(c) && blank(self.text.peek_at(1))
+    }
+
+    /// Whether the current line starts with a document marker, `---` or `...`.
+    fn at_marker(&self) -> bool {
+        let text = &self.text;
+        let marker = |c| (0 .. 3).all(|i| text.peek_at(i) == Some(c)) && blank(text.peek_at(3));
+        self.text.at.col == 0 && (marker('-') || marker('.'))
+    }
+
+    /// The text of a node as a map key.
+    fn key(&mut self, node: &Node) -> Result<String, Error> {
+        node.key(&mut self.budget).map_err(|e| self.error(e))
+    }
+
+    fn enter(&mut self) -> Result<(), Error> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.syntax("YAML nested too deeply"));
+        }
+        Ok(())
+    }
+
+    /// Parse the next document in the stream, or return `None` if there are no more.
+    fn document(&mut self) -> Result<Option<Rc<Node>>, Error> {
+        loop {
+            self.skip_blank();
+            if self.text.at.col == 0 && self.text.peek() == Some('%') {
+                // A directive, which doesn't change anything we do.
+                self.text.skip_line();
+            } else if self.at_marker() && self.text.peek() == Some('.') {
+                (0 .. 3).for_each(|_| { self.text.advance(); });
+            } else {
+                break;
+            }
+        }
+        if self.text.peek().is_none() {
+            return Ok(None);
+        }
+        if self.at_marker() {
+            (0 .. 3).for_each(|_| { self.text.advance(); });
+        }
+        self.anchors.clear();
+        let node = self.block_node(0, None)?;
+        self.skip_blank();
+        if self.text.peek().is_some() && !self.at_marker() {
+            return Err(self.syntax("expected the end of the document"));
+        }
+        Ok(Some(node))
+    }
+
+    /// Parse `&anchor` and `!tag` properties, returning them if they're given.
+    fn properties(&mut self) -> Result<(Option<String>, Option<String>), Error> {
+        let mut anchor = None;
+        let mut tag = None;
+        loop {
+            let c = self.text.peek();
+            if c != Some('&') && c != Some('!') {
+                return Ok((anchor, tag));
+            }
+            self.text.advance();
+            let mut name = String::new();
+            if c == Some('!') && self.text.peek() == Some('<') {
+                // A verbatim tag, like `!<tag:yaml.org,2002:str>`, which can have commas in it.
+                while let Some(c) = self.text.peek().filter(|&c| c != '\n') {
+                    name.push(c);
+                    self.text.advance();
+                    if c == '>' {
+                        break;
+                    }
+                }
+            }
+            while !blank(self.text.peek()) && !flow_indicator(self.text.peek()) {
+                name.push(self.text.advance().unwrap());
+            }
+            if c == Some('&') {
+                if name.is_empty() {
+                    return Err(self.syntax("expected an anchor name after '&'"));
+                }
+                anchor = Some(name);
+            } else {
+                tag = Some(name);
+            }
+            self.text.skip_space();
+        }
+    }
+
+    /// Give a node its properties. A scalar tagged with one of the core schema's types has to be
+    /// a value of that type, written any way the schema allows, like `!!int "0x1F"`.
+    fn apply(&mut self, node: Rc<Node>, anchor: Option<String>, tag: Option<String>)
+        -> Result<Rc<Node>, Error>
+    {
+        let tag = tag.as_deref().and_then(|tag| {
+            tag.strip_prefix('!')
+                .or_else(|| tag.strip_prefix("<tag:yaml.org,2002:")?.strip_suffix('>'))
+                .or_else(|| tag.strip_prefix("tag:yaml.org,2002:"))
+        });
+        let node = match (&*node, tag) {
+            (Node::Plain(s), Some("str")) => Rc::new(Node::Scalar(Terminal::String(s.clone()))),
+            (Node::Plain(s) | Node::Scalar(Terminal::String(s)), Some(tag))
+                if matches!(tag, "int" | "float" | "bool" | "null") =>
+            {
+                let value = resolve(s);
+                let ok = match (&value, tag) {
+                    (Terminal::Number(n), "int") => {
+                        !n.contains(['.', 'e', 'E']) && !n.ends_with("Infinity") && n != "NaN"
+                    }
+                    (Terminal::Number(_), "float") | (Terminal::Bool(_), "bool")
+                        | (Terminal::Null, "null") => true,
+                    _ => false,
+                };
+                if !ok {
+                    return Err(self.syntax(&format!("{} isn't a valid !!{tag}",
+                                                    Terminal::String(s.clone()))));
+                }
+                Rc::new(Node::Scalar(value))
+            }
+            (Node::Seq(_) | Node::Map(_), Some(tag))
+                if matches!(tag, "str" | "int" | "float" | "bool" | "null") =>
+            {
+                return Err(self.syntax(&format!("a collection can't be a !!{tag}")));
+            }
+            _ => node,
+        };
+        if let Some(anchor) = anchor {
+            self.anchors.insert(anchor, node.clone());
+        }
+        Ok(node)
+    }
+
+    /// Parse a node in block context. Any part of it on a following line has to be indented by at
+    /// least `indent` columns, except that a sequence can be at `seq_indent`, for one which is the
+    /// value in a mapping.
+    fn block_node(&mut self, indent: usize, seq_indent: Option<usize>) -> Result<Rc<Node>, Error> {
+        self.enter()?;
+        let result = self.block_node_inner(indent, seq_indent);
+        self.depth -= 1;
+        result
+    }
+
+    /// Whether the next thing in the input, on a later line, is too far out to be part of the
+    /// current node.
+    fn outdented(&self, line: u64, indent: usize, seq_indent: Option<usize>) -> bool {
+        if self.text.peek().is_none() || self.at_marker() {
+            return true;
+        }
+        let entry = self.indicator('-');
+        self.text.at.line != line && self.text.at.col < indent
+            && !(entry && Some(self.text.at.col) == seq_indent)
+    }
+
+    fn block_node_inner(&mut self, indent: usize, seq_indent: Option<usize>)
+        -> Result<Rc<Node>, Error>
+    {
+        let line = self.text.at.line;
+        self.skip_blank();
+        if self.outdented(line, indent, seq_indent) {
+            return Ok(Node::empty());
+        }
+        let (anchor, tag) = self.properties()?;
+        let props_line = self.text.at.line;
+        if anchor.is_some() || tag.is_some() {
+            // The content can be on the following lines.
+            self.skip_blank();
+            if self.outdented(props_line, indent, seq_indent) {
+                return self.apply(Node::empty(), anchor, tag);
+            }
+        }
+
+        let col = self.text.at.col;
+        let next = self.text.peek_at(1);
+        let node = match self.text.peek() {
+            Some('-') if blank(next) => {
+                // A sequence can't start on the same line as a mapping key, like `a: - b`.
+                if self.text.at.line == line
+                    && (seq_indent.is_some() || (indent > 0 && col < indent))
+                {
+                    return Err(self.syntax("a sequence can't start here"));
+                }
+                self.block_seq(col)?
+            }
+            Some('?') if blank(next) => self.block_map(col, None)?,
+            Some('|' | '>') => self.block_scalar(indent)?,
+            _ => {
+                let start = self.text.at.line;
+                let node = self.inline_node(indent, false)?;
+                self.text.skip_space();
+                // Keys have to be on one line.
+                if self.text.at.line == start && self.indicator(':') {
+                    // It was the first key of a mapping. Properties on the same line are the
+                    // key's, and otherwise they're the mapping's.
+                    if self.text.at.line == props_line {
+                        let node = self.apply(node, anchor, tag)?;
+                        let key = self.key(&node)?;
+                        return self.block_map(col, Some(key));
+                    }
+                    let key = self.key(&node)?;
+                    let map = self.block_map(col, Some(key))?;
+                    return self.apply(map, anchor, tag);
+                }
+                self.end_of_line()?;
+                node
+            }
+        };
+        self.apply(node, anchor, tag)
+    }
+
+    /// Parse a node which starts on the current line: a flow collection, an alias, or a quoted or
+    /// plain scalar. A plain scalar can go on over the following lines which are indented by at
+    /// least `indent` columns, or any lines in `flow` context.
+    fn inline_node(&mut self, indent: usize, flow: bool) -> Result<Rc<Node>, Error> {
+        match self.text.peek() {
+            Some('[') => self.flow_seq(),
+            Some('{') => self.flow_map(),
+            Some('"') => self.double_quoted().map(|s| Rc::new(Node::Scalar(Terminal::String(s)))),
+            Some('\'') => self.single_quoted().map(|s| Rc::new(Node::Scalar(Terminal::String(s)))),
+            Some('*') => {
+                self.text.advance();
+                let mut name = String::new();
+                while !blank(self.text.peek()) && !flow_indicator(self.text.peek()) {
+                    name.push(self.text.advance().unwrap());
+                }
+                match self.anchors.get(&name) {
+                    Some(node) => Ok(node.clone()),
+                    None => Err(self.syntax(&format!("unknown anchor {name:?}"))),
+                }
+            }
+            _ => {
+                let text = self.plain(indent, flow)?;
+                Ok(Rc::new(Node::Plain(text)))
+            }
+        }
+    }
+
+    /// Scan one line of a plain scalar, leaving the position after any trailing whitespace.
+    fn plain_line(&mut self, flow: bool) -> String {
+        let mut text = String::new();
+        let mut spaces = String::new();
+        loop {
+            match self.text.peek() {
+                None | Some('\n') => break,
+                Some(':') if blank(self.text.peek_at(1))
+                    || (flow && flow_indicator(self.text.peek_at(1))) => break,
+                c if flow && flow_indicator(c) => break,
+                Some(c @ (' ' | '\t')) => {
+                    self.text.advance();
+                    if self.text.peek() == Some('#') {
+                        break;
+                    }
+                    spaces.push(c);
+                }
+                Some(c) => {
+                    self.text.advance();
+                    text.push_str(&spaces);
+                    spaces.clear();
+                    text.push(c);
+                }
+            }
+        }
+        text
+    }
+
+    /// Parse a plain scalar, which can go on over several lines, as long as they're indented by
+    /// at least `indent` columns in block context.
+    fn plain(&mut self, indent: usize, flow: bool) -> Result<String, Error> {
+        let c = self.text.peek();
+        let indicator = matches!(c, Some('-' | '?' | ':'))
+            && (blank(self.text.peek_at(1)) || (flow && flow_indicator(self.text.peek_at(1))));
+        if indicator || matches!(c, None | Some(',' | '[' | ']' | '{' | '}' | '#' | '&' | '*' | '!'
+                                            | '|' | '>' | '\'' | '"' | '%' | '@' | '`'))
+        {
+            return Err(match c {
+                None => self.syntax("expected a value"),
+                Some(c) => self.syntax(&format!("unexpected '{c}'")),
+            });
+        }
+        let mut text = self.plain_line(flow);
+        loop {
+            let mark = self.text.at;
+            if self.text.peek() != Some('\n') {
+                break;
+            }
+            let mut breaks = 0;
+            while self.text.peek() == Some('\n') {
+                self.text.advance();
+                breaks += 1;
+                self.text.skip_space();
+            }
+            let next = self.text.peek();
+            let stop = next.is_none() || self.at_marker() || next == Some('#')
+                || (!flow && self.text.at.col < indent)
+                || (flow && (flow_indicator(next) || next == Some(':')));
+            let more = if stop { String::new() } else { self.plain_line(flow) };
+            if more.is_empty() {
+                self.text.at = mark;
+                break;
+            }
+            if breaks == 1 {
+                text.push(' ');
+            } else {
+                (1 .. breaks).for_each(|_| text.push('\n'));
+            }
+            text.push_str(&more);
+        }
+        Ok(text)
+    }
+
+    /// Fold the line breaks in a quoted scalar, starting at the first one: a single break becomes
+    /// a space, and any more are kept. Whitespace around them is dropped.
+    fn fold_quoted(&mut self, text: &mut String) -> Result<(), Error> {
+        let mut breaks = 0;
+        while self.text.peek() == Some('\n') {
+            self.text.advance();
+            breaks += 1;
+            if self.at_marker() {
+                return Err(self.syntax("document marker in a quoted string"));
+            }
+            self.text.skip_space();
+        }
+        if breaks == 1 {
+            text.push(' ');
+        } else {
+            (1 .. breaks).for_each(|_| text.push('\n'));
+        }
+        Ok(())
+    }
+
+    /// Add whitespace to a quoted scalar, unless it comes before a line break.
+    fn quoted_space(&mut self, text: &mut String) -> Result<(), Error> {
+        let mut spaces = String::new();
+        while let Some(c @ (' ' | '\t')) = self.text.peek() {
+            spaces.push(c);
+            self.text.advance();
+        }
+        if self.text.peek() == Some('\n') {
+            self.fold_quoted(text)
+        } else {
+            text.push_str(&spaces);
+            Ok(())
+        }
+    }
+
+    fn single_quoted(&mut self) -> Result<String, Error> {
+        self.text.advance();
+        let mut text = String::new();
+        loop {
+            match self.text.peek() {
+                None => return Err(self.syntax("unterminated quoted string")),
+                Some('\'') if self.text.peek_at(1) == Some('\'') => {
+                    self.text.advance();
+                    self.text.advance();
+                    text.push('\'');
+                }
+                Some('\'') => {
+                    self.text.advance();
+                    return Ok(text);
+                }
+                Some(' ' | '\t' | '\n') => self.quoted_space(&mut text)?,
+                Some(c) => {
+                    self.text.advance();
+                    text.push(c);
+                }
+            }
+        }
+    }
+
+    fn double_quoted(&mut self) -> Result<String, Error> {
+        self.text.advance();
+        let mut text = String::new();
+        loop {
+            match self.text.peek() {
+                None => return Err(self.syntax("unterminated quoted string")),
+                Some('"') => {
+                    self.text.advance();
+                    return Ok(text);
+                }
+                Some('\\') => {
+                    self.text.advance();
+                    self.escape(&mut text)?;
+                }
+                Some(' ' | '\t' | '\n') => self.quoted_space(&mut text)?,
+                Some(c) => {
+                    self.text.advance();
+                    text.push(c);
+                }
+            }
+        }
+    }
+
+    /// Parse an escape sequence in a double-quoted scalar, after the `\`.
+    fn escape(&mut self, text: &mut String) -> Result<(), Error> {
+        let Some(c) = self.text.advance() else {
+            return Err(self.syntax("unterminated quoted string"));
+        };
+        let digits = match c {
+            '0' => '\0',
+            'a' => '\x07',
+            'b' => '\x08',
+            't' | '\t' => '\t',
+            'n' => '\n',
+            'v' => '\x0B',
+            'f' => '\x0C',
+            'r' => '\r',
+            'e' => '\x1B',
+            ' ' | '"' | '/' | '\\' => c,
+            'N' => '\u{85}',
+            '_' => '\u{A0}',
+            'L' => '\u{2028}',
+            'P' => '\u{2029}',
+            '\n' => {
+                // An escaped line break is dropped, along with the indentation after it.
+                self.text.skip_space();
+                return Ok(());
+            }
+            'x' | 'u' | 'U' => {
+                let len = match c {
+                    'x' => 2,
+                    'u' => 4,
+                    _ => 8,
+                };
+                let mut hex = String::new();
+                for _ in 0 .. len {
+                    match self.text.peek() {
+                        Some(h) if h.is_ascii_hexdigit() => hex.push(self.text.advance().unwrap()),
+                        _ => {
+                            return Err(self.error(JsonError::InvalidEscape(
+                                format!("\\{c}{hex}: expected {len} hex digits"))));
+                        }
+                    }
+                }
+                let code = u32::from_str_radix(&hex, 16).unwrap();
+                char::from_u32(code).ok_or_else(|| self.error(JsonError::InvalidEscape(
+                    format!("\\{c}{hex} is not a character"))))?
+            }
+            _ => return Err(self.error(JsonError::InvalidEscape(format!("\\{c}")))),
+        };
+        text.push(digits);
+        Ok(())
+    }
+
+    /// Parse a literal (`|`) or folded (`>`) block scalar, whose lines are indented by at least
+    /// `indent` columns.
+    fn block_scalar(&mut self, indent: usize) -> Result<Rc<Node>, Error> {
+        let literal = self.text.advance() == Some('|');
+        let mut chomp = None;
+        let mut explicit = None;
+        loop {
+            match self.text.peek() {
+                Some(c @ ('+' | '-')) if chomp.is_none() => chomp = Some(c),
+                Some(c @ '1' ..= '9') if explicit.is_none() => {
+                    explicit = Some(c.to_digit(10).unwrap() as usize);
+                }
+                _ => break,
+            }
+            self.text.advance();
+        }
+        self.end_of_line()?;
+
+        // Find the indentation from the first line with something on it, if it wasn't given.
+        let width = match explicit {
+            Some(n) => indent.saturating_sub(1) + n,
+            None => {
+                let mut pos = self.text.at.pos;
+                let mut width = 0;
+                loop {
+                    let spaces = self.text.chars[pos ..].iter().take_while(|&&c| c == ' ').count();
+                    match self.text.chars.get(pos + spaces) {
+                        Some('\n') => {
+                            width = width.max(spaces);
+                            pos += spaces + 1;
+                        }
+                        Some(_) => break spaces.max(indent).max(1),
+                        None => break width.max(indent).max(1),
+                    }
+                }
+            }
+        };
+
+        let mut lines: Vec<String> = vec![];
+        while self.text.peek().is_some() {
+            let mark = self.text.at;
+            if self.at_marker() {
+                break;
+            }
+            let mut spaces = 0;
+            while spaces < width && self.text.peek() == Some(' ') {
+                self.text.advance();
+                spaces += 1;
+            }
+            if matches!(self.text.peek(), None | Some('\n')) {
+                lines.push(String::new());
+                self.text.advance();
+                continue;
+            }
+            if spaces < width {
+                self.text.at = mark;
+                break;
+            }
+            let start = self.text.at.pos;
+            self.text.skip_line();
+            lines.push(self.text.chars[start .. self.text.at.pos].iter().collect());
+            self.text.advance();
+        }
+
+        let content = lines.iter().rposition(|l| !l.is_empty()).map_or(0, |i| i + 1);
+        let trailing = lines.len() - content;
+        let mut text = String::new();
+        if literal {
+            text = lines[.. content].join("\n");
+        } else {
+            let mut breaks = 0;
+            let mut first = true;
+            let mut last_indented = false;
+            for line in &lines[.. content] {
+                if line.is_empty() {
+                    breaks += 1;
+                    continue;
+                }
+                let indented = line.starts_with([' ', '\t']);
+                if first {
+                    (0 .. breaks).for_each(|_| text.push('\n'));
+                } else if breaks == 0 && !indented && !last_indented {
+                    text.push(' ');
+                } else {
+                    let kept = usize::from(indented || last_indented);
+                    (0 .. breaks + kept).for_each(|_| text.push('\n'));
+                }
+                text.push_str(line);
+                breaks = 0;
+                first = false;
+                last_indented = indented;
+            }
+        }
+        match chomp {
+            Some('-') => (),
+            Some(_) => {
+                (0 .. usize::from(content > 0) + trailing).for_each(|_| text.push('\n'));
+            }
+            None if content > 0 => text.push('\n'),
+            None => (),
+        }
+        Ok(Rc::new(Node::Scalar(Terminal::String(text))))
+    }
+
+    /// Parse a block sequence whose entries start at column `col`.
+    fn block_seq(&mut self, col: usize) -> Result<Rc<Node>, Error> {
+        let mut items = vec![];
+        loop {
+            self.text.advance(); // the '-'
+            self.path.push(Segment::Index(items.len() as u64));
+            let item = self.block_node(col + 1, None)?;
+            self.path.pop();
+            items.push(item);
+
+            self.skip_blank();
+            if self.ended(col) {
+                break;
+            }
+            if self.text.at.col > col {
+                return Err(self.syntax("bad indentation of a sequence entry"));
+            }
+            if !(self.indicator('-')) {
+                // The next key of the mapping this sequence is a value in.
+                break;
+            }
+        }
+        Ok(Rc::new(Node::Seq(items)))
+    }
+
+    /// Parse a block mapping whose keys start at column `col`. If the first key has already been
+    /// parsed, it's given, and the position is at its `:`.
+    fn block_map(&mut self, col: usize, first: Option<String>) -> Result<Rc<Node>, Error> {
+        let mut pairs = vec![];
+        let mut next_key = first;
+        loop {
+            let key = match next_key.take() {
+                Some(key) => key,
+                None if self.indicator('?') => {
+                    self.text.advance();
+                    let node = self.block_node(col + 1, None)?;
+                    let key = self.key(&node)?;
+                    self.skip_blank();
+                    if !(self.text.at.col == col && self.indicator(':')) {
+                        // A key with no value.
+                        pairs.push((key, Node::empty()));
+                        if self.ended(col) {
+                            break;
+                        }
+                        continue;
+                    }
+                    key
+                }
+                None => {
+                    let (anchor, tag) = self.properties()?;
+                    let node = self.inline_node(col + 1, false)?;
+                    let node = self.apply(node, anchor, tag)?;
+                    let key = self.key(&node)?;
+                    self.text.skip_space();
+                    if !(self.indicator(':')) {
+                        return Err(self.syntax("expected ':' after mapping key"));
+                    }
+                    key
+                }
+            };
+            self.text.advance(); // the ':'
+            self.path.push(Segment::Key(key.clone()));
+            let value = self.block_node(col + 1, Some(col))?;
+            self.path.pop();
+            pairs.push((key, value));
+
+            self.skip_blank();
+            if self.ended(col) {
+                break;
+            }
+            if self.text.at.col > col {
+                return Err(self.syntax("bad indentation of a mapping entry"));
+            }
+        }
+        Ok(Rc::new(Node::Map(merge(pairs))))
+    }
+
+    /// Parse a node in flow context, where indentation doesn't matter.
+    fn flow_node(&mut self) -> Result<Rc<Node>, Error> {
+        self.enter()?;
+        self.skip_blank();
+        let result = self.properties().and_then(|(anchor, tag)| {
+            self.skip_blank();
+            let node = match self.text.peek() {
+                Some(',' | ']' | '}' | ':') if anchor.is_some() || tag.is_some() => Node::empty(),
+                _ => self.inline_node(0, true)?,
+            };
+            self.apply(node, anchor, tag)
+        });
+        self.depth -= 1;
+        result
+    }
+
+    /// The value after a key in flow context, if there's a `:`.
+    fn flow_value(&mut self, end: char) -> Result<Rc<Node>, Error> {
+        self.skip_blank();
+        if self.text.peek() != Some(':') {
+            return Ok(Node::empty());
+        }
+        self.text.advance();
+        self.skip_blank();
+        if self.text.peek() == Some(',') || self.text.peek() == Some(end) {
+            return Ok(Node::empty());
+        }
+        self.flow_node()
+    }
+
+    /// After an entry in a flow collection, expect a `,` or the end.
+    fn flow_next(&mut self, end: char) -> Result<(), Error> {
+        self.skip_blank();
+        match self.text.peek() {
+            Some(',') => {
+                self.text.advance();
+                Ok(())
+            }
+            Some(c) if c == end => Ok(()),
+            None => {
+                Err(self.syntax(&format!("expected ',' or '{end}', found the end of the input")))
+            }
+            Some(c) => Err(self.syntax(&format!("expected ',' or '{end}', found '{c}'"))),
+        }
+    }
+
+    fn flow_seq(&mut self) -> Result<Rc<Node>, Error> {
+        self.text.advance();
+        let mut items = vec![];
+        loop {
+            self.skip_blank();
+            if self.text.peek() == Some(']') {
+                self.text.advance();
+                return Ok(Rc::new(Node::Seq(items)));
+            }
+            self.path.push(Segment::Index(items.len() as u64));
+            let explicit = self.indicator('?');
+            if explicit {
+                self.text.advance();
+            }
+            let node = self.flow_node()?;
+            self.skip_blank();
+            let item = if explicit || self.text.peek() == Some(':') {
+                // A mapping with a single pair.
+                let key = self.key(&node)?;
+                let value = self.flow_value(']')?;
+                Rc::new(Node::Map(vec![(key, value)]))
+            } else {
+                node
+            };
+            self.path.pop();
+            items.push(item);
+            self.flow_next(']')?;
+        }
+    }
+
+    fn flow_map(&mut self) -> Result<Rc<Node>, Error> {
+        self.text.advance();
+        let mut pairs = vec![];
+        loop {
+            self.skip_blank();
+            if self.text.peek() == Some('}') {
+                self.text.advance();
+                return Ok(Rc::new(Node::Map(merge(pairs))));
+            }
+            if self.indicator('?') {
+                self.text.advance();
+                self.skip_blank();
+            }
+            let node = match self.text.peek() {
+                Some(':') => Node::empty(),
+                _ => self.flow_node()?,
+            };
+            let key = self.key(&node)?;
+            self.path.push(Segment::Key(key.clone()));
+            let value = self.flow_value('}')?;
+            self.path.pop();
+            pairs.push((key, value));
+            self.flow_next('}')?;
+        }
+    }
+}
+
+/// Give a parsed document to the builder, like a top-level JSON value. Each node written uses up
+/// one of `budget`.
+fn emit<S: Sink>(node: &Node, builder: &mut Builder<S>, depth: usize, budget: &mut usize)
+    -> Result<(), JsonError>
+{
+    if depth > MAX_DEPTH {
+        return Err(JsonError::Syntax("YAML nested too deeply, through aliases".to_owned()));
+    }
+    *budget = budget.checked_sub(1).ok_or_else(too_many_nodes)?;
+    match node {
+        Node::Plain(s) => builder.scalar(resolve(s))?,
+        Node::Scalar(t) => builder.scalar(t.clone())?,
+        Node::Seq(items) => {
+            builder.start_list()?;
+            for item in items {
+                if builder.done() {
+                    return Ok(());
+                }
+                emit(item, builder, depth + 1, budget)?;
+            }
+            builder.end()?;
+        }
+        Node::Map(pairs) => {
+            builder.start_object()?;
+            for (key, value) in pairs {
+                if builder.done() {
+                    return Ok(());
+                }
+                builder.key(key.clone())?;
+                emit(value, builder, depth + 1, budget)?;
+            }
+            builder.end()?;
+        }
+    }
+    Ok(())
+}
+
+/// Read a stream of YAML documents and give them to the sink, one after another like top-level
+/// JSON values.
+pub(crate) fn decode<S: Sink>(input: impl BufRead, sink: S) -> Result<(), Error> {
+    let text = read_text(input)?;
+    let mut parser = Parser::new(&text);
+    let mut builder = Builder::new(sink);
+    while !builder.done() {
+        let document = parser.document().map_err(|e| e.with_snippet(text.as_bytes(), 0))?;
+        let Some(document) = document else {
+            break;
+        };
+        emit(&document, &mut builder, 0, &mut parser.budget).map_err(|e| parser.error(e))?;
+    }
+    builder.sink.end().map_err(|e| parser.error(e.into()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{parse_with, Format, Options};
+
+    fn run(input: &str) -> Result<String, crate::Error> {
+        let mut out = vec![];
+        let options = Options { format: Format::Yaml, ..Options::default() };
+        parse_with(input.as_bytes(), &mut out, options)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_block() {
+        let input = r#"
+# a config
+name: catj
+version: 0.3.3   # comment
+empty:
+numbers: [0x1F, 0o17, +12, 007, 1.50, .5, 1e3, -.inf, 1_000]
+"quoted key": 'it''s'
+metadata:
+  labels: {app: web, "tier": front}
+  list:
+  - a
+  - b: 1
+    c: ~
+  -   - nested
+      - true
+  multi: this is
+    one line
+
+    and another
+"#;
+        assert_eq!(run(input).unwrap(), r#".name = "catj"
+.version = "0.3.3"
+.empty = null
+.numbers[0] = 31
+.numbers[1] = 15
+.numbers[2] = 12
+.numbers[3] = 7
+.numbers[4] = 1.50
+.numbers[5] = 0.5
+.numbers[6] = 1e3
+.numbers[7] = -Infinity
+.numbers[8] = "1_000"
+."quoted key" = "it's"
+.metadata.labels.app = "web"
+.metadata.labels.tier = "front"
+.metadata.list[0] = "a"
+.metadata.list[1].b = 1
+.metadata.list[1].c = null
+.metadata.list[2][0] = "nested"
+.metadata.list[2][1] = true
+.metadata.multi = "this is one line\nand another"
+
+"#);
+    }
+
+    #[test]
+    fn test_scalars() {
+        let input = "\
+literal: |
+  line 1
+    indented
+
+  line 3
+folded: >-
+  some
+  folded
+  text
+
+  new paragraph
+keep: |+
+  kept
+
+strip: |-
+  stripped
+double: \"tab\\there \\u00e9\\x21 \\
+  joined\n  folded\"
+";
+        assert_eq!(run(input).unwrap(), r#".literal = "line 1\n  indented\n\nline 3\n"
+.folded = "some folded text\nnew paragraph"
+.keep = "kept\n\n"
+.strip = "stripped"
+.double = "tab\there é! joined folded"
+
+"#);
+    }
+
+    #[test]
+    fn test_tags() {
+        let input = "\
+a: !!int \"0x1F\"
+b: !!float 1
+c: !!bool 'true'
+d: !!null
+e: !!str 1
+f: !<tag:yaml.org,2002:int> '2'
+g: !custom 3
+";
+        assert_eq!(run(input).unwrap(), r#".a = 31
+.b = 1
+.c = true
+.d = null
+.e = "1"
+.f = 2
+.g = 3
+
+"#);
+        let error = |input: &str| run(input).unwrap_err().error.to_string();
+        assert_eq!(error("a: !!int 1.5\n"), "\"1.5\" isn't a valid !!int");
+        assert_eq!(error("a: !!bool yes\n"), "\"yes\" isn't a valid !!bool");
+        assert_eq!(error("a: !!null [1]\n"), "a collection can't be a !!null");
+    }
+
+    #[test]
+    fn test_documents_and_aliases() {
+        let input = "\
+%YAML 1.2
+---
+defaults: &defaults
+  adapter: postgres
+  host: localhost
+dev:
+  <<: *defaults
+  host: dev.example.com
+list: &l [1, 2]
+again: *l
+--- plain text
+...
+---
+- &k key: value
+- *k
+";
+        assert_eq!(run(input).unwrap(), r#".defaults.adapter = "postgres"
+.defaults.host = "localhost"
+.dev.adapter = "postgres"
+.dev.host = "dev.example.com"
+.list[0] = 1
+.list[1] = 2
+.again[0] = 1
+.again[1] = 2
+
+
+[0].key = "value"
+[1] = "key"
+
+"#);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |input: &str| {
+            let e = run(input).unwrap_err();
+            (e.line, e.col, e.path.to_string(), e.error.to_string())
+        };
+        assert_eq!(error("a:\n  b: 1\n c: 2\n"),
+                   (3, 2, "".to_owned(), "bad indentation of a mapping entry".to_owned()));
+        assert_eq!(error("a: [1, 2}\n"),
+                   (1, 9, ".a".to_owned(), "expected ',' or ']', found '}'".to_owned()));
+        assert_eq!(error("a: 1\n  b: 2\n"),
+                   (2, 4, ".a".to_owned(), "expected the end of the line".to_owned()));
+        assert_eq!(error("a: *nope\n"),
+                   (1, 9, ".a".to_owned(), "unknown anchor \"nope\"".to_owned()));
+        assert_eq!(error("a: \"open\n"),
+                   (2, 1, ".a".to_owned(), "unterminated quoted string".to_owned()));
+        assert_eq!(error("a: - b\n"),
+                   (1, 4, ".a".to_owned(), "a sequence can't start here".to_owned()));
+
+        // aliases which would expand to millions of nodes, in a key and in a value
+        let mut input = "a0: &a0 [x]\n".to_owned();
+        for i in 1 .. 7 {
+            let items = vec![format!("*a{}", i - 1); 10].join(", ");
+            input.push_str(&format!("a{i}: &a{i} [{items}]\n"));
+        }
+        let too_many = "YAML aliases expand to too many nodes".to_owned();
+        assert_eq!(error(&format!("{input}? *a6\n: 1\n")).3, too_many);
+        let options = Options { format: Format::Yaml, ..Options::default() };
+        let e = parse_with(input.as_bytes(), std::io::sink(), options).unwrap_err();
+        assert_eq!(e.error.to_string(), too_many);
+    }
+}
@@ -0,0 +1,316 @@
+//! Reading the files inside a zip archive, without extracting them first.
+//!
+//! The archive's central directory at the end of the file lists the members, and each one is
+//! read straight from where it's stored, decompressing it if need be. Members can be stored as-is
+//! or compressed with DEFLATE, which covers what nearly every zip tool writes. Zip64 archives
+//! (over 4 GiB, or with a lot of members) are supported too.
+
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Take};
+
+use crate::inflate::{crc32, InflateError, Inflater};
+
+const LOCAL_HEADER: &[u8] = b"PK\x03\x04";
+const CENTRAL_HEADER: &[u8] = b"PK\x01\x02";
+const END: &[u8] = b"PK\x05\x06";
+const ZIP64_LOCATOR: &[u8] = b"PK\x06\x07";
+const ZIP64_END: &[u8] = b"PK\x06\x06";
+
+/// The most the end of central directory record can be from the end of the file: its own size,
+/// plus the longest comment.
+const MAX_END_SIZE: u64 = 22 + 0xFFFF;
+
+fn invalid(msg: &'static str) -> io::Error {
+    InflateError::Invalid(msg).into()
+}
+
+fn u16_at(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([data[pos], data[pos + 1]])
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos .. pos + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos .. pos + 8].try_into().unwrap())
+}
+
+/// Read exactly `len` bytes at `pos`.
+fn read_at(input: &mut (impl Read + Seek), pos: u64, len: usize) -> io::Result<Vec<u8>> {
+    input.seek(SeekFrom::Start(pos))?;
+    let mut buf = vec![0; len];
+    input.read_exact(&mut buf).map_err(|e| match e.kind() {
+        io::ErrorKind::UnexpectedEof => invalid("zip archive truncated"),
+        _ => e,
+    })?;
+    Ok(buf)
+}
+
+/// A file in an archive.
+#[derive(Debug, Clone)]
+pub struct Member {
+    /// The path of the file within the archive.
+    pub name: String,
+    /// How big the file is when it's decompressed.
+    pub size: u64,
+    flags: u16,
+    method: u16,
+    crc: u32,
+    compressed_size: u64,
+    offset: u64,
+}
+
+/// A zip archive, with the list of the files in it.
+pub struct Archive<R> {
+    input: R,
+    members: Vec<Member>,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    /// Read the list of files in the archive. Returns `None` if the input doesn't start like a zip
+    /// archive.
+    pub fn new(mut input: R) -> io::Result<Option<Self>> {
+        let mut magic = [0; 4];
+        input.seek(SeekFrom::Start(0))?;
+        let is_zip = match input.read_exact(&mut magic) {
+            Ok(()) => magic == LOCAL_HEADER || magic == END,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(e) => return Err(e),
+        };
+        if !is_zip {
+            input.seek(SeekFrom::Start(0))?;
+            return Ok(None);
+        }
+
+        // Find the end of central directory record, searching back past the comment.
+        let len = input.seek(SeekFrom::End(0))?;
+        let tail_start = len.saturating_sub(MAX_END_SIZE);
+        let tail = read_at(&mut input, tail_start, (len - tail_start) as usize)?;
+        let end = (0 ..= tail.len().saturating_sub(22)).rev()
+            .find(|&i| tail[i ..].starts_with(END))
+            .ok_or_else(|| invalid("zip archive has no central directory"))?;
+        let end_pos = tail_start + end as u64;
+        let record = &tail[end ..];
+        let mut count = u64::from(u16_at(record, 10));
+        let mut dir_size = u64::from(u32_at(record, 12));
+        let mut dir_offset = u64::from(u32_at(record, 16));
+
+        // Zip64 archives have the real numbers in another record, found from one just before.
+        if count == 0xFFFF || dir_size == 0xFFFF_FFFF || dir_offset == 0xFFFF_FFFF {
+            let locator_pos = end_pos.saturating_sub(20);
+            let locator = read_at(&mut input, locator_pos, 20)?;
+            if locator.starts_with(ZIP64_LOCATOR) {
+                let record_pos = u64_at(&locator, 8);
+                if record_pos.checked_add(56).is_none_or(|end| end > locator_pos) {
+                    return Err(invalid("invalid zip64 end of central directory locator"));
+                }
+                let record = read_at(&mut input, record_pos, 56)?;
+                if !record.starts_with(ZIP64_END) {
+                    return Err(invalid("invalid zip64 end of central directory record"));
+                }
+                count = u64_at(&record, 32);
+                dir_size = u64_at(&record, 40);
+                dir_offset = u64_at(&record, 48);
+            }
+        }
+
+        // Check where the directory is before making room for it, so a bad size can't use up all
+        // the memory.
+        if dir_offset.checked_add(dir_size).is_none_or(|end| end > end_pos) {
+            return Err(invalid("zip central directory is outside the archive"));
+        }
+        let dir_len = usize::try_from(dir_size)
+            .map_err(|_| invalid("zip central directory too big"))?;
+        let dir = read_at(&mut input, dir_offset, dir_len)?;
+        let mut members = vec![];
+        let mut pos = 0;
+        for _ in 0 .. count {
+            if dir.len() < pos + 46 || !dir[pos ..].starts_with(CENTRAL_HEADER) {
+                return Err(invalid("invalid zip central directory"));
+            }
+            let entry = &dir[pos ..];
+            let name_len = usize::from(u16_at(entry, 28));
+            let extra_len = usize::from(u16_at(entry, 30));
+            let comment_len = usize::from(u16_at(entry, 32));
+            if entry.len() < 46 + name_len + extra_len {
+                return Err(invalid("invalid zip central directory"));
+            }
+            let mut member = Member {
+                name: String::from_utf8_lossy(&entry[46 .. 46 + name_len]).into_owned(),
+                size: u64::from(u32_at(entry, 24)),
+                flags: u16_at(entry, 8),
+                method: u16_at(entry, 10),
+                crc: u32_at(entry, 16),
+                compressed_size: u64::from(u32_at(entry, 20)),
+                offset: u64::from(u32_at(entry, 42)),
+            };
+
+            // The zip64 extra field has the sizes and offset which didn't fit, in this order.
+            let mut extra = &entry[46 + name_len .. 46 + name_len + extra_len];
+            while extra.len() >= 4 {
+                let id = u16_at(extra, 0);
+                let len = usize::from(u16_at(extra, 2)).min(extra.len() - 4);
+                if id == 1 {
+                    let mut fields = extra[4 .. 4 + len].chunks_exact(8).map(|f| u64_at(f, 0));
+                    for value in [&mut member.size, &mut member.compressed_size,
+                                  &mut member.offset]
+                    {
+                        if *value == 0xFFFF_FFFF {
+                            *value = fields.next()
+                                .ok_or_else(|| invalid("invalid zip64 extra field"))?;
+                        }
+                    }
+                }
+                extra = &extra[4 + len ..];
+            }
+
+            if !member.name.ends_with('/') {
+                members.push(member);
+            }
+            pos += 46 + name_len + extra_len + comment_len;
+        }
+        Ok(Some(Archive { input, members }))
+    }
+
+    /// The files in the archive, in the order they're listed. Directories are left out.
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    /// Read the contents of one of the files, which are checked against their CRC at the end.
+    pub fn open(&mut self, index: usize) -> io::Result<MemberReader<'_, R>> {
+        let member = &self.members[index];
+        if member.flags & 1 != 0 {
+            return Err(invalid("encrypted zip members aren't supported"));
+        }
+        let header = read_at(&mut self.input, member.offset, 30)?;
+        if !header.starts_with(LOCAL_HEADER) {
+            return Err(invalid("invalid zip local file header"));
+        }
+        let data = member.offset + 30 + u64::from(u16_at(&header, 26))
+            + u64::from(u16_at(&header, 28));
+        self.input.seek(SeekFrom::Start(data))?;
+        let raw = BufReader::new(&mut self.input).take(member.compressed_size);
+        let body = match member.method {
+            0 => Body::Stored(raw),
+            8 => Body::Deflated(Inflater::new(raw)),
+            _ => return Err(invalid("unsupported zip compression method")),
+        };
+        Ok(MemberReader {
+            body,
+            crc: 0,
+            size: 0,
+            expected_crc: member.crc,
+            expected_size: member.size,
+        })
+    }
+}
+
+enum Body<'a, R> {
+    Stored(Take<BufReader<&'a mut R>>),
+    Deflated(Inflater<Take<BufReader<&'a mut R>>>),
+}
+
+/// The contents of a file in an archive.
+pub struct MemberReader<'a, R> {
+    body: Body<'a, R>,
+    crc: u32,
+    size: u64,
+    expected_crc: u32,
+    expected_size: u64,
+}
+
+impl<R: Read> Read for MemberReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match &mut self.body {
+            Body::Stored(input) => input.read(buf)?,
+            Body::Deflated(inflater) => inflater.read(buf)?,
+        };
+        if n == 0 && !buf.is_empty() {
+            if self.size < self.expected_size {
+                return Err(InflateError::Truncated.into());
+            }
+            if self.crc != self.expected_crc || self.size != self.expected_size {
+                return Err(InflateError::Checksum.into());
+            }
+        }
+        self.crc = crc32(self.crc, &buf[.. n]);
+        self.size += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    // A stored a.json, a directory, and a deflated dir/b.json, from Python's zipfile.
+    const ZIP: &[u8] = b"\
+        \x50\x4b\x03\x04\x14\x00\x00\x00\x00\x00\x00\x00\x21\x50\x97\x8e\xa1\xfb\x08\x00\x00\x00\
+        \x08\x00\x00\x00\x06\x00\x00\x00\x61\x2e\x6a\x73\x6f\x6e\x7b\x22\x61\x22\x3a\x20\x31\x7d\
+        \x50\x4b\x03\x04\x14\x00\x00\x00\x00\x00\x00\x00\x21\x50\x00\x00\x00\x00\x00\x00\x00\x00\
+        \x00\x00\x00\x00\x04\x00\x00\x00\x64\x69\x72\x2f\x50\x4b\x03\x04\x14\x00\x00\x00\x08\x00\
+        \x00\x00\x21\x50\x1d\x9a\x0e\x6d\x09\x00\x00\x00\x12\x00\x00\x00\x0a\x00\x00\x00\x64\x69\
+        \x72\x2f\x62\x2e\x6a\x73\x6f\x6e\x8b\x36\xd4\x51\x30\x42\x46\xb1\x00\x50\x4b\x01\x02\x14\
+        \x03\x14\x00\x00\x00\x00\x00\x00\x00\x21\x50\x97\x8e\xa1\xfb\x08\x00\x00\x00\x08\x00\x00\
+        \x00\x06\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80\x01\x00\x00\x00\x00\x61\x2e\x6a\
+        \x73\x6f\x6e\x50\x4b\x01\x02\x14\x03\x14\x00\x00\x00\x00\x00\x00\x00\x21\x50\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x00\x00\x00\x00\x04\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x80\
+        \x01\x2c\x00\x00\x00\x64\x69\x72\x2f\x50\x4b\x01\x02\x14\x03\x14\x00\x00\x00\x08\x00\x00\
+        \x00\x21\x50\x1d\x9a\x0e\x6d\x09\x00\x00\x00\x12\x00\x00\x00\x0a\x00\x00\x00\x00\x00\x00\
+        \x00\x00\x00\x00\x00\x80\x01\x4e\x00\x00\x00\x64\x69\x72\x2f\x62\x2e\x6a\x73\x6f\x6e\x50\
+        \x4b\x05\x06\x00\x00\x00\x00\x03\x00\x03\x00\x9e\x00\x00\x00\x7f\x00\x00\x00\x00\x00";
+
+    fn read_member(archive: &mut Archive<Cursor<Vec<u8>>>, index: usize) -> io::Result<String> {
+        let mut out = String::new();
+        archive.open(index)?.read_to_string(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_members() {
+        let mut archive = Archive::new(Cursor::new(ZIP.to_vec())).unwrap().unwrap();
+        let names: Vec<_> = archive.members().iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(names, ["a.json", "dir/b.json"]);
+        assert_eq!(read_member(&mut archive, 0).unwrap(), r#"{"a": 1}"#);
+        assert_eq!(read_member(&mut archive, 1).unwrap(), "[1, 2, 2, 2, 2, 2]");
+
+        assert!(Archive::new(Cursor::new(b"[1, 2]".to_vec())).unwrap().is_none());
+        assert!(Archive::new(Cursor::new(vec![])).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut bad = ZIP.to_vec();
+        bad[0x2A] = b'2'; // in the contents of a.json
+        let mut archive = Archive::new(Cursor::new(bad)).unwrap().unwrap();
+        let e = read_member(&mut archive, 0).unwrap_err();
+        assert!(matches!(e.downcast().unwrap(), InflateError::Checksum));
+        assert!(read_member(&mut archive, 1).is_ok());
+
+        assert!(Archive::new(Cursor::new(ZIP[.. 200].to_vec())).is_err());
+
+        // a zip64 archive claiming a directory of 2^62 bytes
+        let mut crafted = LOCAL_HEADER.to_vec();
+        crafted.extend(ZIP64_END);
+        crafted.extend([0; 36]);
+        crafted.extend((1u64 << 62).to_le_bytes());
+        crafted.extend(0u64.to_le_bytes());
+        crafted.extend(ZIP64_LOCATOR);
+        crafted.extend([0; 4]);
+        crafted.extend(4u64.to_le_bytes());
+        crafted.extend([1, 0, 0, 0]);
+        crafted.extend(END);
+        crafted.extend([0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF]);
+        crafted.extend([0xFF; 8]);
+        crafted.extend([0, 0]);
+        let e = Archive::new(Cursor::new(crafted.clone())).err().unwrap();
+        assert_eq!(e.to_string(), "zip central directory is outside the archive");
+
+        // and one whose zip64 record is said to be past the end
+        crafted[68 .. 76].copy_from_slice(&(1u64 << 62).to_le_bytes());
+        let e = Archive::new(Cursor::new(crafted)).err().unwrap();
+        assert_eq!(e.to_string(), "invalid zip64 end of central directory locator");
+    }
+}